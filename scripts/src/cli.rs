@@ -1,4 +1,5 @@
 use crate::commands::Commands;
+use crate::output::OutputFormat;
 use clap::Parser;
 
 #[derive(Parser, Debug)]
@@ -12,6 +13,10 @@ It guides you through each installation step — networking, disk setup, filesys
 Designed for both interactive users and automated workflows, sharch keeps installation logic predictable, reproducible, and easy to extend."
 )]
 pub struct Cli {
+    /// `json` prints disks, free regions, the plan, created partitions and LVM/RAID setup as JSON lines on stdout
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -0,0 +1,102 @@
+use anyhow::{Context, bail};
+use dialoguer::Confirm;
+use std::path::PathBuf;
+
+use crate::colors;
+use crate::commands::core::disk_setup::backup;
+use crate::commands::core::disk_setup::preflight;
+use crate::helpers::ensure_tool_exists;
+use crate::helpers::runner::{CommandRunner, DryRunRunner, RealRunner};
+
+#[derive(clap::Args, Debug)]
+/// Maintenance commands for disks partitioned by disk-setup
+pub struct DiskArgs {
+    #[command(subcommand)]
+    pub command: DiskCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum DiskCommand {
+    /// Put back a partition table saved before disk-setup changed it
+    Restore(RestoreArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct RestoreArgs {
+    /// Backup file, or just its name inside the backup directory
+    pub backup: PathBuf,
+
+    /// Disk to restore onto (default: the disk the backup was taken from)
+    #[clap(long)]
+    pub disk: Option<String>,
+
+    /// Do not ask for confirmation
+    #[clap(long)]
+    pub yes: bool,
+
+    /// If set, do not perform any changes, just simulate
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+pub fn handle(args: DiskArgs) -> anyhow::Result<()> {
+    match args.command {
+        DiskCommand::Restore(args) => restore(args),
+    }
+}
+
+fn restore(args: RestoreArgs) -> anyhow::Result<()> {
+    say!("{}", colors::header("Restore Partition Table"));
+
+    let runner: Box<dyn CommandRunner> = if args.dry_run {
+        Box::new(DryRunRunner)
+    } else {
+        Box::new(RealRunner)
+    };
+    let runner = runner.as_ref();
+
+    let backup = backup::resolve_backup(&args.backup)?;
+    let disk = match args.disk {
+        Some(disk) => disk,
+        None => backup::disk_from_backup_name(&backup)
+            .context("Cannot tell the disk from the backup's name, pass --disk")?,
+    };
+
+    // Only the tool for this backup's format (sgdisk for GPT, sfdisk for MBR)
+    ensure_tool_exists(backup::restore_tool(&backup)?)?;
+
+    say!(
+        "{}",
+        colors::info(&format!(
+            "Backup: {}\nDisk:   {}",
+            colors::highlight(&backup.display().to_string()),
+            colors::highlight(&disk)
+        ))
+    );
+
+    // Restoring replaces the whole table, nothing on the disk may be in use
+    preflight::check_disk(&disk, true, false)?;
+
+    let confirmed = args.yes
+        || Confirm::new()
+            .with_prompt(colors::warn(&format!(
+                "Overwrite the partition table of {}? Partitions created since the backup are lost!",
+                disk
+            )))
+            .default(false)
+            .interact()
+            .context("Failed to get confirmation")?;
+
+    if !confirmed {
+        bail!("User declined to restore the partition table");
+    }
+
+    backup::restore_partition_table(runner, &backup, &disk)?;
+
+    say!(
+        "{}",
+        colors::success(&format!("✓ Partition table of {} restored", disk))
+    );
+
+    Ok(())
+}
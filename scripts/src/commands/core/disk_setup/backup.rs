@@ -0,0 +1,252 @@
+use anyhow::{Context, Result, bail};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::colors;
+use crate::commands::core::disk_setup::devices;
+use crate::helpers::CommandRunner;
+use crate::helpers::{run_out, run_show, run_show_with_input};
+
+/// `sgdisk --backup` image (protective MBR, both GPT headers and entries)
+const GPT_EXTENSION: &str = "gpt";
+/// `sfdisk --dump` script for MBR disks
+const SFDISK_EXTENSION: &str = "sfdisk";
+
+// ---------------------------------------------------------
+// sharch's state directory ($XDG_STATE_HOME/sharch)
+// ---------------------------------------------------------
+pub fn state_dir() -> Result<PathBuf> {
+    let state = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let home =
+                std::env::var_os("HOME").context("Neither XDG_STATE_HOME nor HOME is set")?;
+            PathBuf::from(home).join(".local/state")
+        }
+    };
+
+    Ok(state.join("sharch"))
+}
+
+// Where backups are kept ($XDG_STATE_HOME/sharch/partition-backups)
+pub fn backup_dir() -> Result<PathBuf> {
+    Ok(state_dir()?.join("partition-backups"))
+}
+
+// ---------------------------------------------------------
+// Save the current partition table before changing it
+// ---------------------------------------------------------
+pub fn backup_partition_table(
+    runner: &dyn CommandRunner,
+    disk_path: &str,
+) -> Result<Option<PathBuf>> {
+    let pttype = run_out(
+        runner,
+        Command::new("lsblk").args(["-dno", "PTTYPE", disk_path]),
+    )
+    .context("Failed to read partition table type")?;
+
+    let extension = match pttype.trim() {
+        "gpt" => GPT_EXTENSION,
+        "dos" => SFDISK_EXTENSION,
+        "" => {
+            say!(
+                "{}",
+                colors::info(&format!("{} has no partition table to back up", disk_path))
+            );
+            return Ok(None);
+        }
+        other => bail!(
+            "Cannot back up `{}` partition table on {}, refusing to continue",
+            other,
+            disk_path
+        ),
+    };
+
+    let dir = backup_dir()?;
+    let path = dir.join(backup_name(
+        &devices::kernel_name(disk_path)?,
+        &chrono::Local::now(),
+        extension,
+    ));
+
+    if runner.is_dry_run() {
+        say!(
+            "{}",
+            colors::info(&format!(
+                "[DRY RUN] Would back up the partition table of {} to {}",
+                disk_path,
+                path.display()
+            ))
+        );
+        return Ok(Some(path));
+    }
+
+    std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    if extension == GPT_EXTENSION {
+        run_show(
+            runner,
+            Command::new("sgdisk").args([&format!("--backup={}", path.display()), disk_path]),
+        )
+        .context("Failed to back up GPT")?;
+    } else {
+        let dump = run_out(runner, Command::new("sfdisk").args(["--dump", disk_path]))
+            .context("Failed to dump MBR partition table")?;
+        std::fs::write(&path, dump)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+
+    say!(
+        "{}",
+        colors::success(&format!(
+            "✓ Partition table backed up to {}",
+            path.display()
+        ))
+    );
+    say!(
+        "{}",
+        colors::info(&format!(
+            "Undo with `sharch disk restore {}`",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ))
+    );
+
+    Ok(Some(path))
+}
+
+// ---------------------------------------------------------
+// Find a backup by path or by file name in the backup dir
+// ---------------------------------------------------------
+pub fn resolve_backup(backup: &Path) -> Result<PathBuf> {
+    if backup.exists() {
+        return Ok(backup.to_path_buf());
+    }
+
+    let in_dir = backup_dir()?.join(backup);
+    if backup.components().count() == 1 && in_dir.exists() {
+        return Ok(in_dir);
+    }
+
+    bail!("Backup {} not found", backup.display());
+}
+
+// "sda-20250101-120000.gpt", read back by `disk_from_backup_name`
+fn backup_name(disk: &str, at: &chrono::DateTime<chrono::Local>, extension: &str) -> String {
+    format!("{}-{}.{}", disk, at.format("%Y%m%d-%H%M%S"), extension)
+}
+
+// "sda-20250101-120000.gpt" -> /dev/sda
+pub fn disk_from_backup_name(backup: &Path) -> Option<String> {
+    let stem = backup.file_stem()?.to_str()?;
+    // Strip "-<date>-<time>"
+    let mut parts = stem.rsplitn(3, '-');
+    let (_time, _date, disk) = (parts.next()?, parts.next()?, parts.next()?);
+    Some(format!("/dev/{}", disk))
+}
+
+// Tool that writes the backup back, from its extension
+pub fn restore_tool(backup: &Path) -> Result<&'static str> {
+    match backup.extension().and_then(|e| e.to_str()) {
+        Some(GPT_EXTENSION) => Ok("sgdisk"),
+        Some(SFDISK_EXTENSION) => Ok("sfdisk"),
+        _ => bail!(
+            "Unknown backup format {} (expected .{} or .{})",
+            backup.display(),
+            GPT_EXTENSION,
+            SFDISK_EXTENSION
+        ),
+    }
+}
+
+// ---------------------------------------------------------
+// Write a backup back onto the disk
+// ---------------------------------------------------------
+pub fn restore_partition_table(
+    runner: &dyn CommandRunner,
+    backup: &Path,
+    disk_path: &str,
+) -> Result<()> {
+    let backup_str = backup.to_string_lossy().to_string();
+
+    if restore_tool(backup)? == "sgdisk" {
+        run_show(
+            runner,
+            Command::new("sgdisk").args([&format!("--load-backup={}", backup_str), disk_path]),
+        )
+        .context("Failed to restore GPT backup")?;
+    } else {
+        let script = std::fs::read(backup)
+            .with_context(|| format!("Failed to read {}", backup.display()))?;
+        run_show_with_input(runner, Command::new("sfdisk").arg(disk_path), &script)
+            .context("Failed to restore MBR partition table")?;
+    }
+
+    if !runner.is_dry_run() {
+        devices::reread_partitions(runner, disk_path);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::runner::FakeRunner;
+    use chrono::TimeZone;
+
+    #[test]
+    fn backup_names_lead_back_to_the_disk() {
+        let at = chrono::Local
+            .with_ymd_and_hms(2026, 10, 18, 12, 30, 5)
+            .unwrap();
+
+        for disk in ["sda", "nvme0n1", "dm-0", "md-root"] {
+            let name = backup_name(disk, &at, GPT_EXTENSION);
+            let path = Path::new("/root/.local/state/sharch/partition-backups").join(&name);
+
+            assert_eq!(
+                disk_from_backup_name(&path),
+                Some(format!("/dev/{}", disk)),
+                "{}",
+                name
+            );
+        }
+
+        assert_eq!(
+            backup_name("nvme0n1", &at, SFDISK_EXTENSION),
+            "nvme0n1-20261018-123005.sfdisk"
+        );
+        assert_eq!(disk_from_backup_name(Path::new("table.gpt")), None);
+    }
+
+    #[test]
+    fn picks_the_tool_from_the_extension() {
+        assert_eq!(
+            restore_tool(Path::new("sda-20261018-123005.gpt")).unwrap(),
+            "sgdisk"
+        );
+        assert_eq!(
+            restore_tool(Path::new("sda-20261018-123005.sfdisk")).unwrap(),
+            "sfdisk"
+        );
+        assert!(restore_tool(Path::new("sda-20261018-123005.img")).is_err());
+    }
+
+    #[test]
+    fn restores_gpt_with_sgdisk() {
+        let runner = FakeRunner::new();
+        let backup = Path::new("/backups/sda-20261018-123005.gpt");
+
+        restore_partition_table(&runner, backup, "/dev/sda").unwrap();
+
+        assert_eq!(
+            runner.calls(),
+            [
+                "sgdisk --load-backup=/backups/sda-20261018-123005.gpt /dev/sda",
+                "partprobe /dev/sda",
+                "udevadm settle",
+            ]
+        );
+    }
+}
@@ -0,0 +1,261 @@
+use anyhow::{Context, Result, bail};
+use dialoguer::Select;
+use std::process::Command;
+
+use crate::colors;
+use crate::commands::core::disk_setup::structs::BootMode;
+use crate::commands::core::disk_setup::structs::Filesystem;
+use crate::commands::core::disk_setup::structs::Firmware;
+use crate::commands::core::disk_setup::structs::PartitionPlan;
+use crate::commands::core::disk_setup::structs::PartitionTable;
+use crate::commands::core::disk_setup::structs::PlannedPartition;
+use crate::commands::core::disk_setup::structs::SectorRange;
+use crate::commands::core::disk_setup::structs::SizeSpec;
+use crate::helpers::CommandRunner;
+use crate::helpers::run_out;
+
+/// GRUB's core image fits easily, 1 MiB also keeps the next partition aligned
+const BIOS_GRUB_BYTES: u64 = 1_048_576;
+
+// ---------------------------------------------------------
+// How the running machine was booted
+// ---------------------------------------------------------
+pub fn detect_firmware() -> Firmware {
+    if std::path::Path::new("/sys/firmware/efi").exists() {
+        Firmware::Uefi
+    } else {
+        Firmware::Bios
+    }
+}
+
+// ---------------------------------------------------------
+// Partition table already on the disk (lsblk PTTYPE)
+// ---------------------------------------------------------
+pub fn existing_table(
+    runner: &dyn CommandRunner,
+    disk_path: &str,
+) -> Result<Option<PartitionTable>> {
+    let pttype = run_out(
+        runner,
+        Command::new("lsblk").args(["-dno", "PTTYPE", disk_path]),
+    )
+    .context("Failed to read partition table type")?;
+
+    match pttype.trim() {
+        "gpt" => Ok(Some(PartitionTable::Gpt)),
+        "dos" => Ok(Some(PartitionTable::Msdos)),
+        "" => Ok(None),
+        other => bail!("Unsupported `{}` partition table on {}", other, disk_path),
+    }
+}
+
+// ---------------------------------------------------------
+// Boot mode from --boot-mode / the layout, the disk or a prompt
+// ---------------------------------------------------------
+pub fn choose_boot_mode(
+    requested: Option<BootMode>,
+    existing: Option<PartitionTable>,
+    interactive: bool,
+) -> Result<BootMode> {
+    let firmware = detect_firmware();
+
+    let mode = match (requested, firmware, existing) {
+        (Some(mode), _, _) => mode,
+        (None, Firmware::Uefi, _) => BootMode::Uefi,
+        // New partitions go next to the existing ones, so the table decides
+        (None, Firmware::Bios, Some(PartitionTable::Gpt)) => BootMode::BiosGpt,
+        (None, Firmware::Bios, Some(PartitionTable::Msdos)) => BootMode::BiosMbr,
+        (None, Firmware::Bios, None) if interactive => prompt_bios_table()?,
+        (None, Firmware::Bios, None) => BootMode::BiosGpt,
+    };
+
+    if let Some(table) = existing
+        && table != mode.table()
+    {
+        bail!(
+            "The disk has a {} partition table, but {} needs {}; pass --wipe to start over",
+            table,
+            mode.describe(),
+            mode.table()
+        );
+    }
+
+    if (firmware == Firmware::Uefi) != (mode == BootMode::Uefi) {
+        say!(
+            "{}",
+            colors::warn(&format!(
+                "This machine booted via {:?}, but the install is set up for {}",
+                firmware,
+                mode.describe()
+            ))
+        );
+    }
+
+    say!(
+        "{}\n",
+        colors::info(&format!("Boot mode: {}", mode.describe()))
+    );
+
+    Ok(mode)
+}
+
+fn prompt_bios_table() -> Result<BootMode> {
+    let choices = [BootMode::BiosGpt, BootMode::BiosMbr];
+    let items = [
+        "GPT with a 1 MiB bios_grub partition (recommended)",
+        "msdos (MBR), for firmware that cannot boot from GPT",
+    ];
+
+    let selection = Select::new()
+        .with_prompt(colors::info("BIOS machine, which partition table?"))
+        .items(items)
+        .default(0)
+        .interact()
+        .context("Partition table selection aborted")?;
+
+    Ok(choices[selection])
+}
+
+// ---------------------------------------------------------
+// Planned bios_grub partition (GPT on BIOS machines)
+// ---------------------------------------------------------
+pub fn bios_grub_partition() -> PlannedPartition {
+    PlannedPartition {
+        size: SizeSpec::Bytes(BIOS_GRUB_BYTES),
+        type_code: "ef02".to_string(),
+        label: "BIOSBOOT".to_string(),
+        filesystem: Filesystem::BiosBoot,
+        mount_point: None,
+        mount_options: None,
+    }
+}
+
+// msdos cannot address sectors past 2^32
+pub fn check_table_limits(table: PartitionTable, ranges: &[SectorRange]) -> Result<()> {
+    if let Some(last) = ranges.iter().map(|r| r.end).max()
+        && last > table.max_sector()
+    {
+        bail!(
+            "Partitions end at sector {}, past what a {} label can address; use --boot-mode bios-gpt",
+            last,
+            table
+        );
+    }
+
+    Ok(())
+}
+
+// ---------------------------------------------------------
+// msdos partitions: parted, since sgdisk only writes GPT
+// ---------------------------------------------------------
+
+// The BIOS boots the partition flagged `boot`: /boot if there is one, else /
+pub fn mbr_boot_index(plan: &PartitionPlan) -> Option<usize> {
+    let index_of = |mp: &str| {
+        plan.partitions
+            .iter()
+            .position(|p| p.mount_point.as_deref() == Some(mp))
+    };
+
+    index_of("/boot").or_else(|| index_of("/"))
+}
+
+/// `parted` arguments for one primary partition; sgdisk type codes become flags
+pub fn mbr_partition_args(
+    disk_path: &str,
+    number: u32,
+    part: &PlannedPartition,
+    range: &SectorRange,
+    boot: bool,
+) -> Vec<String> {
+    let mut args: Vec<String> = ["-s", disk_path, "unit", "s", "mkpart", "primary"]
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+
+    // The fs-type only picks the MBR type byte (82 instead of 83)
+    if part.filesystem == Filesystem::Swap {
+        args.push("linux-swap".to_string());
+    }
+    args.push(format!("{}s", range.start));
+    args.push(format!("{}s", range.end));
+
+    let mut flags = Vec::new();
+    match part.type_code.to_ascii_lowercase().as_str() {
+        "8e00" => flags.push("lvm"),
+        "fd00" => flags.push("raid"),
+        _ => {}
+    }
+    if boot {
+        flags.push("boot");
+    }
+
+    for flag in flags {
+        args.extend([
+            "set".to_string(),
+            number.to_string(),
+            flag.to_string(),
+            "on".to_string(),
+        ]);
+    }
+
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partition(type_code: &str, filesystem: Filesystem, mount: Option<&str>) -> PlannedPartition {
+        PlannedPartition {
+            size: SizeSpec::Rest,
+            type_code: type_code.to_string(),
+            label: "ROOT".to_string(),
+            filesystem,
+            mount_point: mount.map(str::to_string),
+            mount_options: None,
+        }
+    }
+
+    #[test]
+    fn mbr_partitions_carry_type_and_boot_flags() {
+        let plan = PartitionPlan {
+            partitions: vec![
+                partition("8200", Filesystem::Swap, None),
+                partition("8300", Filesystem::Ext4, Some("/")),
+            ],
+            reuse_esp: None,
+            swap: None,
+            lvm: None,
+            boot_mode: BootMode::BiosMbr,
+        };
+        assert_eq!(mbr_boot_index(&plan), Some(1));
+
+        let swap = SectorRange {
+            start: 2048,
+            end: 16_779_263,
+        };
+        assert_eq!(
+            mbr_partition_args("/dev/sda", 1, &plan.partitions[0], &swap, false).join(" "),
+            "-s /dev/sda unit s mkpart primary linux-swap 2048s 16779263s"
+        );
+
+        let root = SectorRange {
+            start: 16_779_264,
+            end: 41_943_039,
+        };
+        assert_eq!(
+            mbr_partition_args("/dev/sda", 2, &plan.partitions[1], &root, true).join(" "),
+            "-s /dev/sda unit s mkpart primary 16779264s 41943039s set 2 boot on"
+        );
+    }
+
+    #[test]
+    fn msdos_cannot_reach_past_two_tib() {
+        let range = |end| SectorRange { start: 2048, end };
+
+        assert!(check_table_limits(PartitionTable::Msdos, &[range(u32::MAX as u64)]).is_ok());
+        assert!(check_table_limits(PartitionTable::Msdos, &[range(1 << 32)]).is_err());
+        assert!(check_table_limits(PartitionTable::Gpt, &[range(1 << 40)]).is_ok());
+    }
+}
@@ -0,0 +1,248 @@
+use anyhow::{Context, Result, bail};
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use crate::helpers::CommandRunner;
+use crate::helpers::run_out;
+
+/// How long to wait for udev to create a partition's device node
+const PARTITION_TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// ---------------------------------------------------------
+// Kernel name of a disk (/dev/disk/by-id/... -> nvme0n1)
+// ---------------------------------------------------------
+pub fn kernel_name(disk_path: &str) -> Result<String> {
+    let canonical = std::fs::canonicalize(disk_path)
+        .with_context(|| format!("Failed to resolve {}", disk_path))?;
+
+    canonical
+        .file_name()
+        .and_then(|n| n.to_str())
+        .map(str::to_string)
+        .with_context(|| format!("{} has no device name", disk_path))
+}
+
+// ---------------------------------------------------------
+// Partition device node from sysfs (lsblk as fallback)
+// ---------------------------------------------------------
+pub fn resolve_partition(
+    runner: &dyn CommandRunner,
+    disk_path: &str,
+    number: u32,
+) -> Result<Option<String>> {
+    let disk = kernel_name(disk_path)?;
+
+    // /sys/class/block/<disk>/<part>/partition holds the partition number
+    let sys_dir = format!("/sys/class/block/{}", disk);
+    if let Ok(entries) = std::fs::read_dir(&sys_dir) {
+        for entry in entries.flatten() {
+            let partn = std::fs::read_to_string(entry.path().join("partition"));
+            if partn.is_ok_and(|n| n.trim().parse::<u32>().ok() == Some(number)) {
+                let name = entry.file_name().to_string_lossy().to_string();
+                return Ok(Some(format!("/dev/{}", name)));
+            }
+        }
+        return Ok(None);
+    }
+
+    let out = run_out(
+        runner,
+        Command::new("lsblk").args(["-J", "-o", "NAME,PARTN,PKNAME"]),
+    )
+    .context("Failed running lsblk")?;
+    let root: serde_json::Value =
+        serde_json::from_str(&out).context("Failed parsing lsblk JSON")?;
+
+    Ok(find_in_lsblk(&root, &disk, number))
+}
+
+fn find_in_lsblk(node: &serde_json::Value, disk: &str, number: u32) -> Option<String> {
+    let children = node
+        .get("blockdevices")
+        .or_else(|| node.get("children"))
+        .and_then(|v| v.as_array())?;
+
+    for child in children {
+        let pkname = child.get("pkname").and_then(|v| v.as_str());
+        let partn = child.get("partn").and_then(|v| v.as_u64());
+
+        if pkname == Some(disk) && partn == Some(number as u64) {
+            let name = child.get("name").and_then(|v| v.as_str())?;
+            return Some(format!("/dev/{}", name));
+        }
+
+        if let Some(found) = find_in_lsblk(child, disk, number) {
+            return Some(found);
+        }
+    }
+
+    None
+}
+
+// ---------------------------------------------------------
+// Wait until the kernel and udev expose the partition
+// ---------------------------------------------------------
+pub fn wait_for_partition(
+    runner: &dyn CommandRunner,
+    disk_path: &str,
+    number: u32,
+) -> Result<String> {
+    let deadline = Instant::now() + PARTITION_TIMEOUT;
+
+    loop {
+        if let Some(device) = resolve_partition(runner, disk_path, number)?
+            && Path::new(&device).exists()
+        {
+            return Ok(device);
+        }
+
+        if Instant::now() >= deadline {
+            bail!(
+                "Partition {} on {} did not appear within {}s",
+                number,
+                disk_path,
+                PARTITION_TIMEOUT.as_secs()
+            );
+        }
+
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// ---------------------------------------------------------
+// Tell the kernel about a changed partition table
+// ---------------------------------------------------------
+pub fn reread_partitions(runner: &dyn CommandRunner, disk_path: &str) {
+    let _ = runner.run(Command::new("partprobe").arg(disk_path), None);
+    let _ = runner.run(Command::new("udevadm").args(["settle"]), None);
+}
+
+// ---------------------------------------------------------
+// Name the kernel will give a partition (dry runs only)
+// ---------------------------------------------------------
+pub fn predicted_partition_path(disk_path: &str, number: u32) -> String {
+    let disk = kernel_name(disk_path).unwrap_or_else(|_| {
+        disk_path
+            .rsplit('/')
+            .next()
+            .unwrap_or(disk_path)
+            .to_string()
+    });
+
+    // Same rule as the kernel: names ending in a digit get a `p` separator
+    if disk.ends_with(|c: char| c.is_ascii_digit()) {
+        format!("/dev/{}p{}", disk, number)
+    } else {
+        format!("/dev/{}{}", disk, number)
+    }
+}
+
+// ---------------------------------------------------------
+// Stable /dev/disk/by-id path of a disk
+// ---------------------------------------------------------
+pub fn by_id_path(name: &str) -> Option<String> {
+    let target = Path::new("/dev").join(name);
+    let entries = std::fs::read_dir("/dev/disk/by-id").ok()?;
+
+    let mut links: Vec<String> = entries
+        .flatten()
+        .filter(|e| std::fs::canonicalize(e.path()).is_ok_and(|p| p == target))
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .collect();
+
+    // Model/serial names (ata-, nvme-Samsung_...) read better than wwn- or eui.
+    links.sort_by_key(|l| (l.starts_with("wwn-") || l.contains("eui."), l.len()));
+
+    links.first().map(|l| format!("/dev/disk/by-id/{}", l))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::runner::FakeRunner;
+
+    #[test]
+    fn names_ending_in_a_digit_get_a_p_separator() {
+        assert_eq!(predicted_partition_path("/dev/sda", 1), "/dev/sda1");
+        assert_eq!(predicted_partition_path("/dev/loop0", 1), "/dev/loop0p1");
+        assert_eq!(
+            predicted_partition_path("/dev/nvme0n1", 1),
+            "/dev/nvme0n1p1"
+        );
+        assert_eq!(
+            predicted_partition_path("/dev/mmcblk0", 2),
+            "/dev/mmcblk0p2"
+        );
+        assert_eq!(predicted_partition_path("/dev/vdb", 12), "/dev/vdb12");
+    }
+
+    #[test]
+    fn symlinks_are_named_after_their_target() {
+        let dir = std::env::temp_dir().join(format!("sharch-test-by-id-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("nvme0n1");
+        let link = dir.join("nvme-Samsung_SSD_980_1TB_S649NX0R");
+        std::fs::write(&target, "").unwrap();
+        let _ = std::fs::remove_file(&link);
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let name = kernel_name(link.to_str().unwrap());
+        let predicted = predicted_partition_path(link.to_str().unwrap(), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(name.unwrap(), "nvme0n1");
+        assert_eq!(predicted, "/dev/nvme0n1p3");
+    }
+
+    #[test]
+    fn falls_back_to_lsblk_without_sysfs() {
+        // No /sys/class/block entry exists for this name
+        let dir = std::env::temp_dir().join(format!("sharch-test-lsblk-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let disk = dir.join("sharchtest0");
+        std::fs::write(&disk, "").unwrap();
+
+        let runner = FakeRunner::new().respond(
+            "lsblk",
+            r#"{"blockdevices": [{"name": "sharchtest0", "partn": null, "pkname": null,
+                "children": [{"name": "sharchtest0p1", "partn": 1, "pkname": "sharchtest0"}]}]}"#,
+        );
+        let found = resolve_partition(&runner, disk.to_str().unwrap(), 1);
+        let missing = resolve_partition(&runner, disk.to_str().unwrap(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(found.unwrap().as_deref(), Some("/dev/sharchtest0p1"));
+        assert_eq!(missing.unwrap(), None);
+        assert_eq!(runner.calls_to("lsblk")[0], "lsblk -J -o NAME,PARTN,PKNAME");
+    }
+
+    #[test]
+    fn finds_the_partition_of_the_right_disk_in_lsblk() {
+        // Same partition number on two disks, one partition holding a LUKS mapper
+        let root: serde_json::Value = serde_json::from_str(
+            r#"{"blockdevices": [
+                {"name": "sda", "partn": null, "pkname": null, "children": [
+                    {"name": "sda1", "partn": 1, "pkname": "sda"},
+                    {"name": "sda2", "partn": 2, "pkname": "sda", "children": [
+                        {"name": "cryptroot", "partn": null, "pkname": "sda2"}
+                    ]}
+                ]},
+                {"name": "loop0", "partn": null, "pkname": null, "children": [
+                    {"name": "loop0p1", "partn": 1, "pkname": "loop0"},
+                    {"name": "loop0p2", "partn": 2, "pkname": "loop0"}
+                ]}
+            ]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(find_in_lsblk(&root, "sda", 2).as_deref(), Some("/dev/sda2"));
+        assert_eq!(
+            find_in_lsblk(&root, "loop0", 2).as_deref(),
+            Some("/dev/loop0p2")
+        );
+        assert_eq!(find_in_lsblk(&root, "loop0", 3), None);
+        assert_eq!(find_in_lsblk(&root, "sdb", 1), None);
+    }
+}
@@ -0,0 +1,273 @@
+use anyhow::{Context, Result, bail};
+use dialoguer::{Confirm, Input, Password};
+use std::process::Command;
+
+use crate::colors;
+use crate::commands::core::disk_setup::structs::CreatedPartitions;
+use crate::commands::core::disk_setup::structs::DiskLayout;
+use crate::commands::core::disk_setup::structs::EncryptedVolume;
+use crate::commands::core::disk_setup::structs::EncryptionConfig;
+use crate::commands::core::disk_setup::structs::Filesystem;
+use crate::helpers::CommandRunner;
+use crate::helpers::{run_show, run_show_with_input};
+
+/// mkinitcpio hook that understands `rd.luks.name=`
+pub const MKINITCPIO_HOOK: &str = "sd-encrypt";
+
+// ---------------------------------------------------------
+// Ask whether to encrypt (or take it from the layout)
+// ---------------------------------------------------------
+pub fn get_encryption_config(layout: Option<&DiskLayout>) -> Result<Option<EncryptionConfig>> {
+    if let Some(layout) = layout {
+        if let Some(config) = &layout.encryption {
+            validate_mapper_name(&config.mapper_name)?;
+            check_mapper_unused(&config.mapper_name)?;
+        }
+        return Ok(layout.encryption.clone());
+    }
+
+    let encrypt = Confirm::new()
+        .with_prompt(colors::info("Encrypt the Linux partition with LUKS2?"))
+        .default(false)
+        .interact()
+        .context("Failed to get confirmation")?;
+
+    if !encrypt {
+        return Ok(None);
+    }
+
+    let mapper_name: String = Input::new()
+        .with_prompt(colors::info("Mapper name (/dev/mapper/<name>)"))
+        .default("cryptroot".to_string())
+        .interact_text()
+        .context("Failed to get mapper name")?;
+
+    let mapper_name = mapper_name.trim().to_string();
+    validate_mapper_name(&mapper_name)?;
+    check_mapper_unused(&mapper_name)?;
+
+    Ok(Some(EncryptionConfig {
+        mapper_name,
+        key_file: None,
+    }))
+}
+
+fn validate_mapper_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+
+    if !valid {
+        bail!(
+            "Invalid mapper name `{}` (use letters, digits, `-`, `_` or `.`)",
+            name
+        );
+    }
+
+    Ok(())
+}
+
+// Checked before the disk is touched, `cryptsetup open` would only fail after the wipe
+fn check_mapper_unused(name: &str) -> Result<()> {
+    if std::path::Path::new(&format!("/dev/mapper/{}", name)).exists() {
+        bail!(
+            "/dev/mapper/{} already exists, close it first or pick another name",
+            name
+        );
+    }
+
+    Ok(())
+}
+
+// ---------------------------------------------------------
+// Format the root partition as LUKS2 and open it
+// ---------------------------------------------------------
+pub fn encrypt_root(
+    runner: &dyn CommandRunner,
+    partitions: &mut CreatedPartitions,
+    config: &EncryptionConfig,
+) -> Result<()> {
+    say!("{}", colors::header("Encrypting Linux Partition (LUKS2)"));
+
+    let root = partitions
+        .partitions
+        .iter_mut()
+        .find(|p| {
+            p.spec.mount_point.as_deref() == Some("/") || p.spec.filesystem == Filesystem::Lvm
+        })
+        .context("No partition is mounted at `/` or holds LVM, nothing to encrypt")?;
+
+    let passphrase = if runner.is_dry_run() {
+        Vec::new()
+    } else {
+        read_passphrase(config)?
+    };
+
+    say!(
+        "{}",
+        colors::warn(&format!(
+            "All data on {} will be lost. Do not forget the passphrase!",
+            root.device
+        ))
+    );
+
+    // --key-file=- reads the passphrase from stdin, so it never shows up in argv
+    run_show_with_input(
+        runner,
+        Command::new("cryptsetup").args([
+            "luksFormat",
+            "--type",
+            "luks2",
+            "--batch-mode",
+            "--key-file=-",
+            &root.device,
+        ]),
+        &passphrase,
+    )
+    .context("Failed to format LUKS2 container")?;
+
+    run_show_with_input(
+        runner,
+        Command::new("cryptsetup").args([
+            "open",
+            "--key-file=-",
+            &root.device,
+            &config.mapper_name,
+        ]),
+        &passphrase,
+    )
+    .context("Failed to open LUKS2 container")?;
+
+    let luks_uuid = run_show(
+        runner,
+        Command::new("cryptsetup").args(["luksUUID", &root.device]),
+    )
+    .context("Failed to read LUKS UUID")?
+    .trim()
+    .to_string();
+
+    let volume = EncryptedVolume {
+        luks_uuid,
+        mapper_name: config.mapper_name.clone(),
+    };
+
+    say!(
+        "{}",
+        colors::success(&format!(
+            "✓ {} opened as {}",
+            root.device,
+            volume.mapper_path()
+        ))
+    );
+    say!();
+
+    root.encryption = Some(volume);
+
+    Ok(())
+}
+
+// ---------------------------------------------------------
+// Passphrase (hidden, asked twice) or key file
+// ---------------------------------------------------------
+fn read_passphrase(config: &EncryptionConfig) -> Result<Vec<u8>> {
+    if let Some(path) = &config.key_file {
+        let key = std::fs::read(path)
+            .with_context(|| format!("Failed to read key file {}", path.display()))?;
+
+        if key.is_empty() {
+            bail!("Key file {} is empty", path.display());
+        }
+
+        return Ok(key);
+    }
+
+    let passphrase = Password::new()
+        .with_prompt(colors::info("LUKS passphrase"))
+        .with_confirmation(
+            colors::info("Repeat passphrase"),
+            colors::warn("Passphrases do not match"),
+        )
+        .interact()
+        .context("Failed to read passphrase")?;
+
+    Ok(passphrase.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::core::disk_setup::structs::CreatedPartition;
+    use crate::commands::core::disk_setup::structs::PlannedPartition;
+    use crate::commands::core::disk_setup::structs::SizeSpec;
+    use crate::helpers::runner::FakeRunner;
+
+    fn root_partition() -> CreatedPartitions {
+        CreatedPartitions {
+            partitions: vec![CreatedPartition {
+                number: 2,
+                device: "/dev/sda2".to_string(),
+                spec: PlannedPartition {
+                    size: SizeSpec::Rest,
+                    type_code: "8300".to_string(),
+                    label: "ROOT".to_string(),
+                    filesystem: Filesystem::Btrfs,
+                    mount_point: Some("/".to_string()),
+                    mount_options: None,
+                },
+                encryption: None,
+                reused: false,
+                raid: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn passphrase_only_goes_through_stdin() {
+        let key_file = std::env::temp_dir().join(format!("sharch-test-{}.key", std::process::id()));
+        std::fs::write(&key_file, "correct horse").unwrap();
+
+        let runner = FakeRunner::new().respond("cryptsetup luksUUID", "1234-abcd\n");
+        let mut partitions = root_partition();
+        let config = EncryptionConfig {
+            mapper_name: "cryptroot".to_string(),
+            key_file: Some(key_file.clone()),
+        };
+
+        let result = encrypt_root(&runner, &mut partitions, &config);
+        std::fs::remove_file(&key_file).unwrap();
+        result.unwrap();
+
+        assert_eq!(
+            runner.calls(),
+            [
+                "cryptsetup luksFormat --type luks2 --batch-mode --key-file=- /dev/sda2",
+                "cryptsetup open --key-file=- /dev/sda2 cryptroot",
+                "cryptsetup luksUUID /dev/sda2",
+            ]
+        );
+        assert!(runner.calls().iter().all(|c| !c.contains("correct horse")));
+
+        let inputs = runner.inputs();
+        assert_eq!(inputs.len(), 2);
+        assert!(inputs.iter().all(|(_, input)| input == b"correct horse"));
+        assert!(inputs[0].0.contains("luksFormat"));
+        assert!(inputs[1].0.contains("open"));
+
+        // Later stages format and mount the mapper, not the partition
+        let root = &partitions.partitions[0];
+        assert_eq!(root.encryption.as_ref().unwrap().luks_uuid, "1234-abcd");
+        assert_eq!(root.fs_device(), "/dev/mapper/cryptroot");
+    }
+
+    #[test]
+    fn rejects_bad_mapper_names() {
+        assert!(validate_mapper_name("cryptroot").is_ok());
+        assert!(validate_mapper_name("crypt-root_1.0").is_ok());
+
+        assert!(validate_mapper_name("").is_err());
+        assert!(validate_mapper_name("../root").is_err());
+        assert!(validate_mapper_name("mapper/root").is_err());
+        assert!(validate_mapper_name("crypt root").is_err());
+    }
+}
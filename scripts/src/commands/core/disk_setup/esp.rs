@@ -0,0 +1,438 @@
+use anyhow::{Context, Result, bail};
+use dialoguer::{Confirm, Input, Select};
+use std::process::Command;
+
+use crate::colors;
+use crate::commands::core::disk_setup::devices;
+use crate::commands::core::disk_setup::size;
+use crate::commands::core::disk_setup::structs::CreatedPartition;
+use crate::commands::core::disk_setup::structs::DiskLayout;
+use crate::commands::core::disk_setup::structs::EspChoice;
+use crate::commands::core::disk_setup::structs::EspConfig;
+use crate::commands::core::disk_setup::structs::ExistingEsp;
+use crate::commands::core::disk_setup::structs::Filesystem;
+use crate::commands::core::disk_setup::structs::PartitionPlan;
+use crate::commands::core::disk_setup::structs::PlannedPartition;
+use crate::commands::core::disk_setup::structs::SizeSpec;
+use crate::helpers::CommandRunner;
+use crate::helpers::{run_out, run_show};
+
+/// GPT partition type GUID of an EFI System Partition
+pub const ESP_TYPE_GUID: &str = "c12a7328-f81f-11d2-ba4b-00a0c93ec93b";
+/// sgdisk type code for XBOOTLDR (Linux extended boot)
+pub const XBOOTLDR_TYPE_CODE: &str = "ea00";
+/// GPT partition type GUID of XBOOTLDR
+pub const XBOOTLDR_TYPE_GUID: &str = "bc13c2ff-59e6-4262-a352-b275fd6f7172";
+
+// ---------------------------------------------------------
+// Detect existing ESPs on the disk
+// ---------------------------------------------------------
+pub fn detect_esps(runner: &dyn CommandRunner, disk_path: &str) -> Result<Vec<ExistingEsp>> {
+    let out = run_out(
+        runner,
+        Command::new("lsblk").args([
+            "-J",
+            "-b",
+            "-o",
+            "PATH,PARTN,PARTTYPE,SIZE,FSAVAIL",
+            disk_path,
+        ]),
+    )
+    .context("Failed running lsblk")?;
+
+    let root: serde_json::Value =
+        serde_json::from_str(&out).context("Failed parsing lsblk JSON")?;
+
+    let children = root
+        .get("blockdevices")
+        .and_then(|v| v.as_array())
+        .and_then(|devs| devs.first())
+        .and_then(|disk| disk.get("children"))
+        .and_then(|v| v.as_array());
+
+    let mut esps = Vec::new();
+
+    for child in children.into_iter().flatten() {
+        let is_esp = child
+            .get("parttype")
+            .and_then(|v| v.as_str())
+            .is_some_and(|t| t.eq_ignore_ascii_case(ESP_TYPE_GUID));
+
+        if !is_esp {
+            continue;
+        }
+
+        let (Some(device), Some(number)) = (
+            child.get("path").and_then(|v| v.as_str()),
+            child.get("partn").and_then(|v| v.as_u64()),
+        ) else {
+            continue;
+        };
+
+        // FSAVAIL is only filled in for mounted filesystems
+        let free_bytes = match json_u64(child.get("fsavail")) {
+            Some(free) => Some(free),
+            None => probe_free_bytes(runner, device),
+        };
+
+        esps.push(ExistingEsp {
+            device: device.to_string(),
+            number: number as u32,
+            size_bytes: json_u64(child.get("size")).unwrap_or(0),
+            free_bytes,
+        });
+    }
+
+    Ok(esps)
+}
+
+// lsblk -b prints numbers, older versions print them as strings
+fn json_u64(value: Option<&serde_json::Value>) -> Option<u64> {
+    let value = value?;
+    value
+        .as_u64()
+        .or_else(|| value.as_str().and_then(|s| s.parse().ok()))
+}
+
+// Mount read-only in a scratch directory and ask df
+fn probe_free_bytes(runner: &dyn CommandRunner, device: &str) -> Option<u64> {
+    // Even a read-only mount is a change, dry runs show the free space as unknown
+    if runner.is_dry_run() {
+        return None;
+    }
+
+    let dir = std::env::temp_dir().join(format!(
+        "sharch-esp-{}",
+        device.trim_start_matches("/dev/").replace('/', "-")
+    ));
+    std::fs::create_dir_all(&dir).ok()?;
+    let dir_str = dir.to_str()?;
+
+    let mounted = run_show(
+        runner,
+        Command::new("mount").args(["-o", "ro", device, dir_str]),
+    )
+    .is_ok();
+
+    let free = if mounted {
+        let out = run_out(
+            runner,
+            Command::new("df").args(["-B1", "--output=avail", dir_str]),
+        );
+        let _ = run_show(runner, Command::new("umount").arg(dir_str));
+        out.ok()
+            .and_then(|o| o.lines().nth(1).and_then(|l| l.trim().parse().ok()))
+    } else {
+        None
+    };
+
+    let _ = std::fs::remove_dir(&dir);
+    free
+}
+
+// ---------------------------------------------------------
+// Display detected ESPs
+// ---------------------------------------------------------
+pub fn display_esps(esps: &[ExistingEsp]) {
+    say!("\n{}\n", colors::header("Existing EFI System Partitions"));
+
+    let path_width = esps
+        .iter()
+        .map(|e| e.device.len())
+        .max()
+        .unwrap_or(6)
+        .max(6);
+
+    say!(
+        "  {:<3}  {:<path_w$}  {:<10}  {:<10}",
+        "#",
+        "Device",
+        "Size",
+        "Free",
+        path_w = path_width
+    );
+    say!();
+
+    for (i, esp) in esps.iter().enumerate() {
+        say!(
+            "  {:<3}  {:<path_w$}  {:<10}  {:<10}",
+            i + 1,
+            esp.device,
+            format_mb(esp.size_bytes),
+            esp.free_bytes.map(format_mb).unwrap_or_else(|| "?".into()),
+            path_w = path_width
+        );
+    }
+    say!();
+}
+
+fn format_mb(bytes: u64) -> String {
+    format!("{} MB", bytes / 1_048_576)
+}
+
+// ---------------------------------------------------------
+// Reuse an ESP or create a new one (layout or prompt)
+// ---------------------------------------------------------
+// `min_size_mb` applies to the prompt, a layout brings its own `[esp]` values
+pub fn choose_esp(
+    runner: &dyn CommandRunner,
+    disk_path: &str,
+    layout: Option<&DiskLayout>,
+    min_size_mb: u64,
+) -> Result<EspChoice> {
+    let esps = detect_esps(runner, disk_path)?;
+
+    if let Some(layout) = layout {
+        let Some(config) = &layout.esp else {
+            return Ok(EspChoice::default());
+        };
+        let Some(wanted) = &config.reuse else {
+            return Ok(EspChoice::default());
+        };
+
+        if layout.partitions.iter().any(|p| p.is_esp()) {
+            bail!("Layout reuses an ESP and also defines an ef00 partition");
+        }
+
+        // /dev/disk/by-id/... and other symlinks point at the kernel name
+        let wanted = wanted.trim();
+        let kernel_name = devices::kernel_name(wanted).ok();
+        let esp = esps
+            .iter()
+            .find(|e| {
+                let name = e.device.strip_prefix("/dev/");
+                e.device == wanted || name == Some(wanted) || name == kernel_name.as_deref()
+            })
+            .with_context(|| {
+                format!(
+                    "`{}` is not an EFI System Partition on {}",
+                    wanted, disk_path
+                )
+            })?
+            .clone();
+
+        // `xbootldr_size_mb = 0` keeps the small ESP as /boot
+        let xbootldr_size_mb = if esp.size_bytes < size::mib_to_bytes(config.min_size_mb)? {
+            warn_small_esp(&esp, config.min_size_mb);
+            (config.xbootldr_size_mb > 0).then_some(config.xbootldr_size_mb)
+        } else {
+            None
+        };
+
+        return Ok(EspChoice {
+            reuse: Some(esp),
+            xbootldr_size_mb,
+        });
+    }
+
+    if esps.is_empty() {
+        return Ok(EspChoice::default());
+    }
+
+    display_esps(&esps);
+
+    let mut items: Vec<String> = esps
+        .iter()
+        .map(|e| format!("Reuse {} (no format)", e.device))
+        .collect();
+    items.push("Create a new ESP".to_string());
+
+    let selection = Select::new()
+        .with_prompt(colors::info("EFI System Partition"))
+        .items(&items)
+        .default(0)
+        .interact()
+        .context("ESP selection aborted")?;
+
+    let Some(esp) = esps.get(selection).cloned() else {
+        return Ok(EspChoice::default());
+    };
+
+    let mut xbootldr_size_mb = None;
+
+    if esp.size_bytes < size::mib_to_bytes(min_size_mb)? {
+        warn_small_esp(&esp, min_size_mb);
+
+        let add = Confirm::new()
+            .with_prompt(colors::info(
+                "Create an XBOOTLDR partition for kernels (mounted at /boot, ESP at /efi)?",
+            ))
+            .default(true)
+            .interact()
+            .context("Failed to get confirmation")?;
+
+        if add {
+            let size: String = Input::new()
+                .with_prompt(colors::info(
+                    "XBOOTLDR partition size (plain numbers are MB)",
+                ))
+                .default(EspConfig::DEFAULT_XBOOTLDR_SIZE_MB.to_string())
+                .interact_text()
+                .context("Failed to get XBOOTLDR size")?;
+
+            xbootldr_size_mb = Some(size::parse_mib(&size).context("Invalid XBOOTLDR size")?);
+        }
+    }
+
+    Ok(EspChoice {
+        reuse: Some(esp),
+        xbootldr_size_mb,
+    })
+}
+
+fn warn_small_esp(esp: &ExistingEsp, min_size_mb: u64) {
+    say!(
+        "{}",
+        colors::warn(&format!(
+            "ESP {} is only {} MB (minimum {} MB), kernels and initramfs may not fit",
+            esp.device,
+            esp.size_bytes / 1_048_576,
+            min_size_mb
+        ))
+    );
+}
+
+// ---------------------------------------------------------
+// Planned XBOOTLDR partition
+// ---------------------------------------------------------
+pub fn xbootldr_partition(size_mb: u64) -> Result<PlannedPartition> {
+    Ok(PlannedPartition {
+        size: SizeSpec::Bytes(size::mib_to_bytes(size_mb).context("Invalid XBOOTLDR size")?),
+        type_code: XBOOTLDR_TYPE_CODE.to_string(),
+        label: "XBOOTLDR".to_string(),
+        filesystem: Filesystem::Vfat,
+        mount_point: Some("/boot".to_string()),
+        mount_options: None,
+    })
+}
+
+// ---------------------------------------------------------
+// Reused ESP as a created partition (mounted, never formatted)
+// ---------------------------------------------------------
+pub fn reused_esp_partition(plan: &PartitionPlan) -> Option<CreatedPartition> {
+    let esp = plan.reuse_esp.as_ref()?;
+
+    // With an XBOOTLDR partition at /boot the ESP moves to /efi
+    let has_boot = plan
+        .partitions
+        .iter()
+        .any(|p| p.mount_point.as_deref() == Some("/boot"));
+
+    Some(CreatedPartition {
+        number: esp.number,
+        device: esp.device.clone(),
+        spec: PlannedPartition {
+            size: SizeSpec::Bytes(esp.size_bytes),
+            type_code: "ef00".to_string(),
+            label: "EFI".to_string(),
+            filesystem: Filesystem::Vfat,
+            mount_point: Some(if has_boot { "/efi" } else { "/boot" }.to_string()),
+            mount_options: None,
+        },
+        encryption: None,
+        reused: true,
+        raid: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::runner::FakeRunner;
+
+    // A mounted 300 MB ESP, an unmounted 1 GiB one and a Linux partition
+    const LSBLK: &str = r#"{"blockdevices": [{
+        "path": "/dev/nvme0n1", "partn": null, "parttype": null, "size": 512110190592, "fsavail": null,
+        "children": [
+            {"path": "/dev/nvme0n1p1", "partn": 1, "parttype": "C12A7328-F81F-11D2-BA4B-00A0C93EC93B",
+             "size": 314572800, "fsavail": 104857600},
+            {"path": "/dev/nvme0n1p2", "partn": 2, "parttype": "c12a7328-f81f-11d2-ba4b-00a0c93ec93b",
+             "size": "1073741824", "fsavail": null},
+            {"path": "/dev/nvme0n1p3", "partn": 3, "parttype": "0fc63daf-8483-4772-8e79-3d69d8477de4",
+             "size": 510722801664, "fsavail": null}
+        ]
+    }]}"#;
+
+    fn runner() -> FakeRunner {
+        FakeRunner::new()
+            .respond("lsblk", LSBLK)
+            .respond("df", "    Avail\n 943718400\n")
+    }
+
+    fn layout(esp: &str) -> DiskLayout {
+        toml::from_str(&format!(
+            "disk = \"nvme0n1\"\n[esp]\n{}\n{}",
+            esp,
+            r#"
+            [[partition]]
+            size = "rest"
+            type = "8300"
+            label = "ROOT"
+            filesystem = "btrfs"
+            mount_point = "/"
+            "#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn detects_esps_and_probes_the_unmounted_ones() {
+        let runner = runner();
+        let esps = detect_esps(&runner, "/dev/nvme0n1").unwrap();
+
+        assert_eq!(esps.len(), 2);
+        assert_eq!(esps[0].device, "/dev/nvme0n1p1");
+        assert_eq!(esps[0].number, 1);
+        assert_eq!(esps[0].free_bytes, Some(104_857_600));
+        assert_eq!(esps[1].size_bytes, 1_073_741_824);
+        assert_eq!(esps[1].free_bytes, Some(943_718_400));
+
+        // Only the unmounted ESP is mounted (read-only) to measure it
+        let mounts = runner.calls_to("mount");
+        assert_eq!(mounts.len(), 1);
+        assert!(mounts[0].starts_with("mount -o ro /dev/nvme0n1p2 "));
+        assert_eq!(runner.calls_to("umount").len(), 1);
+    }
+
+    #[test]
+    fn layout_names_the_esp_to_reuse_like_a_disk() {
+        for reuse in ["/dev/nvme0n1p2", "nvme0n1p2", " nvme0n1p2 "] {
+            let layout = layout(&format!("reuse = \"{}\"", reuse));
+            let choice = choose_esp(&runner(), "/dev/nvme0n1", Some(&layout), 512).unwrap();
+
+            assert_eq!(choice.reuse.unwrap().device, "/dev/nvme0n1p2");
+            assert_eq!(choice.xbootldr_size_mb, None);
+        }
+
+        let layout = layout("reuse = \"/dev/nvme0n1p3\"");
+        assert!(choose_esp(&runner(), "/dev/nvme0n1", Some(&layout), 512).is_err());
+    }
+
+    #[test]
+    fn small_reused_esp_gets_an_xbootldr_unless_the_layout_declines() {
+        let layout_with = layout("reuse = \"nvme0n1p1\"");
+        let choice = choose_esp(&runner(), "/dev/nvme0n1", Some(&layout_with), 512).unwrap();
+        assert_eq!(
+            choice.xbootldr_size_mb,
+            Some(EspConfig::DEFAULT_XBOOTLDR_SIZE_MB)
+        );
+
+        let layout_without = layout("reuse = \"nvme0n1p1\"\nxbootldr_size_mb = 0");
+        let choice = choose_esp(&runner(), "/dev/nvme0n1", Some(&layout_without), 512).unwrap();
+        assert_eq!(choice.reuse.unwrap().device, "/dev/nvme0n1p1");
+        assert_eq!(choice.xbootldr_size_mb, None);
+
+        // The layout's own minimum counts, not the --esp-min-size default
+        let layout_lower = layout("reuse = \"nvme0n1p1\"\nmin_size_mb = 256");
+        let choice = choose_esp(&runner(), "/dev/nvme0n1", Some(&layout_lower), 512).unwrap();
+        assert_eq!(choice.xbootldr_size_mb, None);
+    }
+
+    #[test]
+    fn layout_without_reuse_creates_a_new_esp() {
+        let choice = choose_esp(&runner(), "/dev/nvme0n1", Some(&layout("")), 512).unwrap();
+
+        assert!(choice.reuse.is_none());
+        assert_eq!(choice.xbootldr_size_mb, None);
+    }
+}
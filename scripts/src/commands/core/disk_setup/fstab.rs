@@ -0,0 +1,356 @@
+use anyhow::{Context, Result};
+use dialoguer::Confirm;
+use std::collections::HashMap;
+use std::process::Command;
+
+use crate::colors;
+use crate::commands::core::disk_setup::structs::CreatedPartitions;
+use crate::commands::core::disk_setup::structs::DeviceIds;
+use crate::commands::core::disk_setup::structs::MountEntry;
+use crate::commands::core::disk_setup::structs::MountTree;
+use crate::commands::core::disk_setup::structs::SwapSetup;
+use crate::commands::core::disk_setup::subvolumes;
+use crate::helpers::CommandRunner;
+use crate::helpers::run_out;
+
+/// sd-encrypt reads this in the initramfs, so the root container is listed here
+const CRYPTTAB_INITRAMFS: &str = "/etc/crypttab.initramfs";
+
+// ---------------------------------------------------------
+// /etc/fstab from the mounts made under the target
+// ---------------------------------------------------------
+pub fn write_fstab(
+    runner: &dyn CommandRunner,
+    tree: &MountTree,
+    swap: Option<&SwapSetup>,
+    assume_yes: bool,
+) -> Result<String> {
+    say!("{}", colors::header("Writing fstab"));
+
+    // Subvolumes share their device, blkid runs once per device
+    let mut specs = HashMap::new();
+    for m in &tree.mounts {
+        if !specs.contains_key(&m.source) {
+            specs.insert(m.source.clone(), device_spec(runner, &m.source)?);
+        }
+    }
+
+    let path = subvolumes::target_path(&tree.root, "/etc/fstab");
+    install_file(
+        runner,
+        &path,
+        &render_fstab(&tree.mounts, &specs, swap),
+        assume_yes,
+    )?;
+    say!();
+
+    Ok(path)
+}
+
+pub fn render_fstab(
+    mounts: &[MountEntry],
+    specs: &HashMap<String, String>,
+    swap: Option<&SwapSetup>,
+) -> String {
+    let mut out = String::from(
+        "# Generated by sharch disk-setup\n#\n# <file system>\t<dir>\t<type>\t<options>\t<dump>\t<pass>\n",
+    );
+
+    for m in mounts {
+        let spec = specs.get(&m.source).unwrap_or(&m.source);
+        out.push_str(&format!(
+            "\n# {}\n{}\t{}\t{}\t{}\t0 {}\n",
+            m.source,
+            spec,
+            m.mount_point,
+            m.fstype,
+            m.options,
+            fsck_pass(&m.fstype, &m.mount_point)
+        ));
+    }
+
+    if let Some(line) = swap.and_then(|s| s.fstab_line()) {
+        out.push_str(&format!("\n# swap\n{}\n", line));
+    }
+
+    out
+}
+
+// fsck.btrfs, fsck.xfs and bcachefs' checker do nothing at boot
+fn fsck_pass(fstype: &str, mount_point: &str) -> u8 {
+    match (fstype, mount_point) {
+        ("btrfs" | "xfs" | "bcachefs", _) => 0,
+        (_, "/") => 1,
+        _ => 2,
+    }
+}
+
+// ---------------------------------------------------------
+// crypttab for the LUKS containers that were opened
+// ---------------------------------------------------------
+pub fn write_crypttab(
+    runner: &dyn CommandRunner,
+    partitions: &CreatedPartitions,
+    tree: &MountTree,
+    assume_yes: bool,
+) -> Result<Option<String>> {
+    // The LUKS UUID is only known after luksFormat, i.e. not in a dry run
+    let volumes: Vec<(String, String)> = partitions
+        .partitions
+        .iter()
+        .filter_map(|p| {
+            let vol = p.encryption.as_ref()?;
+            let device = if vol.luks_uuid.is_empty() {
+                p.device.clone()
+            } else {
+                format!("UUID={}", vol.luks_uuid)
+            };
+            Some((vol.mapper_name.clone(), device))
+        })
+        .collect();
+
+    if volumes.is_empty() {
+        return Ok(None);
+    }
+
+    say!("{}", colors::header("Writing crypttab"));
+
+    let path = subvolumes::target_path(&tree.root, CRYPTTAB_INITRAMFS);
+    install_file(runner, &path, &render_crypttab(&volumes), assume_yes)?;
+    say!();
+
+    Ok(Some(path))
+}
+
+pub fn render_crypttab(volumes: &[(String, String)]) -> String {
+    let mut out = String::from(
+        "# Generated by sharch disk-setup, unlocked in the initramfs (sd-encrypt)\n#\n# <name>\t<device>\t<password>\t<options>\n",
+    );
+
+    for (name, device) in volumes {
+        out.push_str(&format!("{}\t{}\tnone\tluks\n", name, device));
+    }
+
+    out
+}
+
+// ---------------------------------------------------------
+// UUID= / PARTUUID= for a device
+// ---------------------------------------------------------
+fn device_spec(runner: &dyn CommandRunner, device: &str) -> Result<String> {
+    // Nothing was formatted in a dry run, there is nothing to probe
+    if runner.is_dry_run() {
+        return Ok(device.to_string());
+    }
+
+    let ids = device_ids(runner, device)?;
+    ids.fstab_spec()
+        .with_context(|| format!("blkid found neither UUID nor PARTUUID on {}", device))
+}
+
+pub fn device_ids(runner: &dyn CommandRunner, device: &str) -> Result<DeviceIds> {
+    let out = run_out(runner, Command::new("blkid").args(["-o", "export", device]))
+        .with_context(|| format!("Failed to read identifiers of {}", device))?;
+
+    Ok(parse_blkid_export(&out))
+}
+
+// KEY=value lines, one block per device
+fn parse_blkid_export(out: &str) -> DeviceIds {
+    let mut ids = DeviceIds::default();
+
+    for line in out.lines() {
+        match line.split_once('=') {
+            Some(("UUID", value)) => ids.uuid = Some(value.to_string()),
+            Some(("PARTUUID", value)) => ids.partuuid = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    ids
+}
+
+// ---------------------------------------------------------
+// Write a file, showing a diff when one is already there
+// ---------------------------------------------------------
+fn install_file(
+    runner: &dyn CommandRunner,
+    path: &str,
+    content: &str,
+    assume_yes: bool,
+) -> Result<()> {
+    let old = std::fs::read_to_string(path).ok();
+
+    match &old {
+        Some(old) if old == content => {
+            say!("{}", colors::info(&format!("{} is up to date", path)));
+            return Ok(());
+        }
+        Some(old) => {
+            say!("{}", colors::warn(&format!("{} already exists:", path)));
+            print_diff(old, content);
+        }
+        None => {}
+    }
+
+    if runner.is_dry_run() {
+        match old {
+            Some(_) => say!("> write {}", path),
+            None => say!("> write {}:\n{}", path, content),
+        }
+        return Ok(());
+    }
+
+    let replace = old.is_none()
+        || assume_yes
+        || Confirm::new()
+            .with_prompt(colors::info(&format!("Replace {}?", path)))
+            .default(true)
+            .interact()
+            .context("Failed to get confirmation")?;
+
+    if !replace {
+        say!("{}", colors::warn(&format!("Kept the existing {}", path)));
+        return Ok(());
+    }
+
+    if let Some(dir) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    std::fs::write(path, content).with_context(|| format!("Failed to write {}", path))?;
+
+    say!("{}", colors::success(&format!("✓ {} written", path)));
+
+    Ok(())
+}
+
+fn print_diff(old: &str, new: &str) {
+    for (tag, line) in line_diff(old, new) {
+        match tag {
+            '-' => say!("{}", colors::error(&format!("- {}", line))),
+            '+' => say!("{}", colors::success(&format!("+ {}", line))),
+            _ => say!("  {}", line),
+        }
+    }
+    say!();
+}
+
+// Line diff via longest common subsequence, fine for files this small
+pub fn line_diff<'a>(old: &'a str, new: &'a str) -> Vec<(char, &'a str)> {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+
+    // lcs[i][j]: common lines of a[i..] and b[j..]
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut diff = Vec::new();
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            diff.push((' ', a[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            diff.push(('-', a[i]));
+            i += 1;
+        } else {
+            diff.push(('+', b[j]));
+            j += 1;
+        }
+    }
+    diff.extend(a[i..].iter().map(|line| ('-', *line)));
+    diff.extend(b[j..].iter().map(|line| ('+', *line)));
+
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::core::disk_setup::structs::SwapKind;
+
+    fn mount(source: &str, mount_point: &str, fstype: &str, options: &str) -> MountEntry {
+        MountEntry {
+            source: source.to_string(),
+            mount_point: mount_point.to_string(),
+            fstype: fstype.to_string(),
+            options: options.to_string(),
+        }
+    }
+
+    #[test]
+    fn fstab_uses_uuids_and_the_mount_options() {
+        let mounts = [
+            mount("/dev/mapper/cryptroot", "/", "btrfs", "noatime,subvol=@"),
+            mount(
+                "/dev/mapper/cryptroot",
+                "/home",
+                "btrfs",
+                "noatime,subvol=@home",
+            ),
+            mount("/dev/nvme0n1p1", "/boot", "vfat", "umask=0077"),
+        ];
+        let specs = HashMap::from([
+            ("/dev/mapper/cryptroot".to_string(), "UUID=0f2c".to_string()),
+            ("/dev/nvme0n1p1".to_string(), "UUID=A1B2-C3D4".to_string()),
+        ]);
+        let swap = SwapSetup {
+            kind: SwapKind::File,
+            source: Some("/swap/swapfile".to_string()),
+        };
+
+        let fstab = render_fstab(&mounts, &specs, Some(&swap));
+        let entries: Vec<&str> = fstab
+            .lines()
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .collect();
+
+        assert_eq!(
+            entries,
+            [
+                "UUID=0f2c\t/\tbtrfs\tnoatime,subvol=@\t0 0",
+                "UUID=0f2c\t/home\tbtrfs\tnoatime,subvol=@home\t0 0",
+                "UUID=A1B2-C3D4\t/boot\tvfat\tumask=0077\t0 2",
+                "/swap/swapfile none swap defaults 0 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn blkid_export_prefers_the_filesystem_uuid() {
+        let ids = parse_blkid_export(
+            "DEVNAME=/dev/sda2\nUUID=5e1f\nBLOCK_SIZE=4096\nTYPE=ext4\nPARTUUID=9a8b-02\n",
+        );
+        assert_eq!(ids.fstab_spec().as_deref(), Some("UUID=5e1f"));
+
+        let raw = parse_blkid_export("DEVNAME=/dev/sda1\nPARTUUID=9a8b-01\n");
+        assert_eq!(raw.fstab_spec().as_deref(), Some("PARTUUID=9a8b-01"));
+    }
+
+    #[test]
+    fn diff_keeps_common_lines() {
+        let diff = line_diff(
+            "# static\nUUID=old / ext4 rw 0 1\n",
+            "# static\nUUID=new / ext4 rw 0 1\n",
+        );
+
+        assert_eq!(
+            diff,
+            [
+                (' ', "# static"),
+                ('-', "UUID=old / ext4 rw 0 1"),
+                ('+', "UUID=new / ext4 rw 0 1"),
+            ]
+        );
+    }
+}
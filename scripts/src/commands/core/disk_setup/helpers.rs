@@ -2,20 +2,47 @@ use anyhow::{Context, Result, bail};
 use std::process::Command;
 
 use crate::colors;
+use crate::commands::core::disk_setup::boot;
+use crate::commands::core::disk_setup::devices;
+use crate::commands::core::disk_setup::esp;
+use crate::commands::core::disk_setup::preflight;
+use crate::commands::core::disk_setup::size;
+use crate::commands::core::disk_setup::structs::BootMode;
+use crate::commands::core::disk_setup::structs::CreatedPartition;
 use crate::commands::core::disk_setup::structs::CreatedPartitions;
 use crate::commands::core::disk_setup::structs::Disk;
+use crate::commands::core::disk_setup::structs::DiskChild;
+use crate::commands::core::disk_setup::structs::DiskRole;
+use crate::commands::core::disk_setup::structs::EspChoice;
+use crate::commands::core::disk_setup::structs::ExistingPartition;
+use crate::commands::core::disk_setup::structs::Filesystem;
 use crate::commands::core::disk_setup::structs::FreeRegion;
+use crate::commands::core::disk_setup::structs::Geometry;
 use crate::commands::core::disk_setup::structs::LsblkNode;
 use crate::commands::core::disk_setup::structs::PartitionPlan;
-use crate::helpers::run_out;
-use dialoguer::{Confirm, Input};
+use crate::commands::core::disk_setup::structs::PartitionTable;
+use crate::commands::core::disk_setup::structs::PlannedPartition;
+use crate::commands::core::disk_setup::structs::SectorRange;
+use crate::commands::core::disk_setup::structs::SizeSpec;
+use crate::commands::core::disk_setup::structs::SwapKind;
+use crate::commands::core::disk_setup::swap;
+use crate::helpers::CommandRunner;
+use crate::helpers::{run_out, run_show, run_status};
+use dialoguer::{Confirm, Input, Select};
 
 // ---------------------------------------------------------
 // List disks
 // ---------------------------------------------------------
-pub fn list_block_disks() -> Result<Vec<Disk>> {
-    let out = run_out(Command::new("lsblk").args(["-J", "-o", "NAME,SIZE,TYPE,MODEL"]))
-        .context("Failed running lsblk")?;
+pub fn list_block_disks(runner: &dyn CommandRunner) -> Result<Vec<Disk>> {
+    let out = run_out(
+        runner,
+        Command::new("lsblk").args([
+            "-J",
+            "-o",
+            "NAME,SIZE,TYPE,MODEL,TRAN,ROTA,RM,SERIAL,WWN,PTTYPE,FSTYPE,LABEL,MOUNTPOINTS",
+        ]),
+    )
+    .context("Failed running lsblk")?;
 
     let root: serde_json::Value =
         serde_json::from_str(&out).context("Failed parsing lsblk JSON")?;
@@ -27,33 +54,82 @@ pub fn list_block_disks() -> Result<Vec<Disk>> {
             let node: LsblkNode =
                 serde_json::from_value(d.clone()).context("Failed parsing lsblk node")?;
 
-            if node.dev_type == "disk" {
-                disks.push(Disk {
-                    name: node.name.clone(),
-                    path: format!("/dev/{}", node.name),
-                    size: node.size.clone(),
-                    model: node.model.unwrap_or_default(),
-                });
+            // Optical drives and the like can't be partitioned
+            if node.dev_type != "disk" && node.dev_type != "loop" {
+                continue;
             }
+
+            let role = if node.dev_type == "loop" {
+                DiskRole::Loop
+            } else if node.name.starts_with("zram") {
+                DiskRole::Zram
+            } else if holds_boot_medium(&node) {
+                DiskRole::BootMedium
+            } else {
+                DiskRole::Regular
+            };
+
+            disks.push(Disk {
+                path: format!("/dev/{}", node.name),
+                by_id: devices::by_id_path(&node.name),
+                size: node.size.clone(),
+                model: node.model.clone().unwrap_or_default().trim().to_string(),
+                transport: node.tran.clone(),
+                rotational: node.rota,
+                removable: node.rm,
+                serial: node.serial.clone(),
+                wwn: node.wwn.clone(),
+                pttype: node.pttype.clone(),
+                role,
+                children: node.children.iter().map(disk_child).collect(),
+                name: node.name,
+            });
         }
     }
 
     Ok(disks)
 }
 
+// Anything below the disk mounted at `/`, the archiso medium or an ISO image
+fn holds_boot_medium(node: &LsblkNode) -> bool {
+    node.fstype.as_deref() == Some("iso9660")
+        || node
+            .mountpoints
+            .iter()
+            .flatten()
+            .any(|mp| preflight::is_boot_mount(mp))
+        || node.children.iter().any(holds_boot_medium)
+}
+
+fn disk_child(node: &LsblkNode) -> DiskChild {
+    DiskChild {
+        name: node.name.clone(),
+        size: node.size.clone(),
+        fstype: node.fstype.clone(),
+        label: node.label.clone(),
+        mountpoints: node.mountpoints.iter().flatten().cloned().collect(),
+        children: node.children.iter().map(disk_child).collect(),
+    }
+}
+
 // ---------------------------------------------------------
 // Disk selector
 // ---------------------------------------------------------
-pub fn select_disk_simple(disks: &[Disk]) -> Result<String> {
+pub fn select_disk_simple(all_disks: &[Disk], show_all: bool) -> Result<String> {
+    let (disks, hidden): (Vec<&Disk>, Vec<&Disk>) = all_disks
+        .iter()
+        .partition(|d| show_all || d.role == DiskRole::Regular);
+
     if disks.is_empty() {
-        bail!("No disks found on system.");
+        bail!("No disks found on system (pass --all-disks to include zram, loop and boot media).");
     }
 
-    println!("\n{}\n", colors::header("Available Disks"));
+    say!("\n{}\n", colors::header("Available Disks"));
 
     // Calculate column widths dynamically
     let max_path_len = disks.iter().map(|d| d.path.len()).max().unwrap_or(10);
     let max_size_len = disks.iter().map(|d| d.size.len()).max().unwrap_or(6);
+    let max_kind_len = disks.iter().map(|d| d.kind().len()).max().unwrap_or(4);
     let max_model_len = disks
         .iter()
         .map(|d| if d.model.is_empty() { 1 } else { d.model.len() })
@@ -64,39 +140,80 @@ pub fn select_disk_simple(disks: &[Disk]) -> Result<String> {
     let num_width = 3;
     let path_width = max_path_len.max(6);
     let size_width = max_size_len.max(4);
+    let kind_width = max_kind_len.max(4);
+    let table_width = 5;
     let model_width = max_model_len.max(5);
 
     // Table header
-    println!(
-        "  {:<num_w$}  {:<path_w$}  {:<size_w$}  {:<model_w$}",
+    say!(
+        "  {:<num_w$}  {:<path_w$}  {:<size_w$}  {:<kind_w$}  {:<table_w$}  {:<model_w$}  Serial",
         "#",
         "Device",
         "Size",
+        "Type",
+        "Table",
         "Model",
         num_w = num_width,
         path_w = path_width,
         size_w = size_width,
+        kind_w = kind_width,
+        table_w = table_width,
         model_w = model_width
     );
-    println!();
+    say!();
 
-    // Table rows
+    // Table rows, each followed by its partition tree
     for (i, d) in disks.iter().enumerate() {
         let model = if d.model.is_empty() { "-" } else { &d.model };
-        println!(
-            "  {:<num_w$}  {:<path_w$}  {:<size_w$}  {:<model_w$}",
+        let note = match d.role.note() {
+            Some(note) => colors::warn(&format!("  [{}]", note)),
+            None => String::new(),
+        };
+
+        say!(
+            "  {:<num_w$}  {:<path_w$}  {:<size_w$}  {:<kind_w$}  {:<table_w$}  {:<model_w$}  {}{}",
             i + 1,
             d.path,
             d.size,
+            d.kind(),
+            d.pttype.as_deref().unwrap_or("-"),
             model,
+            d.serial.as_deref().unwrap_or("-"),
+            note,
             num_w = num_width,
             path_w = path_width,
             size_w = size_width,
+            kind_w = kind_width,
+            table_w = table_width,
             model_w = model_width
         );
+
+        let indent = " ".repeat(num_width + 4);
+        print_disk_children(&d.children, &indent);
+
+        if let Some(by_id) = &d.by_id {
+            say!("{}{}", indent, colors::info(by_id));
+        } else if let Some(wwn) = &d.wwn {
+            say!("{}{}", indent, colors::info(&format!("wwn: {}", wwn)));
+        }
+    }
+
+    if !hidden.is_empty() {
+        let names: Vec<String> = hidden
+            .iter()
+            .map(|d| format!("{} ({})", d.name, d.role.note().unwrap_or("hidden")))
+            .collect();
+        say!();
+        say!(
+            "{}",
+            colors::info(&format!(
+                "Hidden: {}. Pass --all-disks to show them.",
+                names.join(", ")
+            ))
+        );
     }
 
-    println!();
+    say!();
 
     // Prompt for selection
     loop {
@@ -116,70 +233,103 @@ pub fn select_disk_simple(disks: &[Disk]) -> Result<String> {
         match input.trim().parse::<usize>() {
             Ok(n) if n >= 1 && n <= disks.len() => {
                 let chosen = &disks[n - 1].path;
-                println!("{}\n", colors::success(&format!("✓ Selected: {}", chosen)));
+                say!("{}\n", colors::success(&format!("✓ Selected: {}", chosen)));
                 return Ok(chosen.clone());
             }
             _ => {
-                println!("{}", colors::warn("Invalid selection. Try again."));
+                say!("{}", colors::warn("Invalid selection. Try again."));
             }
         }
     }
 }
 
+// ├─ nvme0n1p1  512M  vfat  EFI  /boot
+fn print_disk_children(children: &[DiskChild], prefix: &str) {
+    for (i, child) in children.iter().enumerate() {
+        let last = i + 1 == children.len();
+        let details: Vec<&str> = [child.fstype.as_deref(), child.label.as_deref()]
+            .into_iter()
+            .flatten()
+            .chain(child.mountpoints.iter().map(String::as_str))
+            .collect();
+
+        say!(
+            "{}{}{}  {}  {}",
+            prefix,
+            if last { "└─ " } else { "├─ " },
+            child.name,
+            child.size,
+            details.join("  ")
+        );
+
+        let nested = format!("{}{}", prefix, if last { "   " } else { "│  " });
+        print_disk_children(&child.children, &nested);
+    }
+}
+
 // ---------------------------------------------------------
 // Check if disk has a partition table
 // ---------------------------------------------------------
-pub fn check_partition_table(disk_path: &str) -> Result<bool> {
-    let result = Command::new("parted")
-        .args(["-s", disk_path, "print"])
-        .output()
-        .context("Failed to check partition table")?;
-
+pub fn check_partition_table(runner: &dyn CommandRunner, disk_path: &str) -> Result<bool> {
     // If exit code is 0, partition table exists
-    Ok(result.status.success())
+    run_status(
+        runner,
+        Command::new("parted").args(["-s", disk_path, "print"]),
+    )
+    .context("Failed to check partition table")
 }
 
 // ---------------------------------------------------------
-// Create GPT partition table
+// Create GPT (or msdos) partition table
 // ---------------------------------------------------------
-pub fn create_partition_table(disk_path: &str, dry_run: bool) -> Result<()> {
-    println!(
+pub fn create_partition_table(
+    runner: &dyn CommandRunner,
+    disk_path: &str,
+    assume_yes: bool,
+    table: PartitionTable,
+) -> Result<()> {
+    say!(
         "{}",
         colors::warn(&format!("Disk {} has no partition table", disk_path))
     );
 
-    let confirmed = Confirm::new()
-        .with_prompt(colors::info(
-            "Create a new GPT partition table? This will erase all data!",
-        ))
-        .default(false)
-        .interact()
-        .context("Failed to get confirmation")?;
+    let confirmed = assume_yes
+        || Confirm::new()
+            .with_prompt(colors::info(&format!(
+                "Create a new {} partition table? This will erase all data!",
+                table
+            )))
+            .default(false)
+            .interact()
+            .context("Failed to get confirmation")?;
 
     if !confirmed {
         bail!("User declined to create partition table");
     }
 
-    if dry_run {
-        println!(
+    if runner.is_dry_run() {
+        say!(
             "{}",
-            colors::info("[DRY RUN] Would create GPT partition table")
+            colors::info(&format!("[DRY RUN] Would create {} partition table", table))
         );
         return Ok(());
     }
 
-    println!("{}", colors::info("Creating GPT partition table..."));
-
-    let status = Command::new("parted")
-        .args(["-s", disk_path, "mklabel", "gpt"])
-        .status()
-        .context("Failed to create partition table")?;
+    say!(
+        "{}",
+        colors::info(&format!("Creating {} partition table...", table))
+    );
 
-    if !status.success() {
-        bail!("Failed to create GPT partition table");
-    }
+    run_show(
+        runner,
+        Command::new("parted").args(["-s", disk_path, "mklabel", table.parted_label()]),
+    )
+    .with_context(|| format!("Failed to create {} partition table", table))?;
 
-    println!("{}", colors::success("✓ GPT partition table created"));
+    say!(
+        "{}",
+        colors::success(&format!("✓ {} partition table created", table))
+    );
 
     Ok(())
 }
@@ -187,9 +337,12 @@ pub fn create_partition_table(disk_path: &str, dry_run: bool) -> Result<()> {
 // ---------------------------------------------------------
 // Get disk size in bytes
 // ---------------------------------------------------------
-pub fn get_disk_size(disk_path: &str) -> Result<u64> {
-    let out = run_out(Command::new("blockdev").args(["--getsize64", disk_path]))
-        .context("Failed to get disk size")?;
+pub fn get_disk_size(runner: &dyn CommandRunner, disk_path: &str) -> Result<u64> {
+    let out = run_out(
+        runner,
+        Command::new("blockdev").args(["--getsize64", disk_path]),
+    )
+    .context("Failed to get disk size")?;
 
     out.trim()
         .parse::<u64>()
@@ -199,29 +352,38 @@ pub fn get_disk_size(disk_path: &str) -> Result<u64> {
 // ---------------------------------------------------------
 // List free regions using parted
 // ---------------------------------------------------------
-pub fn list_free_regions(disk_path: &str) -> Result<Vec<FreeRegion>> {
-    let out =
-        run_out(Command::new("parted").args(["-s", "-m", disk_path, "unit", "B", "print", "free"]))
-            .context("Failed running parted")?;
+pub fn list_free_regions(runner: &dyn CommandRunner, disk_path: &str) -> Result<Vec<FreeRegion>> {
+    let out = run_out(
+        runner,
+        Command::new("parted").args(["-s", "-m", disk_path, "unit", "B", "print", "free"]),
+    )
+    .context("Failed running parted")?;
 
     let mut regions = Vec::new();
 
     for line in out.lines() {
-        let parts: Vec<&str> = line.split(':').collect();
+        // Machine-readable lines end with `;`
+        let parts: Vec<&str> = line.trim_end_matches(';').split(':').collect();
 
-        // Free space lines have "free" as the 5th field
+        // Free space lines: "1:<start>B:<end>B:<size>B:free"
         if parts.len() >= 5 && parts[4] == "free" {
-            let start = parts[0].trim_end_matches('B').to_string();
-            let end = parts[1].trim_end_matches('B').to_string();
-            let size = parts[2].trim_end_matches('B').to_string();
-
-            if let Ok(size_bytes) = size.parse::<u64>() {
+            let start = parts[1].trim_end_matches('B').to_string();
+            let end = parts[2].trim_end_matches('B').to_string();
+            let size = parts[3].trim_end_matches('B').to_string();
+
+            if let (Ok(start_bytes), Ok(end_bytes), Ok(size_bytes)) = (
+                start.parse::<u64>(),
+                end.parse::<u64>(),
+                size.parse::<u64>(),
+            ) {
                 // Only show regions larger than 1MB
                 if size_bytes > 1_048_576 {
                     regions.push(FreeRegion {
-                        start: format_bytes(start.parse().unwrap_or(0)),
-                        end: format_bytes(end.parse().unwrap_or(0)),
+                        start: format_bytes(start_bytes),
+                        end: format_bytes(end_bytes),
                         size: format_bytes(size_bytes),
+                        start_bytes,
+                        end_bytes,
                         size_bytes,
                     });
                 }
@@ -235,18 +397,22 @@ pub fn list_free_regions(disk_path: &str) -> Result<Vec<FreeRegion>> {
 // ---------------------------------------------------------
 // Display free regions or disk size
 // ---------------------------------------------------------
-pub fn display_free_regions(regions: &[FreeRegion], disk_path: &str) -> Result<()> {
+pub fn display_free_regions(
+    runner: &dyn CommandRunner,
+    regions: &[FreeRegion],
+    disk_path: &str,
+) -> Result<()> {
     if regions.is_empty() {
-        println!("{}", colors::warn("No free space regions found."));
+        say!("{}", colors::warn("No free space regions found."));
 
         // Show total disk size instead
-        match get_disk_size(disk_path) {
+        match get_disk_size(runner, disk_path) {
             Ok(size_bytes) => {
-                println!(
+                say!(
                     "{}",
                     colors::info(&format!("Total disk size: {}", format_bytes(size_bytes)))
                 );
-                println!(
+                say!(
                     "{}",
                     colors::info(&format!(
                         "Usable space: ~{}",
@@ -255,302 +421,590 @@ pub fn display_free_regions(regions: &[FreeRegion], disk_path: &str) -> Result<(
                 );
             }
             Err(_) => {
-                println!("{}", colors::warn("Could not determine disk size"));
+                say!("{}", colors::warn("Could not determine disk size"));
             }
         }
 
-        println!();
+        say!();
         return Ok(());
     }
 
-    println!("\n{}\n", colors::header("Free Space Regions"));
+    say!("\n{}\n", colors::header("Free Space Regions"));
 
     let max_start_len = regions.iter().map(|r| r.start.len()).max().unwrap_or(10);
     let max_end_len = regions.iter().map(|r| r.end.len()).max().unwrap_or(10);
     let max_size_len = regions.iter().map(|r| r.size.len()).max().unwrap_or(10);
 
+    let num_width = 3;
     let start_width = max_start_len.max(5);
     let end_width = max_end_len.max(3);
     let size_width = max_size_len.max(4);
 
-    println!(
-        "  {:<start_w$}  {:<end_w$}  {:<size_w$}",
+    say!(
+        "  {:<num_w$}  {:<start_w$}  {:<end_w$}  {:<size_w$}",
+        "#",
         "Start",
         "End",
         "Size",
+        num_w = num_width,
         start_w = start_width,
         end_w = end_width,
         size_w = size_width
     );
-    println!();
+    say!();
 
-    for r in regions {
-        println!(
-            "  {:<start_w$}  {:<end_w$}  {:<size_w$}",
+    for (i, r) in regions.iter().enumerate() {
+        say!(
+            "  {:<num_w$}  {:<start_w$}  {:<end_w$}  {:<size_w$}",
+            i + 1,
             r.start,
             r.end,
             r.size,
+            num_w = num_width,
             start_w = start_width,
             end_w = end_width,
             size_w = size_width
         );
     }
-    println!();
+    say!();
 
     Ok(())
 }
 
 // ---------------------------------------------------------
-// Get partition plan from user (with disk size fallback)
+// Select the free region to partition
 // ---------------------------------------------------------
-pub fn get_partition_plan(free_regions: &[FreeRegion], disk_path: &str) -> Result<PartitionPlan> {
-    let total_free_bytes = if free_regions.is_empty() {
-        // No free regions found, use total disk size minus GPT overhead
-        let disk_size = get_disk_size(disk_path)?;
+pub fn select_free_region(
+    runner: &dyn CommandRunner,
+    regions: &[FreeRegion],
+    disk_path: &str,
+    preselected: Option<usize>,
+    interactive: bool,
+) -> Result<FreeRegion> {
+    if regions.is_empty() {
+        // No free regions found (fresh disk), use the whole disk minus GPT overhead
+        let disk_size = get_disk_size(runner, disk_path)?;
         let gpt_overhead = 34_603_008; // ~33MB for GPT tables
 
         if disk_size <= gpt_overhead {
             bail!("Disk is too small for partitioning");
         }
 
-        disk_size - gpt_overhead
-    } else {
-        free_regions.iter().map(|r| r.size_bytes).sum()
+        // Leave the first MiB and the backup GPT at the end alone
+        let start_bytes = 1_048_576;
+        let end_bytes = disk_size - 1_048_576 - 1;
+
+        return Ok(FreeRegion {
+            start: format_bytes(start_bytes),
+            end: format_bytes(end_bytes),
+            size: format_bytes(disk_size - gpt_overhead),
+            start_bytes,
+            end_bytes,
+            size_bytes: disk_size - gpt_overhead,
+        });
+    }
+
+    let largest = regions
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, r)| r.size_bytes)
+        .map(|(i, _)| i + 1)
+        .unwrap_or(1);
+
+    let index = match preselected {
+        Some(n) if n >= 1 && n <= regions.len() => n,
+        Some(n) => bail!(
+            "Free region {} does not exist (disk has {} free regions)",
+            n,
+            regions.len()
+        ),
+        None if !interactive || regions.len() == 1 => largest,
+        None => loop {
+            let input: String = Input::new()
+                .with_prompt(colors::info(&format!(
+                    "Select free region to use (1-{})",
+                    regions.len()
+                )))
+                .default(largest.to_string())
+                .interact_text()
+                .context("Free region selection aborted")?;
+
+            match input.trim().parse::<usize>() {
+                Ok(n) if n >= 1 && n <= regions.len() => break n,
+                _ => say!("{}", colors::warn("Invalid selection. Try again.")),
+            }
+        },
     };
 
-    let total_free_mb = total_free_bytes / 1_048_576;
+    let region = regions[index - 1].clone();
+    say!(
+        "{}\n",
+        colors::success(&format!(
+            "✓ Using free region {}: {} - {} ({})",
+            index, region.start, region.end, region.size
+        ))
+    );
+
+    Ok(region)
+}
 
-    println!(
+// ---------------------------------------------------------
+// Get partition plan from user
+// ---------------------------------------------------------
+pub fn get_partition_plan(
+    region: &FreeRegion,
+    esp: &EspChoice,
+    boot_mode: BootMode,
+    root_fs: Option<Filesystem>,
+    swap_kind: Option<SwapKind>,
+) -> Result<PartitionPlan> {
+    say!(
         "{}",
         colors::info(&format!(
-            "Available space: {} MB ({:.2} GB)",
-            total_free_mb,
-            total_free_mb as f64 / 1024.0
+            "Available space: {}",
+            size::format_size(region.size_bytes)
         ))
     );
-    println!();
+    say!(
+        "{}",
+        colors::info("Sizes take 512M, 20GiB, 40% (of the region), rest, or -8G (leave 8G free)")
+    );
+    say!();
+
+    let boot_partition = match (boot_mode, &esp.reuse, esp.xbootldr_size_mb) {
+        // GRUB embeds itself in the bios_grub partition, msdos has the gap before sector 2048
+        (BootMode::BiosGpt, _, _) => Some(boot::bios_grub_partition()),
+        (BootMode::BiosMbr, _, _) => None,
+        // Reused ESP is big enough, nothing to create for /boot
+        (BootMode::Uefi, Some(_), None) => None,
+        (BootMode::Uefi, Some(_), Some(size_mb)) => Some(esp::xbootldr_partition(size_mb)?),
+        (BootMode::Uefi, None, _) => {
+            // Get EFI partition size
+            let efi_size: String = Input::new()
+                .with_prompt(colors::info("EFI partition size (recommended: 1G)"))
+                .default("1G".to_string())
+                .interact_text()
+                .context("Failed to get EFI size")?;
+
+            let efi_size: SizeSpec = efi_size.parse().context("Invalid EFI size")?;
+
+            if efi_size.fixed_bytes().is_none_or(|b| b < 512 * 1_048_576) {
+                bail!("EFI partition needs a fixed size of at least 512M");
+            }
+
+            Some(PlannedPartition {
+                size: efi_size,
+                type_code: "ef00".to_string(),
+                label: "EFI".to_string(),
+                filesystem: Filesystem::Vfat,
+                mount_point: Some("/boot".to_string()),
+                mount_options: None,
+            })
+        }
+    };
+
+    // Filesystem for the Linux partition (--filesystem skips the prompt)
+    let filesystem = match root_fs {
+        Some(fs) => fs,
+        None => {
+            let items: Vec<String> = Filesystem::ROOT_CHOICES
+                .iter()
+                .map(|fs| fs.to_string())
+                .collect();
+
+            let selection = Select::new()
+                .with_prompt(colors::info("Filesystem for the Linux partition"))
+                .items(&items)
+                .default(0)
+                .interact()
+                .context("Filesystem selection aborted")?;
+
+            Filesystem::ROOT_CHOICES[selection]
+        }
+    };
+
+    // Swap strategy (--swap skips the prompt, the size is still asked)
+    let swap = swap::choose_swap(filesystem, swap_kind)?;
+    let swap_partition = match &swap {
+        Some(config) if config.kind == SwapKind::Partition => {
+            let size_mb = match config.size_mb {
+                Some(mb) => mb,
+                None => swap::ram_mb()?,
+            };
+            Some(swap::swap_partition(size_mb)?)
+        }
+        _ => None,
+    };
 
-    // Get EFI partition size
-    let efi_size: String = Input::new()
-        .with_prompt(colors::info("EFI partition size in MB (recommended: 1024)"))
-        .default("1024".to_string())
+    // Get Linux partition size (whether it fits is checked by size::solve)
+    let linux_size: String = Input::new()
+        .with_prompt(colors::info("Linux partition size"))
+        .default("rest".to_string())
         .interact_text()
-        .context("Failed to get EFI size")?;
+        .context("Failed to get Linux partition size")?;
+
+    let linux_size: SizeSpec = linux_size.parse().context("Invalid Linux partition size")?;
 
-    let efi_size_mb: u64 = efi_size
-        .trim()
-        .parse()
-        .context("Invalid EFI size - must be a number")?;
+    let mut partitions: Vec<PlannedPartition> = boot_partition.into_iter().collect();
+    partitions.extend(swap_partition);
+    partitions.push(PlannedPartition {
+        size: linux_size,
+        type_code: "8300".to_string(),
+        label: "ROOT".to_string(),
+        filesystem,
+        mount_point: Some("/".to_string()),
+        mount_options: None,
+    });
 
-    if efi_size_mb < 512 {
-        bail!("EFI partition must be at least 512 MB");
+    Ok(PartitionPlan {
+        partitions,
+        reuse_esp: esp.reuse.clone(),
+        swap,
+        // LVM volumes only come from a layout's `[lvm]` section
+        lvm: None,
+        boot_mode,
+    })
+}
+
+// ---------------------------------------------------------
+// Print partition plan
+// ---------------------------------------------------------
+pub fn print_partition_plan(plan: &PartitionPlan, ranges: &[SectorRange], geometry: Geometry) {
+    say!();
+    say!("{}", colors::success("Partition Plan:"));
+
+    if let Some(esp) = &plan.reuse_esp {
+        say!(
+            "  Reusing ESP {} ({}, not formatted)",
+            esp.device,
+            size::format_size(esp.size_bytes)
+        );
     }
 
-    if efi_size_mb > total_free_mb {
-        bail!("EFI partition size exceeds available space");
+    if let Some(swap) = &plan.swap {
+        let size = match swap.size_mb {
+            Some(mb) => {
+                size::mib_to_bytes(mb).map_or_else(|_| format!("{} MB", mb), size::format_size)
+            }
+            None => "default size".to_string(),
+        };
+        say!("  Swap: {:?} ({})", swap.kind, size);
     }
 
-    // Get Linux partition size
-    let remaining_mb = total_free_mb - efi_size_mb;
+    let label_width = plan
+        .partitions
+        .iter()
+        .map(|p| p.label.len())
+        .max()
+        .unwrap_or(5)
+        .max(5);
+
+    for (part, range) in plan.partitions.iter().zip(ranges) {
+        // What was asked for and what it comes out as after alignment
+        let actual = size::format_size(range.bytes(geometry));
+        let size = match part.size {
+            SizeSpec::Bytes(_) => actual,
+            spec => format!("{} = {}", spec, actual),
+        };
+        let mount = match (&part.mount_point, &part.mount_options) {
+            (Some(mp), Some(opts)) => format!(" -> {} [{}]", mp, opts),
+            (Some(mp), None) => format!(" -> {}", mp),
+            _ => String::new(),
+        };
 
-    println!(
-        "{}",
-        colors::info(&format!(
-            "Remaining space: {} MB ({:.2} GB)",
-            remaining_mb,
-            remaining_mb as f64 / 1024.0
-        ))
-    );
+        say!(
+            "  {:<label_w$}  {}  {} ({}){}",
+            part.label,
+            size,
+            part.type_code,
+            part.filesystem,
+            mount,
+            label_w = label_width
+        );
+    }
+    say!();
+}
 
-    let linux_size: String = Input::new()
-        .with_prompt(colors::info(&format!(
-            "Linux partition size in MB (max: {})",
-            remaining_mb
-        )))
-        .default(remaining_mb.to_string())
-        .interact_text()
-        .context("Failed to get Linux partition size")?;
+// ---------------------------------------------------------
+// Existing partitions (so Windows partitions are left alone)
+// ---------------------------------------------------------
+pub fn list_partitions(
+    runner: &dyn CommandRunner,
+    disk_path: &str,
+) -> Result<Vec<ExistingPartition>> {
+    let out = run_out(
+        runner,
+        Command::new("parted").args(["-s", "-m", disk_path, "unit", "B", "print"]),
+    )
+    .context("Failed running parted")?;
+
+    // "3:<start>B:<end>B:<size>B:<fs>:<name>:<flags>;", skip "BYT;" and the disk line
+    let mut partitions = Vec::new();
+    for line in out.lines() {
+        let parts: Vec<&str> = line.trim_end_matches(';').split(':').collect();
+        let Some(Ok(number)) = parts.first().map(|n| n.parse::<u32>()) else {
+            continue;
+        };
+        let bytes = |i: usize| -> Option<u64> { parts.get(i)?.trim_end_matches('B').parse().ok() };
+
+        if let (Some(start_bytes), Some(end_bytes), Some(size_bytes)) =
+            (bytes(1), bytes(2), bytes(3))
+        {
+            partitions.push(ExistingPartition {
+                number,
+                start_bytes,
+                end_bytes,
+                size_bytes,
+                filesystem: parts.get(4).unwrap_or(&"").to_string(),
+                name: parts.get(5).unwrap_or(&"").to_string(),
+            });
+        }
+    }
 
-    let linux_size_mb: u64 = linux_size
-        .trim()
-        .parse()
-        .context("Invalid Linux size - must be a number")?;
+    Ok(partitions)
+}
 
-    if linux_size_mb > remaining_mb {
-        bail!("Linux partition size exceeds remaining space");
+// Lowest unused partition numbers, like sgdisk and parted would pick
+pub fn new_partition_numbers(
+    existing_numbers: &[u32],
+    count: usize,
+    table: PartitionTable,
+) -> Result<Vec<u32>> {
+    let numbers: Vec<u32> = (1..=table.max_partitions())
+        .filter(|n| !existing_numbers.contains(n))
+        .take(count)
+        .collect();
+
+    if numbers.len() < count {
+        bail!(
+            "{} partition table is full ({} entries)",
+            table,
+            table.max_partitions()
+        );
     }
 
-    println!();
-    println!("{}", colors::success("Partition Plan:"));
-    println!(
-        "  EFI:   {} MB ({:.2} GB)",
-        efi_size_mb,
-        efi_size_mb as f64 / 1024.0
-    );
-    println!(
-        "  Linux: {} MB ({:.2} GB)",
-        linux_size_mb,
-        linux_size_mb as f64 / 1024.0
-    );
-    println!();
+    Ok(numbers)
+}
 
-    Ok(PartitionPlan {
-        efi_size_mb,
-        linux_size_mb,
+// ---------------------------------------------------------
+// Logical and physical sector size in bytes
+// ---------------------------------------------------------
+pub fn get_geometry(runner: &dyn CommandRunner, disk_path: &str) -> Result<Geometry> {
+    let query = |flag: &str| -> Result<u64> {
+        run_out(runner, Command::new("blockdev").args([flag, disk_path]))
+            .with_context(|| format!("Failed to get sector size (blockdev {})", flag))?
+            .trim()
+            .parse::<u64>()
+            .context("Failed to parse sector size")
+    };
+
+    Ok(Geometry {
+        logical: query("--getss")?,
+        physical: query("--getpbsz")?,
     })
 }
 
 // ---------------------------------------------------------
-// Create partitions using parted
+// Create partitions inside the chosen free region using sgdisk
 // ---------------------------------------------------------
+// `ranges` and `numbers` are the placement the user confirmed, never re-solved here
 pub fn create_partitions(
+    runner: &dyn CommandRunner,
     disk_path: &str,
     plan: &PartitionPlan,
-    dry_run: bool,
+    ranges: &[SectorRange],
+    numbers: &[u32],
 ) -> Result<CreatedPartitions> {
-    use crate::helpers::run_show;
-    use std::process::Command;
-
-    println!("\n{}", colors::header("Creating Partitions (using sgdisk)"));
-
-    // Calculate partition boundaries (for display only)
-    let efi_start = 1; // 1 MiB (informational)
-    let efi_end = efi_start + plan.efi_size_mb;
-    let linux_start = efi_end;
-    let linux_end = linux_start + plan.linux_size_mb;
-
-    if dry_run {
-        println!("{}", colors::info("[DRY RUN] Would create:"));
-        println!(
-            "  EFI partition: {}MiB - {}MiB (size: {}MiB)",
-            efi_start, efi_end, plan.efi_size_mb
-        );
-        println!(
-            "  Linux partition: {}MiB - {}MiB (size: {}MiB)",
-            linux_start, linux_end, plan.linux_size_mb
-        );
+    let tool = match plan.boot_mode.table() {
+        PartitionTable::Gpt => "sgdisk",
+        PartitionTable::Msdos => "parted",
+    };
+    say!(
+        "\n{}",
+        colors::header(&format!("Creating Partitions (using {})", tool))
+    );
 
-        // Return fake partition names for dry run (respect nvme naming)
-        let (efi_fake, linux_fake) = if disk_path.contains("nvme") || disk_path.contains("mmcblk") {
-            (format!("{}p1", disk_path), format!("{}p2", disk_path))
-        } else {
-            (format!("{}1", disk_path), format!("{}2", disk_path))
-        };
+    let mut created = write_partition_entries(runner, disk_path, plan, ranges, numbers)?;
 
+    if runner.is_dry_run() {
         return Ok(CreatedPartitions {
-            efi_partition: efi_fake,
-            linux_partition: linux_fake,
+            partitions: created,
         });
     }
 
-    // Ensure sgdisk exists? (optional) - you may prefer calling ensure_tool_exists earlier
-    // Create EFI partition: number 1, size +{efi_size_mb}M, type ef00 (EFI System)
-    println!("{}", colors::info("Creating EFI partition (sgdisk)..."));
-    // sgdisk -n 1:0:+{size}M -t 1:ef00 <disk>
-    let mut cmd = Command::new("sgdisk");
-    cmd.args([
-        "-n",
-        &format!("1:0:+{}M", plan.efi_size_mb),
-        "-t",
-        "1:ef00",
-        disk_path,
-    ]);
+    say!(
+        "{}",
+        colors::success(&format!("✓ Partitions created with {}", tool))
+    );
 
-    // run_show prints the command and respects dry_run (we are not in dry_run here, but keep consistency)
-    run_show(&mut cmd, dry_run).context("Failed to run sgdisk to create EFI partition")?;
+    // Tell the kernel about the new table, then wait for the device nodes
+    devices::reread_partitions(runner, disk_path);
 
-    // Create Linux partition: number 2, use remaining space (0 means rest), type 8300
-    println!("{}", colors::info("Creating Linux partition (sgdisk)..."));
-    // sgdisk -n 2:0:0 -t 2:8300 <disk>
-    let mut cmd2 = Command::new("sgdisk");
-    cmd2.args(["-n", "2:0:0", "-t", "2:8300", disk_path]);
+    say!();
+    for part in &mut created {
+        if !part.reused {
+            part.device = devices::wait_for_partition(runner, disk_path, part.number)?;
+        }
 
-    run_show(&mut cmd2, dry_run).context("Failed to run sgdisk to create Linux partition")?;
+        say!(
+            "{}",
+            colors::info(&format!(
+                "{} partition: {} (#{})",
+                part.spec.label, part.device, part.number
+            ))
+        );
+    }
 
-    println!("{}", colors::success("✓ Partitions created with sgdisk"));
+    Ok(CreatedPartitions {
+        partitions: created,
+    })
+}
 
-    // Determine partition naming scheme (sda1/sda2 vs nvme0n1p1/nvme0n1p2)
-    let (efi_part, linux_part) = if disk_path.contains("nvme") || disk_path.contains("mmcblk") {
-        (format!("{}p1", disk_path), format!("{}p2", disk_path))
-    } else {
-        (format!("{}1", disk_path), format!("{}2", disk_path))
-    };
+// One sgdisk (or parted) call per planned partition, device paths are only predicted
+pub fn write_partition_entries(
+    runner: &dyn CommandRunner,
+    disk_path: &str,
+    plan: &PartitionPlan,
+    ranges: &[SectorRange],
+    numbers: &[u32],
+) -> Result<Vec<CreatedPartition>> {
+    if ranges.len() != plan.partitions.len() || numbers.len() != plan.partitions.len() {
+        bail!(
+            "Placement does not match the plan ({} partitions, {} ranges, {} numbers)",
+            plan.partitions.len(),
+            ranges.len(),
+            numbers.len()
+        );
+    }
 
-    // Give kernel a moment to see new partition table (and tell it explicitly)
-    std::thread::sleep(std::time::Duration::from_millis(500));
-    let _ = Command::new("partprobe").arg(disk_path).status();
-    // optionally run `udevadm settle` if you want to be extra sure:
-    let _ = Command::new("udevadm").args(["settle"]).status();
+    let table = plan.boot_mode.table();
+    let boot_index = boot::mbr_boot_index(plan);
+
+    let mut created = Vec::new();
+
+    for (i, ((part, range), &number)) in plan.partitions.iter().zip(ranges).zip(numbers).enumerate()
+    {
+        let (start, end) = (range.start, range.end);
+
+        let mut cmd = match table {
+            PartitionTable::Gpt => {
+                // sgdisk -n {n}:{start}:{end} -t {n}:{type} -c {n}:{label} <disk>
+                let mut cmd = Command::new("sgdisk");
+                cmd.args([
+                    "-n",
+                    &format!("{}:{}:{}", number, start, end),
+                    "-t",
+                    &format!("{}:{}", number, part.type_code),
+                    "-c",
+                    &format!("{}:{}", number, part.label),
+                    disk_path,
+                ]);
+                cmd
+            }
+            // msdos has no partition names, the filesystem label is all there is
+            PartitionTable::Msdos => {
+                let mut cmd = Command::new("parted");
+                cmd.args(boot::mbr_partition_args(
+                    disk_path,
+                    number,
+                    part,
+                    range,
+                    boot_index == Some(i),
+                ));
+                cmd
+            }
+        };
 
-    println!();
-    println!("{}", colors::info(&format!("EFI partition: {}", efi_part)));
-    println!(
-        "{}",
-        colors::info(&format!("Linux partition: {}", linux_part))
-    );
+        let tool = cmd.get_program().to_string_lossy().to_string();
+        say!(
+            "{}",
+            colors::info(&format!("Creating {} partition ({})...", part.label, tool))
+        );
 
-    Ok(CreatedPartitions {
-        efi_partition: efi_part,
-        linux_partition: linux_part,
-    })
+        run_show(runner, &mut cmd).with_context(|| {
+            format!("Failed to run {} to create {} partition", tool, part.label)
+        })?;
+
+        created.push(CreatedPartition {
+            number,
+            // Predicted for now, read back from the kernel after partprobe
+            device: devices::predicted_partition_path(disk_path, number),
+            spec: part.clone(),
+            encryption: None,
+            reused: false,
+            raid: None,
+        });
+    }
+
+    // The reused ESP is mounted like a new one, it just never gets formatted
+    created.extend(esp::reused_esp_partition(plan));
+
+    Ok(created)
 }
 
 // ---------------------------------------------------------
 // Format partitions
 // ---------------------------------------------------------
-pub fn format_partitions(partitions: &CreatedPartitions, dry_run: bool) -> Result<()> {
-    println!("{}", colors::header("Formatting Partitions"));
+pub fn format_partitions(runner: &dyn CommandRunner, partitions: &CreatedPartitions) -> Result<()> {
+    say!("{}", colors::header("Formatting Partitions"));
+
+    // Reused partitions keep their data, LVM physical volumes were set up by pvcreate,
+    // bios_grub partitions stay raw
+    let to_format = || {
+        partitions.partitions.iter().filter_map(|p| {
+            let tool = p.spec.filesystem.mkfs_tool()?;
+            (!p.reused && p.spec.filesystem != Filesystem::Lvm).then_some((p, tool))
+        })
+    };
 
-    if dry_run {
-        println!("{}", colors::info("[DRY RUN] Would format:"));
-        println!("  {} as FAT32 (EFI)", partitions.efi_partition);
-        println!("  {} as Btrfs (Linux)", partitions.linux_partition);
+    if runner.is_dry_run() {
+        say!("{}", colors::info("[DRY RUN] Would format:"));
+        for (part, _) in to_format() {
+            say!(
+                "  {} as {} ({})",
+                part.fs_device(),
+                part.spec.filesystem,
+                part.spec.label
+            );
+            if let Some(raid) = &part.raid {
+                say!(
+                    "    {} with {}",
+                    raid.profile.as_str(),
+                    raid.devices.join(", ")
+                );
+            }
+        }
         return Ok(());
     }
 
-    // Format EFI partition as FAT32
-    println!(
-        "{}",
-        colors::info(&format!(
-            "Formatting {} as FAT32...",
-            partitions.efi_partition
-        ))
-    );
-
-    let status = Command::new("mkfs.fat")
-        .args(["-F", "32", "-n", "EFI", &partitions.efi_partition])
-        .status()
-        .context("Failed to format EFI partition")?;
+    for (part, tool) in to_format() {
+        let fs = part.spec.filesystem;
+        let device = part.fs_device();
 
-    if !status.success() {
-        bail!("Failed to format EFI partition as FAT32");
-    }
-
-    println!("{}", colors::success("✓ EFI partition formatted as FAT32"));
+        say!(
+            "{}",
+            colors::info(&format!("Formatting {} as {}...", device, fs))
+        );
 
-    // Format Linux partition as Btrfs
-    println!(
-        "{}",
-        colors::info(&format!(
-            "Formatting {} as Btrfs...",
-            partitions.linux_partition
-        ))
-    );
+        // A multi-disk Btrfs is created in one go, the other members come last
+        let mut args = fs.mkfs_args(&part.spec.label, &device);
+        if let Some(raid) = &part.raid {
+            args.splice(0..0, raid.mkfs_args());
+            args.extend(raid.devices.iter().cloned());
+        }
 
-    let status = Command::new("mkfs.btrfs")
-        .args(["-f", "-L", "ROOT", &partitions.linux_partition])
-        .status()
-        .context("Failed to format Linux partition")?;
+        run_show(runner, Command::new(tool).args(args))
+            .with_context(|| format!("Failed to format {} partition as {}", part.spec.label, fs))?;
 
-    if !status.success() {
-        bail!("Failed to format Linux partition as Btrfs");
+        say!(
+            "{}",
+            colors::success(&format!(
+                "✓ {} partition formatted as {}",
+                part.spec.label, fs
+            ))
+        );
     }
-
-    println!(
-        "{}",
-        colors::success("✓ Linux partition233 formatted as Btrfs")
-    );
-    println!();
+    say!();
 
     Ok(())
 }
@@ -576,3 +1030,280 @@ fn format_bytes(bytes: u64) -> String {
         format!("{} B", bytes)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::core::disk_setup::structs::RaidMembers;
+    use crate::commands::core::disk_setup::structs::RaidProfile;
+    use crate::helpers::runner::FakeRunner;
+
+    // Windows install on a 500 GB disk, booted from the Arch ISO on a USB stick
+    const LSBLK: &str = r#"{"blockdevices": [
+        {"name": "sda", "size": "465.8G", "type": "disk", "model": "Samsung SSD", "tran": "sata",
+         "rota": false, "rm": false, "serial": "S3Z9NB0K", "wwn": "0x5002538e40a1b2c3",
+         "pttype": "gpt", "fstype": null, "label": null, "mountpoints": [null],
+         "children": [
+            {"name": "sda1", "size": "100M", "type": "part", "fstype": "vfat", "label": null,
+             "mountpoints": [null]},
+            {"name": "sda3", "size": "186.2G", "type": "part", "fstype": "ntfs", "label": "Windows",
+             "mountpoints": ["/mnt/windows"]}
+         ]},
+        {"name": "sdb", "size": "28.9G", "type": "disk", "model": "Ultra Fit", "tran": "usb",
+         "rota": "0", "rm": "1", "pttype": "dos", "mountpoints": [null],
+         "children": [
+            {"name": "sdb1", "size": "1.1G", "type": "part", "fstype": "iso9660",
+             "label": "ARCH_202610", "mountpoints": ["/run/archiso/bootmnt"]}
+         ]},
+        {"name": "sr0", "size": "1024M", "type": "rom", "model": null},
+        {"name": "loop0", "size": "846.5M", "type": "loop", "fstype": "squashfs",
+         "mountpoints": ["/run/archiso/airootfs"]},
+        {"name": "zram0", "size": "4G", "type": "disk", "model": null, "mountpoints": ["[SWAP]"]}
+    ]}"#;
+
+    const PARTED_FREE: &str = "BYT;
+/dev/sda:500107862016B:scsi:512:512:gpt:Samsung SSD:;
+1:17408B:1048575B:1031168B:free;
+1:1048576B:105906175B:104857600B:fat32:EFI system partition:boot, esp;
+2:105906176B:122683391B:16777216B::Microsoft reserved partition:msftres;
+3:122683392B:200000000511B:199877317120B:ntfs:Basic data partition:msftdata;
+1:200000000512B:500107845119B:300107844608B:free;
+";
+
+    const PARTED_PRINT: &str = "BYT;
+/dev/sda:500107862016B:scsi:512:512:gpt:Samsung SSD:;
+1:1048576B:105906175B:104857600B:fat32:EFI system partition:boot, esp;
+2:105906176B:122683391B:16777216B::Microsoft reserved partition:msftres;
+3:122683392B:200000000511B:199877317120B:ntfs:Basic data partition:msftdata;
+";
+
+    fn dual_boot_disk() -> FakeRunner {
+        FakeRunner::new()
+            .respond("lsblk", LSBLK)
+            .respond("parted -s -m /dev/sda unit B print free", PARTED_FREE)
+            .respond("parted -s -m /dev/sda unit B print", PARTED_PRINT)
+            .respond("blockdev --getss /dev/sda", "512\n")
+            .respond("blockdev --getpbsz /dev/sda", "4096\n")
+    }
+
+    fn partition(size: &str, type_code: &str, label: &str, fs: Filesystem) -> PlannedPartition {
+        PlannedPartition {
+            size: size.parse().unwrap(),
+            type_code: type_code.to_string(),
+            label: label.to_string(),
+            filesystem: fs,
+            mount_point: None,
+            mount_options: None,
+        }
+    }
+
+    fn swap_and_root() -> PartitionPlan {
+        PartitionPlan {
+            partitions: vec![
+                partition("8G", "8200", "SWAP", Filesystem::Swap),
+                partition("rest", "8300", "Linux", Filesystem::Btrfs),
+            ],
+            reuse_esp: None,
+            swap: None,
+            lvm: None,
+            boot_mode: BootMode::Uefi,
+        }
+    }
+
+    #[test]
+    fn classifies_disks() {
+        let runner = dual_boot_disk();
+        let disks = list_block_disks(&runner).unwrap();
+
+        let roles: Vec<(&str, DiskRole)> =
+            disks.iter().map(|d| (d.name.as_str(), d.role)).collect();
+        assert_eq!(
+            roles,
+            [
+                ("sda", DiskRole::Regular),
+                ("sdb", DiskRole::BootMedium),
+                ("loop0", DiskRole::Loop),
+                ("zram0", DiskRole::Zram),
+            ]
+        );
+        assert_eq!(disks[0].kind(), "sata SSD");
+        assert_eq!(disks[1].kind(), "usb SSD removable");
+        assert_eq!(disks[3].model, "");
+    }
+
+    #[test]
+    fn keeps_the_partition_tree() {
+        let runner = dual_boot_disk();
+        let disks = list_block_disks(&runner).unwrap();
+        let sda = &disks[0];
+
+        assert_eq!(sda.pttype.as_deref(), Some("gpt"));
+        assert_eq!(sda.serial.as_deref(), Some("S3Z9NB0K"));
+
+        let children: Vec<&str> = sda.children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(children, ["sda1", "sda3"]);
+        assert!(sda.children[0].mountpoints.is_empty());
+        assert_eq!(sda.children[1].mountpoints, ["/mnt/windows"]);
+        assert_eq!(sda.children[1].label.as_deref(), Some("Windows"));
+    }
+
+    #[test]
+    fn skips_free_regions_below_one_mib() {
+        let runner = dual_boot_disk();
+        let regions = list_free_regions(&runner, "/dev/sda").unwrap();
+
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].start_bytes, 200_000_000_512);
+        assert_eq!(regions[0].end_bytes, 500_107_845_119);
+        assert_eq!(regions[0].size_bytes, 300_107_844_608);
+    }
+
+    #[test]
+    fn missing_partition_table_is_not_an_error() {
+        let runner =
+            FakeRunner::new().fail("parted -s /dev/sdb print", 1, "unrecognised disk label");
+
+        assert!(!check_partition_table(&runner, "/dev/sdb").unwrap());
+        assert_eq!(runner.calls(), ["parted -s /dev/sdb print"]);
+    }
+
+    // What `install` computes before the preview
+    fn place(
+        runner: &FakeRunner,
+        plan: &PartitionPlan,
+        existing: &[u32],
+    ) -> (Vec<SectorRange>, Vec<u32>) {
+        let region = list_free_regions(runner, "/dev/sda").unwrap().remove(0);
+        let geometry = get_geometry(runner, "/dev/sda").unwrap();
+        let ranges = size::solve(&plan.partitions, &region, geometry).unwrap();
+        let numbers =
+            new_partition_numbers(existing, plan.partitions.len(), PartitionTable::Gpt).unwrap();
+        (ranges, numbers)
+    }
+
+    #[test]
+    fn issues_aligned_sgdisk_calls_after_existing_partitions() {
+        let runner = dual_boot_disk();
+        let partitions = list_partitions(&runner, "/dev/sda").unwrap();
+        let existing: Vec<u32> = partitions.iter().map(|p| p.number).collect();
+        assert_eq!(existing, [1, 2, 3]);
+        assert_eq!(partitions[2].filesystem, "ntfs");
+        assert_eq!(partitions[2].name, "Basic data partition");
+
+        let plan = swap_and_root();
+        let (ranges, numbers) = place(&runner, &plan, &existing);
+        let created =
+            write_partition_entries(&runner, "/dev/sda", &plan, &ranges, &numbers).unwrap();
+
+        assert_eq!(
+            runner.calls_to("sgdisk"),
+            [
+                "sgdisk -n 4:390625280:407402495 -t 4:8200 -c 4:SWAP /dev/sda",
+                "sgdisk -n 5:407402496:976773134 -t 5:8300 -c 5:Linux /dev/sda",
+            ]
+        );
+
+        let numbers: Vec<u32> = created.iter().map(|p| p.number).collect();
+        assert_eq!(numbers, [4, 5]);
+    }
+
+    #[test]
+    fn fills_gaps_in_partition_numbers() {
+        let runner = dual_boot_disk();
+        let plan = swap_and_root();
+        let (ranges, numbers) = place(&runner, &plan, &[1, 3]);
+
+        write_partition_entries(&runner, "/dev/sda", &plan, &ranges, &numbers).unwrap();
+
+        let sgdisk = runner.calls_to("sgdisk");
+        assert!(sgdisk[0].starts_with("sgdisk -n 2:"));
+        assert!(sgdisk[1].starts_with("sgdisk -n 4:"));
+    }
+
+    #[test]
+    fn rejects_a_placement_that_does_not_match_the_plan() {
+        let runner = dual_boot_disk();
+        let plan = swap_and_root();
+        let (ranges, numbers) = place(&runner, &plan, &[1, 2, 3]);
+
+        let err = write_partition_entries(&runner, "/dev/sda", &plan, &ranges[..1], &numbers);
+
+        assert!(err.is_err());
+        assert!(runner.calls_to("sgdisk").is_empty());
+    }
+
+    #[test]
+    fn stops_at_the_first_failing_sgdisk_call() {
+        let runner = dual_boot_disk().fail("sgdisk -n 4:", 4, "Could not create partition 4");
+        let plan = swap_and_root();
+        let (ranges, numbers) = place(&runner, &plan, &[1, 2, 3]);
+
+        let err =
+            write_partition_entries(&runner, "/dev/sda", &plan, &ranges, &numbers).unwrap_err();
+
+        assert!(format!("{:#}", err).contains("Could not create partition 4"));
+        assert_eq!(runner.calls_to("sgdisk").len(), 1);
+    }
+
+    #[test]
+    fn formats_new_partitions_only() {
+        let runner = FakeRunner::new();
+        let mut esp = partition("512M", "ef00", "EFI", Filesystem::Vfat);
+        esp.mount_point = Some("/boot".to_string());
+
+        let created = |number: u32, spec: PlannedPartition, reused: bool| CreatedPartition {
+            number,
+            device: format!("/dev/sda{}", number),
+            spec,
+            encryption: None,
+            reused,
+            raid: None,
+        };
+        let partitions = CreatedPartitions {
+            partitions: vec![
+                created(4, partition("8G", "8200", "SWAP", Filesystem::Swap), false),
+                created(
+                    5,
+                    partition("rest", "8300", "Linux", Filesystem::Btrfs),
+                    false,
+                ),
+                created(1, esp, true),
+            ],
+        };
+
+        format_partitions(&runner, &partitions).unwrap();
+
+        assert_eq!(
+            runner.calls(),
+            [
+                "mkswap -L SWAP /dev/sda4",
+                "mkfs.btrfs -f -L Linux /dev/sda5",
+            ]
+        );
+    }
+
+    #[test]
+    fn formats_one_btrfs_across_raid_members() {
+        let runner = FakeRunner::new();
+        let partitions = CreatedPartitions {
+            partitions: vec![CreatedPartition {
+                number: 2,
+                device: "/dev/nvme0n1p2".to_string(),
+                spec: partition("rest", "8300", "ROOT", Filesystem::Btrfs),
+                encryption: None,
+                reused: false,
+                raid: Some(RaidMembers {
+                    profile: RaidProfile::Raid1,
+                    devices: vec!["/dev/nvme1n1p2".to_string()],
+                }),
+            }],
+        };
+
+        format_partitions(&runner, &partitions).unwrap();
+
+        assert_eq!(
+            runner.calls(),
+            ["mkfs.btrfs -d raid1 -m raid1 -f -L ROOT /dev/nvme0n1p2 /dev/nvme1n1p2"]
+        );
+    }
+}
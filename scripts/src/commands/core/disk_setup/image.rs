@@ -0,0 +1,146 @@
+use anyhow::{Context, Result, bail};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::colors;
+use crate::commands::core::disk_setup::size;
+use crate::helpers::CommandRunner;
+use crate::helpers::interrupt;
+use crate::helpers::runner::command_line;
+use crate::helpers::{ensure_tool_exists, run_out, run_show};
+
+// ---------------------------------------------------------
+// Disk image attached to a loop device
+// ---------------------------------------------------------
+pub struct LoopImage<'a> {
+    pub image: PathBuf,
+    /// e.g. /dev/loop0
+    pub device: String,
+    runner: &'a dyn CommandRunner,
+}
+
+impl Drop for LoopImage<'_> {
+    fn drop(&mut self) {
+        // After Ctrl-C the journal's cleanup detaches it (a second Ctrl-C never gets here)
+        if interrupt::interrupted() {
+            return;
+        }
+
+        say!(
+            "{}",
+            colors::info(&format!(
+                "Detaching {} ({})",
+                self.device,
+                self.image.display()
+            ))
+        );
+        if let Err(e) = detach(self.runner, &self.device) {
+            say!("{}", colors::warn(&format!("{:#}", e)));
+        }
+    }
+}
+
+// `losetup -d` on a busy device only sets autoclear, so mounted
+// partitions keep working and the loop goes away with the last umount
+pub fn detach(runner: &dyn CommandRunner, device: &str) -> Result<()> {
+    let mut cmd = Command::new("losetup");
+    cmd.args(["-d", device]);
+    say!("> {}", command_line(&cmd));
+
+    let out = runner.cleanup(&mut cmd)?;
+    if !out.success {
+        bail!("Failed to detach {}: {}", device, out.stderr.trim());
+    }
+
+    Ok(())
+}
+
+// ---------------------------------------------------------
+// Create (sparse) and attach an image file
+// ---------------------------------------------------------
+pub fn attach_image<'a>(
+    runner: &'a dyn CommandRunner,
+    path: &Path,
+    size: Option<&str>,
+) -> Result<LoopImage<'a>> {
+    ensure_tool_exists("losetup")?;
+
+    say!("{}", colors::header("Disk Image"));
+
+    if path.exists() {
+        if size.is_some() {
+            say!(
+                "{}",
+                colors::warn(&format!(
+                    "{} already exists, ignoring --size",
+                    path.display()
+                ))
+            );
+        }
+    } else {
+        let Some(size) = size else {
+            bail!(
+                "{} does not exist, pass --size to create it",
+                path.display()
+            );
+        };
+
+        if runner.is_dry_run() {
+            bail!(
+                "Dry runs cannot create {}, create the image first",
+                path.display()
+            );
+        }
+
+        let bytes = size::parse_bytes(size).context("Invalid image size")?;
+
+        // set_len on a fresh file leaves it sparse
+        std::fs::File::create(path)
+            .and_then(|f| f.set_len(bytes))
+            .with_context(|| format!("Failed to create image {}", path.display()))?;
+
+        say!(
+            "{}",
+            colors::success(&format!(
+                "✓ Created sparse image {} ({})",
+                path.display(),
+                size
+            ))
+        );
+    }
+
+    let path_str = path.to_string_lossy().to_string();
+
+    // -P scans partitions so loop0p1, loop0p2... show up; dry runs stay read-only
+    let mut cmd = Command::new("losetup");
+    cmd.args(["--find", "--show", "-P"]);
+    if runner.is_dry_run() {
+        cmd.arg("-r");
+    }
+    cmd.arg(&path_str);
+
+    let device = if runner.is_dry_run() {
+        say!("> {}", command_line(&cmd));
+        run_out(runner, &mut cmd)
+    } else {
+        run_show(runner, &mut cmd)
+    }
+    .context("Failed to attach image to a loop device")?
+    .trim()
+    .to_string();
+
+    if device.is_empty() {
+        bail!("losetup did not report a loop device");
+    }
+
+    say!(
+        "{}\n",
+        colors::success(&format!("✓ {} attached as {}", path.display(), device))
+    );
+
+    Ok(LoopImage {
+        image: path.to_path_buf(),
+        device,
+        runner,
+    })
+}
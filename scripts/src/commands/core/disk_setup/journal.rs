@@ -0,0 +1,334 @@
+use anyhow::Result;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::colors;
+use crate::commands::core::disk_setup::backup;
+use crate::commands::core::disk_setup::devices;
+use crate::commands::core::disk_setup::image;
+use crate::commands::core::disk_setup::structs::CleanupAction;
+use crate::commands::core::disk_setup::structs::CleanupRecord;
+use crate::commands::core::disk_setup::structs::InstallStep;
+use crate::commands::core::disk_setup::structs::RunOutcome;
+use crate::commands::core::disk_setup::structs::RunRecord;
+use crate::commands::core::disk_setup::structs::StepRecord;
+use crate::helpers::interrupt;
+use crate::helpers::run_show;
+use crate::helpers::runner::{CommandRunner, DryRunRunner, RealRunner};
+
+// ---------------------------------------------------------
+// Record of a disk-setup run, rewritten after every step
+// ---------------------------------------------------------
+pub struct Journal {
+    state: Arc<Mutex<JournalState>>,
+}
+
+struct JournalState {
+    record: RunRecord,
+    /// Registered in the order things were set up, undone in reverse
+    cleanups: Vec<CleanupAction>,
+    path: PathBuf,
+    dry_run: bool,
+}
+
+impl Journal {
+    /// Starts recording and takes over Ctrl-C until the process exits
+    pub fn start(runner: &dyn CommandRunner, disk: &str, target: &str) -> Result<Journal> {
+        let now = chrono::Local::now();
+        let path = backup::state_dir()?.join("runs").join(format!(
+            "{}-{}.json",
+            devices::kernel_name(disk)?,
+            now.format("%Y%m%d-%H%M%S")
+        ));
+
+        let journal = Journal {
+            state: Arc::new(Mutex::new(JournalState {
+                record: RunRecord {
+                    disk: disk.to_string(),
+                    target: target.to_string(),
+                    started_at: now.to_rfc3339(),
+                    outcome: RunOutcome::Running,
+                    disk_state: "unchanged".to_string(),
+                    steps: Vec::new(),
+                    cleanup: Vec::new(),
+                },
+                cleanups: Vec::new(),
+                path,
+                dry_run: runner.is_dry_run(),
+            })),
+        };
+
+        let state = Arc::clone(&journal.state);
+        interrupt::arm(move || abort(&state))?;
+
+        Ok(journal)
+    }
+
+    /// Records a finished step; fails if Ctrl-C was pressed meanwhile
+    pub fn step(&self, step: InstallStep) -> Result<()> {
+        {
+            let mut state = self.lock();
+            let disk_state = step.disk_state();
+            state.record.steps.push(StepRecord {
+                finished_at: chrono::Local::now().to_rfc3339(),
+                step,
+                disk_state: disk_state.clone(),
+            });
+            state.record.disk_state = disk_state;
+            state.save();
+        }
+
+        interrupt::check()
+    }
+
+    /// Undo `action` if the run is interrupted (registered before the step runs)
+    pub fn on_interrupt(&self, action: CleanupAction) {
+        let mut state = self.lock();
+        if !state.cleanups.contains(&action) {
+            state.cleanups.push(action);
+        }
+    }
+
+    /// Records how the run ended; an interrupted run is cleaned up first
+    pub fn finish(&self, result: &Result<()>) {
+        let mut state = self.lock();
+
+        state.record.outcome = match result {
+            Ok(()) => RunOutcome::Completed,
+            Err(e) if interrupt::is_interrupt(e) => RunOutcome::Interrupted(format!("{:#}", e)),
+            Err(e) => RunOutcome::Failed(format!("{:#}", e)),
+        };
+
+        let interrupted = matches!(state.record.outcome, RunOutcome::Interrupted(_));
+        if interrupted && interrupt::begin_cleanup() {
+            state.clean_up();
+        }
+
+        state.save();
+        state.report();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, JournalState> {
+        // A panic while holding the lock still leaves a usable record
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Second Ctrl-C while no command runs, e.g. at the passphrase prompt
+fn abort(state: &Mutex<JournalState>) {
+    if !interrupt::begin_cleanup() {
+        return;
+    }
+
+    let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+    state.record.outcome = RunOutcome::Interrupted("Stopped while waiting for input".to_string());
+    state.clean_up();
+    state.save();
+    state.report();
+
+    // The hidden passphrase prompt turns echo off and never gets to restore it
+    let _ = Command::new("stty").arg("echo").status();
+}
+
+// Command that reverses one registered step
+fn undo(runner: &dyn CommandRunner, action: &CleanupAction) -> Result<()> {
+    let mut cmd = match action {
+        CleanupAction::Unmount(target) => {
+            let mut cmd = Command::new("umount");
+            cmd.args(["-R", target]);
+            cmd
+        }
+        CleanupAction::Swapoff(device) => {
+            let mut cmd = Command::new("swapoff");
+            cmd.arg(device);
+            cmd
+        }
+        CleanupAction::DeactivateVg(vg) => {
+            let mut cmd = Command::new("vgchange");
+            cmd.args(["-an", vg]);
+            cmd
+        }
+        CleanupAction::CloseLuks(mapper) => {
+            let mut cmd = Command::new("cryptsetup");
+            cmd.args(["close", mapper]);
+            cmd
+        }
+        // Attached for real in dry runs too
+        CleanupAction::DetachLoop(device) => return image::detach(runner, device),
+    };
+
+    run_show(runner, &mut cmd)?;
+    Ok(())
+}
+
+impl JournalState {
+    fn clean_up(&mut self) {
+        if self.cleanups.is_empty() {
+            return;
+        }
+
+        say!();
+        say!("{}", colors::header("Cleaning Up"));
+
+        let runner: Box<dyn CommandRunner> = if self.dry_run {
+            Box::new(DryRunRunner)
+        } else {
+            Box::new(RealRunner)
+        };
+
+        for action in self.cleanups.iter().rev() {
+            let error = undo(runner.as_ref(), action)
+                .err()
+                .map(|e| format!("{:#}", e));
+            if let Some(e) = &error {
+                say!("{}", colors::warn(e));
+            }
+
+            self.record.cleanup.push(CleanupRecord {
+                action: action.clone(),
+                error,
+            });
+        }
+    }
+
+    // Best effort: a failing write must not hide why the run stopped
+    fn save(&self) {
+        // Nothing was written to the disk yet, nothing to record
+        if self.dry_run || self.record.steps.is_empty() {
+            return;
+        }
+
+        let write = || -> Result<()> {
+            if let Some(dir) = self.path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(&self.path, serde_json::to_string_pretty(&self.record)?)?;
+            Ok(())
+        };
+
+        if let Err(e) = write() {
+            say!(
+                "{}",
+                colors::warn(&format!(
+                    "Could not write run record {}: {:#}",
+                    self.path.display(),
+                    e
+                ))
+            );
+        }
+    }
+
+    fn report(&self) {
+        say!();
+        match &self.record.outcome {
+            RunOutcome::Completed | RunOutcome::Running => {}
+            RunOutcome::Interrupted(_) => say!("{}", colors::warn("Disk setup interrupted")),
+            RunOutcome::Failed(_) => {
+                say!("{}", colors::warn("Disk setup failed"));
+                // The loop device of an image is detached either way
+                if self
+                    .cleanups
+                    .iter()
+                    .any(|c| !matches!(c, CleanupAction::DetachLoop(_)))
+                {
+                    say!(
+                        "{}",
+                        colors::info(&format!(
+                            "`sharch teardown --target {}` releases what is still mounted or open",
+                            self.record.target
+                        ))
+                    );
+                }
+            }
+        }
+
+        if !matches!(self.record.outcome, RunOutcome::Completed) {
+            say!(
+                "{}",
+                colors::info(&format!("Disk state: {}", self.record.disk_state))
+            );
+        }
+
+        if self.record.steps.is_empty() {
+            return;
+        }
+
+        if self.dry_run {
+            say!(
+                "{}",
+                colors::info(&format!(
+                    "[DRY RUN] Would record the run in {}",
+                    self.path.display()
+                ))
+            );
+        } else {
+            say!(
+                "{}",
+                colors::info(&format!("Run record: {}", self.path.display()))
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::runner::FakeRunner;
+
+    #[test]
+    fn undoes_each_action_with_its_own_command() {
+        let runner = FakeRunner::new();
+        for action in [
+            CleanupAction::Unmount("/mnt".to_string()),
+            CleanupAction::CloseLuks("cryptroot".to_string()),
+            CleanupAction::DetachLoop("/dev/loop0".to_string()),
+        ] {
+            undo(&runner, &action).unwrap();
+        }
+
+        assert_eq!(
+            runner.calls(),
+            [
+                "umount -R /mnt",
+                "cryptsetup close cryptroot",
+                "losetup -d /dev/loop0"
+            ]
+        );
+    }
+
+    #[test]
+    fn record_names_the_step_and_the_disk_state() {
+        let step = InstallStep::PartitionsCreated {
+            devices: vec!["/dev/sda1".to_string(), "/dev/sda2".to_string()],
+        };
+        let record = RunRecord {
+            disk: "/dev/sda".to_string(),
+            target: "/mnt".to_string(),
+            started_at: "2026-10-18T12:00:00+02:00".to_string(),
+            outcome: RunOutcome::Interrupted("Interrupted by Ctrl-C".to_string()),
+            disk_state: step.disk_state(),
+            steps: vec![StepRecord {
+                finished_at: "2026-10-18T12:00:05+02:00".to_string(),
+                disk_state: step.disk_state(),
+                step,
+            }],
+            cleanup: vec![CleanupRecord {
+                action: CleanupAction::CloseLuks("cryptroot".to_string()),
+                error: None,
+            }],
+        };
+
+        let json = serde_json::to_value(&record).unwrap();
+
+        assert_eq!(json["outcome"]["status"], "interrupted");
+        assert_eq!(json["steps"][0]["step"], "partitions_created");
+        assert_eq!(json["steps"][0]["devices"][1], "/dev/sda2");
+        assert_eq!(
+            json["disk_state"],
+            "partitions /dev/sda1, /dev/sda2 created, nothing encrypted or formatted"
+        );
+        assert_eq!(json["cleanup"][0]["action"], "close_luks");
+        assert_eq!(json["cleanup"][0]["device"], "cryptroot");
+    }
+}
@@ -0,0 +1,449 @@
+use anyhow::{Context, Result, bail};
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::colors;
+use crate::commands::core::disk_setup::boot;
+use crate::commands::core::disk_setup::devices;
+use crate::commands::core::disk_setup::esp;
+use crate::commands::core::disk_setup::lvm;
+use crate::commands::core::disk_setup::structs::BootMode;
+use crate::commands::core::disk_setup::structs::Disk;
+use crate::commands::core::disk_setup::structs::DiskLayout;
+use crate::commands::core::disk_setup::structs::EspChoice;
+use crate::commands::core::disk_setup::structs::Filesystem;
+use crate::commands::core::disk_setup::structs::PartitionPlan;
+use crate::commands::core::disk_setup::structs::PlannedPartition;
+use crate::commands::core::disk_setup::subvolumes;
+use crate::commands::core::disk_setup::swap;
+
+// GPT partition names are stored as 36 UTF-16 code units
+const GPT_LABEL_MAX: usize = 36;
+
+// ---------------------------------------------------------
+// Load layout file
+// ---------------------------------------------------------
+pub fn load_layout(path: &Path) -> Result<DiskLayout> {
+    let raw = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read layout file {}", path.display()))?;
+
+    let layout: DiskLayout = toml::from_str(&raw)
+        .with_context(|| format!("Failed to parse layout file {}", path.display()))?;
+
+    say!(
+        "{}",
+        colors::info(&format!(
+            "Using layout {} for {}",
+            colors::highlight(&path.display().to_string()),
+            colors::highlight(&layout.disk)
+        ))
+    );
+
+    Ok(layout)
+}
+
+// ---------------------------------------------------------
+// Resolve the layout's disk against lsblk
+// ---------------------------------------------------------
+pub fn resolve_disk(layout: &DiskLayout, disks: &[Disk]) -> Result<String> {
+    resolve_disk_name(&layout.disk, disks)
+}
+
+// `nvme0n1`, `/dev/nvme0n1` or a /dev/disk/by-id link to the disk's path
+pub fn resolve_disk_name(wanted: &str, disks: &[Disk]) -> Result<String> {
+    let wanted = wanted.trim();
+
+    // /dev/disk/by-id/... and other symlinks point at the kernel name
+    let kernel_name = devices::kernel_name(wanted).ok();
+
+    match disks
+        .iter()
+        .find(|d| d.path == wanted || d.name == wanted || kernel_name.as_ref() == Some(&d.name))
+    {
+        Some(d) => {
+            say!("{}\n", colors::success(&format!("✓ Selected: {}", d.path)));
+            Ok(d.path.clone())
+        }
+        None => {
+            let known: Vec<&str> = disks.iter().map(|d| d.path.as_str()).collect();
+            bail!(
+                "Layout disk `{}` not found. Available disks: {}",
+                wanted,
+                known.join(", ")
+            );
+        }
+    }
+}
+
+// ---------------------------------------------------------
+// Build and validate the partition plan from the layout
+// ---------------------------------------------------------
+pub fn plan_from_layout(
+    layout: &DiskLayout,
+    esp: &EspChoice,
+    boot_mode: BootMode,
+) -> Result<PartitionPlan> {
+    if layout.partitions.is_empty() {
+        bail!("Layout does not define any [[partition]] entries");
+    }
+
+    if boot_mode != BootMode::Uefi
+        && (layout.esp.is_some() || layout.partitions.iter().any(|p| p.is_esp()))
+    {
+        bail!(
+            "Layout has an EFI System Partition, but the boot mode is {}",
+            boot_mode.describe()
+        );
+    }
+
+    let mut mount_points = HashSet::new();
+    let mut flexible = None;
+
+    for (i, part) in layout.partitions.iter().enumerate() {
+        let name = if part.label.is_empty() {
+            format!("partition #{}", i + 1)
+        } else {
+            format!("partition `{}`", part.label)
+        };
+
+        if part.label.is_empty() || part.label.chars().count() > GPT_LABEL_MAX {
+            bail!(
+                "Layout {}: label must be 1-{} characters",
+                name,
+                GPT_LABEL_MAX
+            );
+        }
+
+        if !is_valid_type_code(&part.type_code) {
+            bail!(
+                "Layout {}: type `{}` is not an sgdisk type code (e.g. ef00, 8300) or GUID",
+                name,
+                part.type_code
+            );
+        }
+
+        if part.label.chars().count() > part.filesystem.label_max_len() {
+            bail!(
+                "Layout {}: {} labels are limited to {} characters",
+                name,
+                part.filesystem,
+                part.filesystem.label_max_len()
+            );
+        }
+
+        // `rest` and `-8G` take whatever the fixed sizes leave over
+        if part.size.is_flexible()
+            && let Some(other) = flexible.replace(name.clone())
+        {
+            bail!(
+                "Layout {}: only one partition may use `rest` or `-<size>`, {} already does",
+                name,
+                other
+            );
+        }
+
+        if part.is_esp() {
+            if part.filesystem != Filesystem::Vfat {
+                bail!("Layout {}: EFI System Partitions must be vfat", name);
+            }
+            if part.size.fixed_bytes().is_none_or(|b| b < 512 * 1_048_576) {
+                bail!(
+                    "Layout {}: EFI partition needs a fixed size of at least 512M",
+                    name
+                );
+            }
+        }
+
+        if let Some(mp) = &part.mount_point {
+            if !mp.starts_with('/') {
+                bail!("Layout {}: mount_point `{}` must be absolute", name, mp);
+            }
+            if !mount_points.insert(mp.as_str()) {
+                bail!("Layout {}: mount_point `{}` is used twice", name, mp);
+            }
+        }
+
+        if part.filesystem == Filesystem::Swap && part.mount_point.is_some() {
+            bail!("Layout {}: swap partitions cannot have a mount_point", name);
+        }
+
+        if part.is_bios_boot() != (part.filesystem == Filesystem::BiosBoot) {
+            bail!(
+                "Layout {}: bios_grub partitions need type `ef02` and filesystem = \"bios_grub\"",
+                name
+            );
+        }
+
+        if part.is_bios_boot() {
+            if boot_mode != BootMode::BiosGpt {
+                bail!(
+                    "Layout {}: bios_grub partitions are only used with boot_mode = \"bios-gpt\"",
+                    name
+                );
+            }
+            if part.mount_point.is_some() {
+                bail!(
+                    "Layout {}: bios_grub partitions cannot have a mount_point",
+                    name
+                );
+            }
+        }
+
+        if part.filesystem == Filesystem::Lvm {
+            if part.mount_point.is_some() {
+                bail!("Layout {}: LVM partitions cannot have a mount_point", name);
+            }
+            if layout.lvm.is_none() {
+                bail!(
+                    "Layout {}: filesystem = \"lvm\" needs an [lvm] section",
+                    name
+                );
+            }
+        }
+
+        if part.mount_options.is_some() && part.mount_point.is_none() {
+            bail!("Layout {}: mount_options given without a mount_point", name);
+        }
+    }
+
+    // Logical volumes count as partitions for `/`, swap and subvolume checks
+    let volumes = match &layout.lvm {
+        Some(config) => {
+            lvm::validate_lvm(config, &layout.partitions)?;
+            lvm::volume_specs(config)
+        }
+        None => Vec::new(),
+    };
+
+    if !layout.subvolumes.is_empty() {
+        let root_is_btrfs =
+            layout.partitions.iter().chain(&volumes).any(|p| {
+                p.mount_point.as_deref() == Some("/") && p.filesystem == Filesystem::Btrfs
+            });
+
+        if !root_is_btrfs {
+            bail!(
+                "Layout defines [[subvolume]] entries but `/` is not a btrfs partition or volume"
+            );
+        }
+
+        subvolumes::validate_subvolumes(&layout.subvolumes)?;
+    }
+
+    // A small reused ESP gets an XBOOTLDR partition in front of the layout's partitions
+    let mut partitions: Vec<PlannedPartition> = esp
+        .xbootldr_size_mb
+        .map(esp::xbootldr_partition)
+        .transpose()?
+        .into_iter()
+        .collect();
+
+    if !partitions.is_empty()
+        && layout
+            .partitions
+            .iter()
+            .any(|p| p.mount_point.as_deref() == Some("/boot"))
+    {
+        bail!("Layout mounts a partition at /boot, but the reused ESP needs an XBOOTLDR there");
+    }
+
+    // GRUB needs a bios_grub partition on GPT, add one if the layout has none
+    if boot_mode == BootMode::BiosGpt && !layout.partitions.iter().any(|p| p.is_bios_boot()) {
+        partitions.push(boot::bios_grub_partition());
+    }
+
+    partitions.extend(layout.partitions.iter().cloned());
+
+    let swap = swap::swap_from_layout(
+        layout,
+        &[partitions.as_slice(), volumes.as_slice()].concat(),
+    )?;
+
+    Ok(PartitionPlan {
+        partitions,
+        reuse_esp: esp.reuse.clone(),
+        swap,
+        lvm: layout.lvm.clone(),
+        boot_mode,
+    })
+}
+
+// ---------------------------------------------------------
+// sgdisk accepts 4-digit hex codes or full GUIDs
+// ---------------------------------------------------------
+fn is_valid_type_code(code: &str) -> bool {
+    let is_hex = |s: &str| s.chars().all(|c| c.is_ascii_hexdigit());
+
+    if code.len() == 4 {
+        return is_hex(code);
+    }
+
+    let groups: Vec<&str> = code.split('-').collect();
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(g, len)| g.len() == len && is_hex(g))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::core::disk_setup::structs::DiskRole;
+    use crate::commands::core::disk_setup::structs::SizeSpec;
+
+    const ESP: &str = r#"
+        [[partition]]
+        size = "1G"
+        type = "ef00"
+        label = "EFI"
+        filesystem = "vfat"
+        mount_point = "/boot"
+    "#;
+
+    const ROOT: &str = r#"
+        [[partition]]
+        size = "rest"
+        type = "8300"
+        label = "ROOT"
+        filesystem = "btrfs"
+        mount_point = "/"
+    "#;
+
+    fn layout(partitions: &[&str]) -> DiskLayout {
+        toml::from_str(&format!("disk = \"sda\"\n{}", partitions.concat())).unwrap()
+    }
+
+    fn plan(partitions: &[&str], boot_mode: BootMode) -> Result<PartitionPlan> {
+        plan_from_layout(&layout(partitions), &EspChoice::default(), boot_mode)
+    }
+
+    fn disk(name: &str) -> Disk {
+        Disk {
+            name: name.to_string(),
+            path: format!("/dev/{}", name),
+            size: "512G".to_string(),
+            model: "Test".to_string(),
+            transport: None,
+            rotational: false,
+            removable: false,
+            serial: None,
+            wwn: None,
+            pttype: None,
+            by_id: None,
+            role: DiskRole::Regular,
+            children: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn accepts_a_plain_uefi_layout() {
+        let plan = plan(&[ESP, ROOT], BootMode::Uefi).unwrap();
+
+        assert_eq!(plan.partitions.len(), 2);
+        assert_eq!(plan.partitions[1].label, "ROOT");
+    }
+
+    #[test]
+    fn rejects_bad_labels() {
+        let long = ROOT.replace("\"ROOT\"", &format!("\"{}\"", "x".repeat(37)));
+        assert!(plan(&[ESP, &long], BootMode::Uefi).is_err());
+
+        let empty = ROOT.replace("\"ROOT\"", "\"\"");
+        assert!(plan(&[ESP, &empty], BootMode::Uefi).is_err());
+
+        // Fits GPT, but vfat labels stop at 11 characters
+        let esp = ESP.replace("\"EFI\"", "\"EFI-SYSTEM-1\"");
+        assert!(plan(&[&esp, ROOT], BootMode::Uefi).is_err());
+    }
+
+    #[test]
+    fn checks_type_codes() {
+        assert!(is_valid_type_code("8300"));
+        assert!(is_valid_type_code("EF00"));
+        assert!(is_valid_type_code("0FC63DAF-8483-4772-8E79-3D69D8477DE4"));
+
+        assert!(!is_valid_type_code("830"));
+        assert!(!is_valid_type_code("83000"));
+        assert!(!is_valid_type_code("zz00"));
+        assert!(!is_valid_type_code("0FC63DAF-8483-4772-8E79-3D69D8477DE"));
+        assert!(!is_valid_type_code("0FC63DAF84834772-8E79-3D69D8477DE4"));
+
+        let bad = ROOT.replace("\"8300\"", "\"linux\"");
+        assert!(plan(&[ESP, &bad], BootMode::Uefi).is_err());
+    }
+
+    #[test]
+    fn checks_esp_and_bios_grub_against_the_boot_mode() {
+        let bios_grub = r#"
+            [[partition]]
+            size = "1M"
+            type = "ef02"
+            label = "BIOS"
+            filesystem = "bios_grub"
+        "#;
+
+        // An ESP only makes sense with UEFI
+        assert!(plan(&[ESP, ROOT], BootMode::BiosGpt).is_err());
+        assert!(plan(&[ESP, ROOT], BootMode::BiosMbr).is_err());
+
+        let small_esp = ESP.replace("\"1G\"", "\"256M\"");
+        assert!(plan(&[&small_esp, ROOT], BootMode::Uefi).is_err());
+
+        let ext4_esp = ESP.replace("\"vfat\"", "\"ext4\"");
+        assert!(plan(&[&ext4_esp, ROOT], BootMode::Uefi).is_err());
+
+        assert!(plan(&[bios_grub, ROOT], BootMode::BiosGpt).is_ok());
+        assert!(plan(&[bios_grub, ROOT], BootMode::Uefi).is_err());
+        assert!(plan(&[bios_grub, ROOT], BootMode::BiosMbr).is_err());
+
+        // Type and filesystem have to agree
+        let untyped = bios_grub.replace("\"ef02\"", "\"8300\"");
+        assert!(plan(&[&untyped, ROOT], BootMode::BiosGpt).is_err());
+    }
+
+    #[test]
+    fn adds_bios_grub_when_the_layout_has_none() {
+        let plan = plan(&[ROOT], BootMode::BiosGpt).unwrap();
+
+        assert_eq!(plan.partitions.len(), 2);
+        assert!(plan.partitions[0].is_bios_boot());
+    }
+
+    #[test]
+    fn rejects_two_flexible_partitions_and_shared_mount_points() {
+        let home = ROOT.replace("\"/\"", "\"/home\"").replace("ROOT", "HOME");
+        assert!(plan(&[ESP, ROOT, &home], BootMode::Uefi).is_err());
+
+        let fixed_root = ROOT.replace("\"rest\"", "\"40G\"");
+        assert!(plan(&[ESP, &fixed_root, &home], BootMode::Uefi).is_ok());
+
+        let second_root = home.replace("\"/home\"", "\"/\"");
+        assert!(plan(&[ESP, &fixed_root, &second_root], BootMode::Uefi).is_err());
+    }
+
+    #[test]
+    fn rejects_size_mb_that_overflows() {
+        let old_style = ESP.replace("size = \"1G\"", "size_mb = 1024");
+        let parsed: DiskLayout =
+            toml::from_str(&format!("disk = \"sda\"\n{}{}", old_style, ROOT)).unwrap();
+        assert_eq!(parsed.partitions[0].size, SizeSpec::Bytes(1 << 30));
+
+        let huge = ESP.replace("size = \"1G\"", &format!("size_mb = {}", u64::MAX / 1024));
+        let error =
+            toml::from_str::<DiskLayout>(&format!("disk = \"sda\"\n{}{}", huge, ROOT)).unwrap_err();
+        assert!(error.to_string().contains("too large"));
+    }
+
+    #[test]
+    fn resolves_disk_names() {
+        let disks = [disk("sda"), disk("nvme0n1")];
+
+        assert_eq!(
+            resolve_disk_name("nvme0n1", &disks).unwrap(),
+            "/dev/nvme0n1"
+        );
+        assert_eq!(resolve_disk_name(" /dev/sda ", &disks).unwrap(), "/dev/sda");
+        assert!(resolve_disk_name("sdb", &disks).is_err());
+    }
+}
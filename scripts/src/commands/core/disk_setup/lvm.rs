@@ -0,0 +1,395 @@
+use anyhow::{Context, Result, bail};
+use std::collections::HashSet;
+use std::process::Command;
+
+use crate::colors;
+use crate::commands::core::disk_setup::structs::CreatedPartition;
+use crate::commands::core::disk_setup::structs::CreatedPartitions;
+use crate::commands::core::disk_setup::structs::Filesystem;
+use crate::commands::core::disk_setup::structs::LvmConfig;
+use crate::commands::core::disk_setup::structs::LvmSetup;
+use crate::commands::core::disk_setup::structs::PlannedPartition;
+use crate::commands::core::disk_setup::structs::SizeSpec;
+use crate::helpers::CommandRunner;
+use crate::helpers::{run_out, run_show};
+
+// ---------------------------------------------------------
+// Validate the [lvm] section against the partitions
+// ---------------------------------------------------------
+pub fn validate_lvm(config: &LvmConfig, partitions: &[PlannedPartition]) -> Result<()> {
+    validate_name("vg_name", &config.vg_name)?;
+
+    let pvs = partitions
+        .iter()
+        .filter(|p| p.filesystem == Filesystem::Lvm)
+        .count();
+    if pvs != 1 {
+        bail!(
+            "[lvm] needs exactly one [[partition]] with filesystem = \"lvm\", found {}",
+            pvs
+        );
+    }
+
+    if config.volumes.is_empty() {
+        bail!("[lvm] does not define any [[lvm.volume]] entries");
+    }
+
+    // Partition mount points (the ESP) must not clash with the volumes
+    let mut mount_points: HashSet<&str> = partitions
+        .iter()
+        .filter_map(|p| p.mount_point.as_deref())
+        .collect();
+    let mut names = HashSet::new();
+    let mut flexible = None;
+    let mut percent: u32 = 0;
+
+    for lv in &config.volumes {
+        validate_name("volume name", &lv.name)?;
+
+        if !names.insert(lv.name.as_str()) {
+            bail!("LVM volume `{}` is defined twice", lv.name);
+        }
+
+        if matches!(lv.filesystem, Filesystem::Vfat | Filesystem::Lvm) {
+            bail!("LVM volume `{}` cannot be {}", lv.name, lv.filesystem);
+        }
+
+        match lv.size {
+            SizeSpec::Percent(p) => percent += p as u32,
+            spec if spec.is_flexible() => {
+                if let Some(other) = flexible.replace(&lv.name) {
+                    bail!(
+                        "LVM volumes `{}` and `{}` both use `rest` or `-<size>`, only one may",
+                        other,
+                        lv.name
+                    );
+                }
+            }
+            _ => {}
+        }
+
+        match (&lv.mount_point, lv.filesystem) {
+            (Some(_), Filesystem::Swap) => {
+                bail!("LVM volume `{}`: swap cannot have a mount_point", lv.name)
+            }
+            (Some(mp), _) if !mp.starts_with('/') => {
+                bail!(
+                    "LVM volume `{}`: mount_point `{}` must be absolute",
+                    lv.name,
+                    mp
+                )
+            }
+            (Some(mp), _) if !mount_points.insert(mp.as_str()) => {
+                bail!(
+                    "LVM volume `{}`: mount_point `{}` is used twice",
+                    lv.name,
+                    mp
+                )
+            }
+            (None, _) if lv.mount_options.is_some() => {
+                bail!(
+                    "LVM volume `{}`: mount_options given without a mount_point",
+                    lv.name
+                )
+            }
+            _ => {}
+        }
+    }
+
+    if percent > 100 {
+        bail!("LVM volumes ask for {}% of the volume group", percent);
+    }
+
+    if !config
+        .volumes
+        .iter()
+        .any(|lv| lv.mount_point.as_deref() == Some("/"))
+    {
+        bail!("[lvm] needs a volume mounted at `/`");
+    }
+
+    Ok(())
+}
+
+// VG and LV names end up in /dev/<vg>/<lv>
+fn validate_name(what: &str, name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '+'));
+
+    if !valid {
+        bail!(
+            "Invalid LVM {} `{}` (use letters, digits, `-`, `_`, `.` or `+`)",
+            what,
+            name
+        );
+    }
+
+    Ok(())
+}
+
+// ---------------------------------------------------------
+// Volumes as planned partitions (swap detection, summaries)
+// ---------------------------------------------------------
+pub fn volume_specs(config: &LvmConfig) -> Vec<PlannedPartition> {
+    config
+        .volumes
+        .iter()
+        .map(|lv| PlannedPartition {
+            size: lv.size,
+            type_code: String::new(),
+            label: lv.name.clone(),
+            filesystem: lv.filesystem,
+            mount_point: lv.mount_point.clone(),
+            mount_options: lv.mount_options.clone(),
+        })
+        .collect()
+}
+
+// ---------------------------------------------------------
+// PV, VG and LVs; the LVs are added to `partitions` for mkfs and mount
+// ---------------------------------------------------------
+pub fn create_volumes(
+    runner: &dyn CommandRunner,
+    partitions: &mut CreatedPartitions,
+    config: &LvmConfig,
+) -> Result<LvmSetup> {
+    say!("{}", colors::header("Creating LVM Volumes"));
+
+    let pv = partitions
+        .partitions
+        .iter()
+        .find(|p| p.spec.filesystem == Filesystem::Lvm)
+        .context("No partition with filesystem = \"lvm\" was created")?;
+    let pv_device = pv.fs_device();
+    let vg = config.vg_name.as_str();
+
+    if !runner.is_dry_run() && std::path::Path::new(&format!("/dev/{}", vg)).exists() {
+        bail!(
+            "Volume group {} already exists, deactivate it or pick another vg_name",
+            vg
+        );
+    }
+
+    run_show(
+        runner,
+        Command::new("pvcreate").args(Filesystem::Lvm.mkfs_args(&pv.spec.label, &pv_device)),
+    )
+    .context("Failed to create LVM physical volume")?;
+
+    run_show(runner, Command::new("vgcreate").args([vg, &pv_device]))
+        .context("Failed to create volume group")?;
+
+    // Fixed and percentage volumes first, the flexible one takes what is left
+    let mut order: Vec<usize> = (0..config.volumes.len()).collect();
+    order.sort_by_key(|&i| config.volumes[i].size.is_flexible());
+
+    let specs = volume_specs(config);
+    let mut setup = LvmSetup {
+        vg_name: vg.to_string(),
+        pv_device: pv_device.clone(),
+        volumes: Vec::new(),
+    };
+
+    for i in order {
+        let lv = &config.volumes[i];
+        let device = format!("/dev/{}/{}", vg, lv.name);
+
+        let size_args = match lv.size {
+            SizeSpec::Bytes(bytes) => vec!["-L".to_string(), format!("{}b", bytes)],
+            SizeSpec::Percent(p) => vec!["-l".to_string(), format!("{}%VG", p)],
+            SizeSpec::Rest => vec!["-l".to_string(), "100%FREE".to_string()],
+            SizeSpec::LeaveFree(bytes) => {
+                if runner.is_dry_run() {
+                    // The VG does not exist yet, nothing to measure
+                    say!(
+                        "{}",
+                        colors::info(&format!(
+                            "[DRY RUN] Would create {} leaving {} free in {}",
+                            device, lv.size, vg
+                        ))
+                    );
+                    push_volume(&mut setup, partitions, &specs[i], device);
+                    continue;
+                }
+                vec![
+                    "-L".to_string(),
+                    format!("{}b", leave_free(runner, vg, bytes)?),
+                ]
+            }
+        };
+
+        let mut cmd = Command::new("lvcreate");
+        cmd.args(["--yes", "-n", &lv.name]).args(&size_args).arg(vg);
+        run_show(runner, &mut cmd)
+            .with_context(|| format!("Failed to create logical volume {}", lv.name))?;
+
+        push_volume(&mut setup, partitions, &specs[i], device);
+    }
+
+    if !runner.is_dry_run() {
+        say!(
+            "{}",
+            colors::success(&format!(
+                "✓ Volume group {} with {} volumes",
+                vg,
+                setup.volumes.len()
+            ))
+        );
+    }
+    say!();
+
+    Ok(setup)
+}
+
+fn push_volume(
+    setup: &mut LvmSetup,
+    partitions: &mut CreatedPartitions,
+    spec: &PlannedPartition,
+    device: String,
+) {
+    setup
+        .volumes
+        .push((spec.label.clone(), device.clone(), spec.mount_point.clone()));
+
+    partitions.partitions.push(CreatedPartition {
+        number: 0,
+        device,
+        spec: spec.clone(),
+        encryption: None,
+        reused: false,
+        raid: None,
+    });
+}
+
+// Everything free in the VG minus `bytes`, rounded down to whole extents
+fn leave_free(runner: &dyn CommandRunner, vg: &str, bytes: u64) -> Result<u64> {
+    let out = run_out(
+        runner,
+        Command::new("vgs").args([
+            "--noheadings",
+            "--units",
+            "b",
+            "--nosuffix",
+            "-o",
+            "vg_free,vg_extent_size",
+            vg,
+        ]),
+    )
+    .context("Failed to read free space of the volume group")?;
+
+    let numbers: Vec<u64> = out
+        .split_whitespace()
+        .filter_map(|n| n.parse().ok())
+        .collect();
+    let [free, extent] = numbers[..] else {
+        bail!("Unexpected vgs output: {}", out.trim());
+    };
+
+    let size = free.saturating_sub(bytes) / extent.max(1) * extent;
+    if size == 0 {
+        bail!(
+            "Volume group {} has only {} bytes free, cannot leave {} free",
+            vg,
+            free,
+            bytes
+        );
+    }
+
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::core::disk_setup::structs::LogicalVolume;
+    use crate::helpers::runner::FakeRunner;
+
+    fn volume(name: &str, size: &str, fs: Filesystem, mount: Option<&str>) -> LogicalVolume {
+        LogicalVolume {
+            name: name.to_string(),
+            size: size.parse().unwrap(),
+            filesystem: fs,
+            mount_point: mount.map(str::to_string),
+            mount_options: None,
+        }
+    }
+
+    fn config() -> LvmConfig {
+        LvmConfig {
+            vg_name: "vg0".to_string(),
+            volumes: vec![
+                volume("root", "40G", Filesystem::Ext4, Some("/")),
+                volume("home", "-10G", Filesystem::Ext4, Some("/home")),
+                volume("swap", "8G", Filesystem::Swap, None),
+            ],
+        }
+    }
+
+    fn pv_partition() -> PlannedPartition {
+        PlannedPartition {
+            size: SizeSpec::Rest,
+            type_code: "8e00".to_string(),
+            label: "LVM".to_string(),
+            filesystem: Filesystem::Lvm,
+            mount_point: None,
+            mount_options: None,
+        }
+    }
+
+    #[test]
+    fn creates_the_flexible_volume_last() {
+        let runner = FakeRunner::new().respond("vgs", "  107369988096  4194304\n");
+        let mut partitions = CreatedPartitions {
+            partitions: vec![CreatedPartition {
+                number: 2,
+                device: "/dev/sda2".to_string(),
+                spec: pv_partition(),
+                encryption: None,
+                reused: false,
+                raid: None,
+            }],
+        };
+
+        let setup = create_volumes(&runner, &mut partitions, &config()).unwrap();
+
+        assert_eq!(
+            runner.calls(),
+            [
+                "pvcreate -ff --yes /dev/sda2",
+                "vgcreate vg0 /dev/sda2",
+                "lvcreate --yes -n root -L 42949672960b vg0",
+                "lvcreate --yes -n swap -L 8589934592b vg0",
+                "vgs --noheadings --units b --nosuffix -o vg_free,vg_extent_size vg0",
+                // 100 GiB minus one 4 MiB extent free, 10 GiB of it stays free
+                "lvcreate --yes -n home -L 96632569856b vg0",
+            ]
+        );
+        assert_eq!(setup.root_device(), Some("/dev/vg0/root"));
+        assert_eq!(partitions.partitions.len(), 4);
+        assert_eq!(partitions.partitions[3].device, "/dev/vg0/home");
+    }
+
+    #[test]
+    fn rejects_broken_lvm_sections() {
+        let partitions = [pv_partition()];
+        assert!(validate_lvm(&config(), &partitions).is_ok());
+
+        let mut no_root = config();
+        no_root.volumes.remove(0);
+        assert!(validate_lvm(&no_root, &partitions).is_err());
+
+        let mut two_rests = config();
+        two_rests.volumes[2].size = SizeSpec::Rest;
+        assert!(validate_lvm(&two_rests, &partitions).is_err());
+
+        let mut bad_name = config();
+        bad_name.vg_name = "-vg".to_string();
+        assert!(validate_lvm(&bad_name, &partitions).is_err());
+
+        // The PV partition is missing
+        assert!(validate_lvm(&config(), &[]).is_err());
+    }
+}
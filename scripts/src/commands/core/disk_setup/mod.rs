@@ -1,9 +1,32 @@
+pub mod backup;
+pub mod boot;
+pub mod devices;
+pub mod encryption;
+pub mod esp;
+pub mod fstab;
 pub mod helpers;
+pub mod image;
+pub mod journal;
+pub mod layout;
+pub mod lvm;
+pub mod preflight;
+pub mod preview;
+pub mod raid;
+pub mod size;
 pub mod structs;
+pub mod subvolumes;
+pub mod swap;
+pub mod wipe;
+
+use std::path::PathBuf;
 
 use anyhow::Ok;
+use clap::ValueEnum;
 
 use crate::colors;
+use crate::helpers::ensure_tool_exists;
+use crate::helpers::runner::{CommandRunner, DryRunRunner, RealRunner};
+use crate::output;
 
 #[derive(clap::Args, Debug)]
 /// Peek inside a basket (tree object) by its ID
@@ -11,39 +34,666 @@ pub struct DiskSetupArgs {
     /// If set, do not perform any changes, just simulate
     #[clap(long)]
     pub dry_run: bool,
+
+    /// Read the disk, partitions and mount options from a TOML layout file instead of prompting
+    /// (LVM volumes are only available through a layout's `[lvm]` section)
+    #[clap(long, value_name = "FILE")]
+    pub layout: Option<PathBuf>,
+
+    /// Filesystem for the Linux partition (skips the prompt)
+    #[clap(long, value_enum, conflicts_with = "layout")]
+    pub filesystem: Option<structs::Filesystem>,
+
+    /// Swap strategy (skips the prompt)
+    #[clap(long, value_enum, conflicts_with = "layout")]
+    pub swap: Option<structs::SwapKind>,
+
+    /// UEFI, or BIOS with GPT or msdos (default: detected from the firmware)
+    #[clap(long, value_enum, conflicts_with = "layout")]
+    pub boot_mode: Option<structs::BootMode>,
+
+    /// Install onto a disk image file (attached with losetup) instead of a disk
+    #[clap(long, value_name = "PATH")]
+    pub image: Option<PathBuf>,
+
+    /// Size of the image to create, e.g. 20G or 500GB; plain numbers are MiB (only if it does not exist yet)
+    #[clap(long, requires = "image")]
+    pub size: Option<String>,
+
+    /// Also offer zram, loop devices and the live/boot medium in the disk selector
+    #[clap(long)]
+    pub all_disks: bool,
+
+    /// Add partitions even if other partitions on the disk are mounted or in use
+    #[clap(long)]
+    pub force: bool,
+
+    /// Spread a Btrfs root over more disks (asks for them; ESP and root are repeated on each)
+    #[clap(long, value_enum, value_name = "PROFILE", conflicts_with_all = ["layout", "image"])]
+    pub raid: Option<structs::RaidProfile>,
+
+    /// A reused ESP smaller than this (MB) gets an XBOOTLDR partition offered
+    #[clap(long, value_name = "MB", default_value_t = structs::EspConfig::DEFAULT_MIN_SIZE_MB, conflicts_with = "layout")]
+    pub esp_min_size: u64,
+
+    /// Clear the whole disk first; it then gets a fresh GPT (each mode asks for confirmation)
+    #[clap(long, value_enum, value_name = "MODE")]
+    pub wipe: Option<structs::WipeMode>,
+
+    /// Skip the typed confirmation before writing (needed when running non-interactively)
+    #[clap(long)]
+    pub yes: bool,
+
+    /// Where to mount the new system
+    #[clap(long, default_value = "/mnt")]
+    pub target: String,
 }
 
 pub fn handle(args: DiskSetupArgs) -> anyhow::Result<()> {
-    // Select disk
-    let disks = helpers::list_block_disks()?;
-    let chosen = helpers::select_disk_simple(&disks)?;
+    for tool in ["lsblk", "parted", "blockdev", "sgdisk"] {
+        ensure_tool_exists(tool)?;
+    }
+
+    let runner: Box<dyn CommandRunner> = if args.dry_run {
+        Box::new(DryRunRunner)
+    } else {
+        Box::new(RealRunner)
+    };
+    let runner = runner.as_ref();
+
+    let layout = match &args.layout {
+        Some(path) => Some(layout::load_layout(path)?),
+        None => None,
+    };
+
+    // Attach the image first; dropping it detaches the loop device, after Ctrl-C the journal does
+    let loop_image = match &args.image {
+        Some(path) => Some(image::attach_image(runner, path, args.size.as_deref())?),
+        None => None,
+    };
+
+    // Select disk (the image's loop device replaces the layout's disk)
+    let chosen = match &loop_image {
+        Some(img) => img.device.clone(),
+        None => {
+            let disks = helpers::list_block_disks(runner)?;
+            output::emit("disks", &disks)?;
+
+            match &layout {
+                Some(layout) => layout::resolve_disk(layout, &disks)?,
+                None => helpers::select_disk_simple(&disks, args.all_disks)?,
+            }
+        }
+    };
 
     // Check if disk has a partition table
-    let has_pt = helpers::check_partition_table(&chosen)?;
+    let has_pt = helpers::check_partition_table(runner, &chosen)?;
+
+    // Refuse to touch a disk that is mounted, swapped on or held by md/LVM/LUKS
+    preflight::check_disk(&chosen, !has_pt || args.wipe.is_some(), args.force)?;
 
-    if !has_pt {
-        // Create partition table if needed
-        helpers::create_partition_table(&chosen, args.dry_run)?;
+    // From here on Ctrl-C lets the running command finish, cleans up and records the steps
+    let journal = journal::Journal::start(runner, &chosen, &args.target)?;
+    if let Some(img) = &loop_image {
+        journal.on_interrupt(structs::CleanupAction::DetachLoop(img.device.clone()));
     }
+    let result = install(runner, &args, &layout, &chosen, has_pt, &journal);
+    journal.finish(&result);
 
-    // Show free regions (or total disk size if no free regions)
-    let free_regions = helpers::list_free_regions(&chosen)?;
-    helpers::display_free_regions(&free_regions, &chosen)?;
+    result
+}
+
+fn install(
+    runner: &dyn CommandRunner,
+    args: &DiskSetupArgs,
+    layout: &Option<structs::DiskLayout>,
+    chosen: &str,
+    has_pt: bool,
+    journal: &journal::Journal,
+) -> anyhow::Result<()> {
+    // A wiped disk, or one without a table, is planned as empty; nothing is written before the preview
+    let fresh = !has_pt || args.wipe.is_some();
+
+    if let Some(mode) = args.wipe {
+        if layout.as_ref().is_some_and(|l| !l.create_partition_table) {
+            anyhow::bail!("--wipe needs `create_partition_table = true` in the layout");
+        }
+        wipe::check_wipe(chosen, mode)?;
+    }
+
+    if !has_pt && layout.as_ref().is_some_and(|l| !l.create_partition_table) {
+        anyhow::bail!(
+            "Disk {} has no partition table; set `create_partition_table = true` in the layout",
+            chosen
+        );
+    }
+
+    if fresh
+        && layout
+            .as_ref()
+            .is_some_and(|l| l.esp.as_ref().is_some_and(|e| e.reuse.is_some()))
+    {
+        anyhow::bail!(
+            "The layout reuses an ESP, but {} gets a new partition table",
+            chosen
+        );
+    }
+
+    // UEFI gets GPT with an ESP; BIOS gets GPT with bios_grub or an msdos label
+    let existing_table = if fresh {
+        None
+    } else {
+        boot::existing_table(runner, chosen)?
+    };
+    let boot_mode = boot::choose_boot_mode(
+        args.boot_mode
+            .or_else(|| layout.as_ref().and_then(|l| l.boot_mode)),
+        existing_table,
+        layout.is_none(),
+    )?;
+
+    // Free regions as they are now; after a wipe the whole disk is free
+    let free_regions = if has_pt {
+        helpers::list_free_regions(runner, chosen)?
+    } else {
+        Vec::new()
+    };
+    let usable_regions: &[structs::FreeRegion] = if args.wipe.is_some() {
+        say!(
+            "{}\n",
+            colors::info(&format!(
+                "{} is wiped before partitioning, the new partitions get the whole disk",
+                chosen
+            ))
+        );
+        &[]
+    } else {
+        helpers::display_free_regions(runner, &free_regions, chosen)?;
+        &free_regions
+    };
+    output::emit("free_regions", usable_regions)?;
+
+    // Pick the free region the new partitions go into (the whole disk if there is none)
+    let region = helpers::select_free_region(
+        runner,
+        usable_regions,
+        chosen,
+        layout.as_ref().and_then(|l| l.free_region),
+        layout.is_none(),
+    )?;
+
+    // Existing partitions keep their numbers (dual boot), unless the disk is wiped
+    let existing = if has_pt {
+        helpers::list_partitions(runner, chosen)?
+    } else {
+        Vec::new()
+    };
+    let existing_numbers: Vec<u32> = if fresh {
+        Vec::new()
+    } else {
+        existing.iter().map(|p| p.number).collect()
+    };
 
-    // Get partition plan from user (handles both cases)
-    let plan = helpers::get_partition_plan(&free_regions, &chosen)?;
+    // Reuse an existing ESP (dual boot) or create a new one
+    let esp_choice = match boot_mode {
+        structs::BootMode::Uefi if !fresh => {
+            esp::choose_esp(runner, chosen, layout.as_ref(), args.esp_min_size)?
+        }
+        _ => structs::EspChoice::default(),
+    };
+
+    // Get partition plan from the layout or the user (handles both cases)
+    let plan = match &layout {
+        Some(layout) => layout::plan_from_layout(layout, &esp_choice, boot_mode)?,
+        None => helpers::get_partition_plan(
+            &region,
+            &esp_choice,
+            boot_mode,
+            args.filesystem,
+            args.swap,
+        )?,
+    };
+
+    // Resolve sizes to aligned sectors now, so a plan that does not fit fails before any write
+    let geometry = helpers::get_geometry(runner, chosen)?;
+    let ranges = size::solve(&plan.partitions, &region, geometry)?;
+    boot::check_table_limits(boot_mode.table(), &ranges)?;
+    let numbers = helpers::new_partition_numbers(
+        &existing_numbers,
+        plan.partitions.len(),
+        boot_mode.table(),
+    )?;
+    helpers::print_partition_plan(&plan, &ranges, geometry);
+    output::emit(
+        "partition_plan",
+        &serde_json::json!({
+            "disk": chosen,
+            "plan": &plan,
+            "placement": numbers
+                .iter()
+                .zip(&ranges)
+                .map(|(number, range)| serde_json::json!({
+                    "number": number,
+                    "sectors": range,
+                    "size_bytes": range.bytes(geometry),
+                }))
+                .collect::<Vec<_>>(),
+        }),
+    )?;
+
+    for tool in plan
+        .partitions
+        .iter()
+        .filter_map(|p| p.filesystem.mkfs_tool())
+    {
+        ensure_tool_exists(tool)?;
+    }
+    if let Some(config) = &plan.lvm {
+        for tool in ["vgcreate", "lvcreate"] {
+            ensure_tool_exists(tool)?;
+        }
+        for tool in config
+            .volumes
+            .iter()
+            .filter_map(|lv| lv.filesystem.mkfs_tool())
+        {
+            ensure_tool_exists(tool)?;
+        }
+    }
+    // A Btrfs root gets its subvolumes from `btrfs subvolume create`
+    let volumes = plan.lvm.as_ref().map(lvm::volume_specs).unwrap_or_default();
+    if plan.partitions.iter().chain(&volumes).any(|p| {
+        p.mount_point.as_deref() == Some("/") && p.filesystem == structs::Filesystem::Btrfs
+    }) {
+        ensure_tool_exists("btrfs")?;
+    }
+
+    // Ask about encryption before touching the disk
+    let encryption = encryption::get_encryption_config(layout.as_ref())?;
+    if encryption.is_some() {
+        ensure_tool_exists("cryptsetup")?;
+
+        // GRUB i386-pc cannot open LUKS2 with argon2id, the kernel has to live outside
+        let separate_boot = plan
+            .partitions
+            .iter()
+            .any(|p| p.mount_point.as_deref() == Some("/boot"));
+        if boot_mode != structs::BootMode::Uefi && !separate_boot {
+            say!(
+                "{}",
+                colors::warn(
+                    "Encrypted root without a /boot partition: GRUB cannot unlock LUKS2 (argon2id) on BIOS"
+                )
+            );
+        }
+    }
+
+    // Other disks of a Btrfs RAID get the same ESP and root, checked before anything is written
+    let raid_profile = args
+        .raid
+        .or_else(|| layout.as_ref()?.raid.as_ref().map(|r| r.profile));
+
+    let mut members = Vec::new();
+    if raid_profile.is_some() {
+        raid::validate_plan(&plan, encryption.is_some())?;
+
+        let disks = raid::select_member_disks(runner, chosen, layout.as_ref(), args.all_disks)?;
+        for (i, disk) in disks.into_iter().enumerate() {
+            let member_plan = raid::member_plan(&plan, &ranges, geometry, i);
+            let member_ranges =
+                raid::check_member(runner, &disk, &member_plan, args.wipe, args.force)?;
+            members.push((disk, member_plan, member_ranges));
+        }
+    }
+
+    // Show the disk before and after, then make the user type its name
+    let before = preview::segments_before(&existing, &free_regions);
+    let kept = if args.wipe.is_some() {
+        &[][..]
+    } else {
+        &before[..]
+    };
+    let after = preview::segments_after(kept, &region, &plan, &ranges, &numbers, geometry);
+    preview::print_preview(chosen, &before, &after);
+    preview::confirm_write(runner, chosen, args.yes)?;
+
+    for (disk, member_plan, _) in &members {
+        raid::print_member_plan(disk, member_plan);
+        preview::confirm_write(runner, disk, args.yes)?;
+    }
+
+    // Keep a copy of the old tables (`sharch disk restore` puts them back), all before the first wipe
+    let mut backups = Vec::new();
+    if has_pt {
+        backups.extend(record_backup(
+            journal,
+            backup::backup_partition_table(runner, chosen)?,
+        )?);
+    }
+    for (disk, _, _) in &members {
+        backups.extend(record_backup(
+            journal,
+            backup::backup_partition_table(runner, disk)?,
+        )?);
+    }
+
+    if let Some(mode) = args.wipe {
+        wipe::wipe_disk(runner, chosen, mode, args.yes)?;
+        journal.step(structs::InstallStep::Wiped {
+            mode: mode
+                .to_possible_value()
+                .map_or(String::new(), |v| v.get_name().to_string()),
+        })?;
+    }
+
+    // The typed confirmation above covers the new label
+    if fresh {
+        helpers::create_partition_table(runner, chosen, true, boot_mode.table())?;
+        journal.step(structs::InstallStep::TableCreated {
+            table: boot_mode.table().to_string(),
+        })?;
+    }
 
     // Create partitions
-    let partitions = helpers::create_partitions(&chosen, &plan, args.dry_run)?;
+    let mut partitions = helpers::create_partitions(runner, chosen, &plan, &ranges, &numbers)?;
+    journal.step(structs::InstallStep::PartitionsCreated {
+        devices: partitions
+            .partitions
+            .iter()
+            .filter(|p| !p.reused)
+            .map(|p| p.device.clone())
+            .collect(),
+    })?;
+
+    // Partition the other disks and let their roots join the primary's Btrfs
+    let raid_setup = match raid_profile {
+        Some(profile) => {
+            let mut created = Vec::new();
+            for (disk, member_plan, member_ranges) in &members {
+                created.push(raid::partition_member(
+                    runner,
+                    disk,
+                    member_plan,
+                    member_ranges,
+                    args.wipe,
+                    args.yes,
+                )?);
+            }
+            journal.step(structs::InstallStep::MembersPartitioned {
+                devices: created
+                    .iter()
+                    .flat_map(|c| &c.partitions)
+                    .map(|p| p.device.clone())
+                    .collect(),
+            })?;
+            Some(raid::attach_members(&mut partitions, created, profile)?)
+        }
+        None => None,
+    };
+
+    // Put the Linux partition inside a LUKS2 container
+    if let Some(config) = &encryption {
+        journal.on_interrupt(structs::CleanupAction::CloseLuks(
+            config.mapper_name.clone(),
+        ));
+        encryption::encrypt_root(runner, &mut partitions, config)?;
+
+        if let Some(part) = partitions
+            .partitions
+            .iter()
+            .find(|p| p.encryption.is_some())
+        {
+            journal.step(structs::InstallStep::Encrypted {
+                device: part.device.clone(),
+                mapper: part.fs_device(),
+            })?;
+        }
+    }
+
+    // PV on the Linux partition (or its LUKS mapper), then the VG and its LVs
+    let lvm_setup = match &plan.lvm {
+        Some(config) => {
+            journal.on_interrupt(structs::CleanupAction::DeactivateVg(config.vg_name.clone()));
+            let setup = lvm::create_volumes(runner, &mut partitions, config)?;
+            journal.step(structs::InstallStep::VolumesCreated {
+                vg: setup.vg_name.clone(),
+                volumes: setup
+                    .volumes
+                    .iter()
+                    .map(|(_, dev, _)| dev.clone())
+                    .collect(),
+            })?;
+            Some(setup)
+        }
+        None => None,
+    };
 
     // Format partitions
-    helpers::format_partitions(&partitions, args.dry_run)?;
+    helpers::format_partitions(runner, &partitions)?;
+    journal.step(structs::InstallStep::Formatted {
+        devices: partitions
+            .partitions
+            .iter()
+            .filter(|p| {
+                !p.reused
+                    && p.spec.filesystem.mkfs_tool().is_some()
+                    && p.spec.filesystem != structs::Filesystem::Lvm
+            })
+            .map(|p| p.fs_device())
+            .collect(),
+    })?;
+
+    // Create subvolumes and mount everything under the target
+    let has_root = partitions
+        .partitions
+        .iter()
+        .any(|p| p.spec.mount_point.as_deref() == Some("/"));
+
+    let mount_tree = if has_root {
+        journal.on_interrupt(structs::CleanupAction::Unmount(args.target.clone()));
+        let tree = subvolumes::mount_target(
+            runner,
+            &partitions,
+            layout.as_ref().map(|l| l.subvolumes.as_slice()),
+            &args.target,
+        )?;
+        journal.step(structs::InstallStep::Mounted {
+            target: tree.root.clone(),
+        })?;
+        Some(tree)
+    } else {
+        None
+    };
 
-    println!("{}", colors::success("Disk setup completed successfully!"));
-    println!();
-    println!("{}", colors::info("Partition Details:"));
-    println!("  EFI:   {} (FAT32)", partitions.efi_partition);
-    println!("  Linux: {} (Btrfs)", partitions.linux_partition);
+    if let (Some(setup), Some(tree)) = (&raid_setup, &mount_tree) {
+        raid::install_esp_sync_hook(runner, setup, tree)?;
+    }
+
+    // Swap partition, swapfile or zram
+    if let Some(part) = partitions
+        .partitions
+        .iter()
+        .find(|p| p.spec.filesystem == structs::Filesystem::Swap)
+    {
+        journal.on_interrupt(structs::CleanupAction::Swapoff(part.device.clone()));
+    }
+    let swap = swap::setup_swap(runner, &plan, &partitions, mount_tree.as_ref())?;
+    if let Some(swap) = &swap {
+        journal.step(structs::InstallStep::SwapEnabled {
+            kind: format!("{:?}", swap.kind).to_lowercase(),
+        })?;
+    }
+
+    // fstab and crypttab with the exact options used above, instead of genfstab
+    let mut written = Vec::new();
+    if let Some(tree) = &mount_tree {
+        let assume_yes = args.yes || layout.is_some();
+        written.push(fstab::write_fstab(runner, tree, swap.as_ref(), assume_yes)?);
+        written.extend(fstab::write_crypttab(
+            runner,
+            &partitions,
+            tree,
+            assume_yes,
+        )?);
+        journal.step(structs::InstallStep::FilesWritten {
+            paths: written.clone(),
+        })?;
+    }
+
+    output::emit("created_partitions", &partitions)?;
+    if let Some(setup) = &lvm_setup {
+        output::emit(
+            "lvm_setup",
+            &serde_json::json!({
+                "setup": setup,
+                "mkinitcpio_hook": structs::LvmSetup::MKINITCPIO_HOOK,
+                "kernel_cmdline": setup.kernel_cmdline(),
+            }),
+        )?;
+    }
+    if let Some(setup) = &raid_setup {
+        output::emit(
+            "raid_setup",
+            &serde_json::json!({
+                "setup": setup,
+                "mkinitcpio_hook": structs::RaidSetup::MKINITCPIO_HOOK,
+                "degraded_cmdline": setup.degraded_cmdline(),
+            }),
+        )?;
+    }
+
+    say!("{}", colors::success("Disk setup completed successfully!"));
+    say!();
+    say!("{}", colors::info("Partition Details:"));
+    for part in &partitions.partitions {
+        say!(
+            "  {}: {} ({}{})",
+            part.spec.label,
+            part.device,
+            part.spec.filesystem,
+            if part.reused { ", reused" } else { "" }
+        );
+
+        // With LVM the root is a logical volume inside the container
+        if let Some(vol) = &part.encryption {
+            say!("    LUKS2 {} -> {}", vol.luks_uuid, vol.mapper_path());
+            match lvm_setup.as_ref().and_then(|l| l.kernel_cmdline()) {
+                Some(root) => say!("    Kernel cmdline: {} {}", vol.luks_cmdline(), root),
+                None => say!("    Kernel cmdline: {}", vol.kernel_cmdline()),
+            }
+            say!("    mkinitcpio HOOKS: add {}", encryption::MKINITCPIO_HOOK);
+        }
+    }
+
+    if let Some(setup) = &lvm_setup {
+        say!();
+        say!(
+            "{}",
+            colors::info(&format!(
+                "LVM volume group {} on {}:",
+                setup.vg_name, setup.pv_device
+            ))
+        );
+        for (name, device, mount_point) in &setup.volumes {
+            say!(
+                "  {}: {} ({})",
+                name,
+                device,
+                mount_point.as_deref().unwrap_or("not mounted")
+            );
+        }
+        if let Some(root) = setup.kernel_cmdline() {
+            say!("  Kernel cmdline: {}", root);
+        }
+        say!(
+            "  mkinitcpio HOOKS: add {} (after {} when encrypted)",
+            structs::LvmSetup::MKINITCPIO_HOOK,
+            encryption::MKINITCPIO_HOOK
+        );
+    }
+
+    if let Some(tree) = mount_tree {
+        say!();
+        say!("{}", colors::info(&format!("Mounted under {}:", tree.root)));
+        for m in &tree.mounts {
+            say!(
+                "  {} -> {} ({}, {})",
+                m.source,
+                subvolumes::target_path(&tree.root, &m.mount_point),
+                m.fstype,
+                m.options
+            );
+        }
+    }
+
+    say!();
+    say!(
+        "{}",
+        colors::info(&format!("Boot mode: {}", boot_mode.describe()))
+    );
+    if boot_mode != structs::BootMode::Uefi {
+        say!(
+            "  Bootloader: grub-install --target={} {}",
+            boot_mode.grub_target(),
+            chosen
+        );
+    }
+
+    if let Some(setup) = &raid_setup {
+        say!();
+        say!(
+            "{}",
+            colors::info(&format!(
+                "Btrfs {}: {}",
+                setup.profile.as_str(),
+                setup.devices.join(", ")
+            ))
+        );
+        say!(
+            "  mkinitcpio HOOKS: add {} (not needed with the systemd hook)",
+            structs::RaidSetup::MKINITCPIO_HOOK
+        );
+        if let Some(param) = setup.degraded_cmdline() {
+            say!("  Fallback boot entry only: {}", param);
+        }
+        for (device, mount_point) in &setup.esp_mirrors {
+            say!("  ESP mirror: {} -> {} (needs rsync)", device, mount_point);
+        }
+    }
+
+    if let Some(swap) = swap {
+        say!();
+        say!("{}", colors::info(&format!("Swap: {:?}", swap.kind)));
+        if let Some(line) = swap.fstab_line() {
+            say!("  fstab: {}", line);
+        }
+    }
+
+    if !written.is_empty() {
+        say!();
+        say!("{}", colors::info("Generated files:"));
+        for path in &written {
+            say!("  {}", path);
+        }
+    }
+
+    if !backups.is_empty() {
+        say!();
+        say!("{}", colors::info("Previous partition tables:"));
+        for path in &backups {
+            say!("  {}", path.display());
+        }
+    }
 
     Ok(())
 }
+
+fn record_backup(
+    journal: &journal::Journal,
+    backup: Option<PathBuf>,
+) -> anyhow::Result<Option<PathBuf>> {
+    if let Some(path) = &backup {
+        journal.step(structs::InstallStep::TableBackedUp { path: path.clone() })?;
+    }
+    Ok(backup)
+}
@@ -0,0 +1,382 @@
+use anyhow::{Context, Result, bail};
+use std::path::Path;
+
+use crate::colors;
+use crate::commands::core::disk_setup::devices;
+use crate::commands::core::disk_setup::structs::DiskUse;
+use crate::commands::core::disk_setup::structs::DiskUseKind;
+
+/// Mount points that mean "this is what we booted from"
+const BOOT_MOUNTS: [&str; 2] = ["/", "/run/archiso"];
+
+// ---------------------------------------------------------
+// Refuse to touch a disk that is in use
+// ---------------------------------------------------------
+pub fn check_disk(disk_path: &str, new_table: bool, force: bool) -> Result<()> {
+    say!("{}", colors::header("Pre-flight Checks"));
+
+    let disk = devices::kernel_name(disk_path)?;
+    let mounts = std::fs::read_to_string("/proc/mounts").context("Failed to read /proc/mounts")?;
+    let swaps = std::fs::read_to_string("/proc/swaps").context("Failed to read /proc/swaps")?;
+    let uses = disk_usage(&SysBlock, &disk, &mounts, &swaps);
+
+    if uses.is_empty() {
+        say!(
+            "{}\n",
+            colors::success(&format!("✓ Nothing on {} is in use", disk_path))
+        );
+        return Ok(());
+    }
+
+    for u in &uses {
+        say!(
+            "  {} {}",
+            colors::highlight(&format!("/dev/{}", u.device)),
+            u.describe()
+        );
+    }
+    say!();
+
+    check_uses(disk_path, &uses, new_table, force)?;
+
+    say!(
+        "{}\n",
+        colors::warn("Continuing because of --force, busy partitions are left untouched")
+    );
+
+    Ok(())
+}
+
+// Ok only if everything in use may be left alone and --force says so
+fn check_uses(disk_path: &str, uses: &[DiskUse], new_table: bool, force: bool) -> Result<()> {
+    if uses
+        .iter()
+        .any(|u| matches!(u.kind, DiskUseKind::BootMedium(_)))
+    {
+        bail!(
+            "Refusing to partition {}: it holds the running system or live ISO, pick another disk",
+            disk_path
+        );
+    }
+
+    if uses.iter().any(|u| !u.overridable(new_table)) {
+        let reason = if new_table {
+            "a new partition table would destroy what is using it"
+        } else {
+            "the whole disk is in use, not just some partitions"
+        };
+
+        bail!(
+            "Refusing to partition {}: {}. Unmount, swapoff or deactivate it first",
+            disk_path,
+            reason
+        );
+    }
+
+    if !force {
+        bail!(
+            "Partitions on {} are in use. They are left untouched, pass --force to add partitions anyway",
+            disk_path
+        );
+    }
+
+    Ok(())
+}
+
+// ---------------------------------------------------------
+// Everything that uses the disk or one of its partitions
+// ---------------------------------------------------------
+// `mounts` and `swaps` are the contents of /proc/mounts and /proc/swaps
+pub fn disk_usage(tree: &dyn BlockTree, disk: &str, mounts: &str, swaps: &str) -> Vec<DiskUse> {
+    let partitions = tree.partitions(disk);
+    let on_disk = |name: &str| name == disk || partitions.iter().any(|p| p == name);
+
+    let mut uses = Vec::new();
+    let mut push = |device: String, kind: DiskUseKind| {
+        let is_partition = device != disk;
+        uses.push(DiskUse {
+            device,
+            is_partition,
+            kind,
+        });
+    };
+
+    // Mounts, following dm/md stacks down to the partitions below them
+    for line in mounts.lines() {
+        let mut fields = line.split_whitespace();
+        let (Some(source), Some(mount_point)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let mount_point = unescape_mount(mount_point);
+
+        for node in underlying_devices(tree, source) {
+            if !on_disk(&node) {
+                continue;
+            }
+
+            let kind = if is_boot_mount(&mount_point) {
+                DiskUseKind::BootMedium(mount_point.clone())
+            } else {
+                DiskUseKind::Mounted(mount_point.clone())
+            };
+            push(node, kind);
+        }
+    }
+
+    // Active swap (the header line is skipped)
+    for line in swaps.lines().skip(1) {
+        let Some(source) = line.split_whitespace().next() else {
+            continue;
+        };
+
+        for node in underlying_devices(tree, &unescape_mount(source)) {
+            if on_disk(&node) {
+                push(node, DiskUseKind::Swap);
+            }
+        }
+    }
+
+    // md arrays, LVM PVs and open LUKS containers show up as holders
+    for node in std::iter::once(disk.to_string()).chain(partitions.iter().cloned()) {
+        for holder in tree.holders(&node) {
+            push(
+                node.clone(),
+                DiskUseKind::Holder(describe_holder(tree, &holder)),
+            );
+        }
+    }
+
+    uses
+}
+
+/// `/` or anything the live ISO mounted from its medium
+pub fn is_boot_mount(mount_point: &str) -> bool {
+    BOOT_MOUNTS
+        .iter()
+        .any(|b| mount_point == *b || mount_point.starts_with(&format!("{}/", b)))
+}
+
+// ---------------------------------------------------------
+// How block devices sit on each other (sysfs, faked in tests)
+// ---------------------------------------------------------
+pub trait BlockTree {
+    /// Kernel name behind a /dev path, e.g. /dev/mapper/root -> dm-0
+    fn kernel_name(&self, path: &str) -> Option<String>;
+    /// Kernel names of the disk's partitions
+    fn partitions(&self, disk: &str) -> Vec<String>;
+    /// Devices `name` is built on (dm and md)
+    fn slaves(&self, name: &str) -> Vec<String>;
+    /// Devices built on top of `name`
+    fn holders(&self, name: &str) -> Vec<String>;
+    /// device-mapper name of a dm-N device
+    fn dm_name(&self, name: &str) -> Option<String>;
+}
+
+pub struct SysBlock;
+
+impl BlockTree for SysBlock {
+    fn kernel_name(&self, path: &str) -> Option<String> {
+        devices::kernel_name(path).ok()
+    }
+
+    fn partitions(&self, disk: &str) -> Vec<String> {
+        sys_entries(&format!("/sys/class/block/{}", disk))
+            .into_iter()
+            .filter(|name| Path::new(&format!("/sys/class/block/{}/partition", name)).exists())
+            .collect()
+    }
+
+    fn slaves(&self, name: &str) -> Vec<String> {
+        sys_entries(&format!("/sys/class/block/{}/slaves", name))
+    }
+
+    fn holders(&self, name: &str) -> Vec<String> {
+        sys_entries(&format!("/sys/class/block/{}/holders", name))
+    }
+
+    fn dm_name(&self, name: &str) -> Option<String> {
+        std::fs::read_to_string(format!("/sys/class/block/{}/dm/name", name))
+            .ok()
+            .map(|n| n.trim().to_string())
+    }
+}
+
+// /dev/mapper/root -> dm-0 -> [sda2]; plain partitions map to themselves
+fn underlying_devices(tree: &dyn BlockTree, source: &str) -> Vec<String> {
+    if !source.starts_with("/dev/") {
+        return Vec::new();
+    }
+
+    match tree.kernel_name(source) {
+        Some(name) => slaves_of(tree, &name),
+        None => Vec::new(),
+    }
+}
+
+fn slaves_of(tree: &dyn BlockTree, name: &str) -> Vec<String> {
+    let slaves = tree.slaves(name);
+    if slaves.is_empty() {
+        return vec![name.to_string()];
+    }
+
+    slaves.iter().flat_map(|s| slaves_of(tree, s)).collect()
+}
+
+// "device-mapper `cryptroot`", "RAID array /dev/md0"
+fn describe_holder(tree: &dyn BlockTree, holder: &str) -> String {
+    match tree.dm_name(holder) {
+        Some(name) => format!("device-mapper `{}` (LVM/LUKS)", name),
+        None if holder.starts_with("md") => format!("RAID array /dev/{}", holder),
+        None => format!("/dev/{}", holder),
+    }
+}
+
+fn sys_entries(dir: &str) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut names: Vec<String> = entries
+        .flatten()
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+// /proc/mounts escapes spaces and friends as octal (\040)
+fn unescape_mount(field: &str) -> String {
+    field
+        .replace("\\040", " ")
+        .replace("\\011", "\t")
+        .replace("\\012", "\n")
+        .replace("\\134", "\\")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct FakeTree {
+        links: HashMap<&'static str, &'static str>,
+        partitions: HashMap<&'static str, Vec<String>>,
+        slaves: HashMap<&'static str, Vec<String>>,
+        holders: HashMap<&'static str, Vec<String>>,
+    }
+
+    impl BlockTree for FakeTree {
+        fn kernel_name(&self, path: &str) -> Option<String> {
+            match self.links.get(path) {
+                Some(name) => Some(name.to_string()),
+                None => path.strip_prefix("/dev/").map(str::to_string),
+            }
+        }
+
+        fn partitions(&self, disk: &str) -> Vec<String> {
+            self.partitions.get(disk).cloned().unwrap_or_default()
+        }
+
+        fn slaves(&self, name: &str) -> Vec<String> {
+            self.slaves.get(name).cloned().unwrap_or_default()
+        }
+
+        fn holders(&self, name: &str) -> Vec<String> {
+            self.holders.get(name).cloned().unwrap_or_default()
+        }
+
+        fn dm_name(&self, name: &str) -> Option<String> {
+            (name == "dm-0").then(|| "cryptroot".to_string())
+        }
+    }
+
+    // sda: sda1 and sda2 with LUKS on it (dm-0 "cryptroot"); sdb: sdb1
+    fn tree() -> FakeTree {
+        FakeTree {
+            links: HashMap::from([("/dev/mapper/cryptroot", "dm-0")]),
+            partitions: HashMap::from([
+                ("sda", vec!["sda1".to_string(), "sda2".to_string()]),
+                ("sdb", vec!["sdb1".to_string()]),
+            ]),
+            slaves: HashMap::from([("dm-0", vec!["sda2".to_string()])]),
+            holders: HashMap::from([("sda2", vec!["dm-0".to_string()])]),
+        }
+    }
+
+    const SWAPS: &str = "Filename\tType\tSize\tUsed\tPriority\n";
+
+    fn described(uses: &[DiskUse]) -> Vec<String> {
+        uses.iter()
+            .map(|u| format!("{} {}", u.device, u.describe()))
+            .collect()
+    }
+
+    #[test]
+    fn follows_mapper_devices_to_their_partition() {
+        let mounts = "\
+/dev/mapper/cryptroot /mnt/my\\040disk btrfs rw 0 0
+/dev/sda1 /mnt/my\\040disk/boot vfat rw 0 0
+/dev/sdb1 /run/archiso/bootmnt iso9660 ro 0 0
+proc /proc proc rw 0 0
+";
+
+        assert_eq!(
+            described(&disk_usage(&tree(), "sda", mounts, SWAPS)),
+            [
+                "sda2 is mounted at /mnt/my disk",
+                "sda1 is mounted at /mnt/my disk/boot",
+                "sda2 is in use by device-mapper `cryptroot` (LVM/LUKS)",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_swap_on_the_disk() {
+        let swaps = format!(
+            "{}/dev/sda1 partition 8388604 0 -2\n/swapfile file 1024 0 -3\n",
+            SWAPS
+        );
+        let uses = disk_usage(&tree(), "sda", "", &swaps);
+
+        assert_eq!(
+            described(&uses),
+            [
+                "sda1 is active swap",
+                "sda2 is in use by device-mapper `cryptroot` (LVM/LUKS)",
+            ]
+        );
+    }
+
+    #[test]
+    fn refuses_the_boot_medium_even_with_force() {
+        let mounts = "/dev/sdb1 /run/archiso/bootmnt iso9660 ro 0 0\n";
+        let uses = disk_usage(&tree(), "sdb", mounts, SWAPS);
+
+        assert!(matches!(uses[0].kind, DiskUseKind::BootMedium(_)));
+        assert!(check_uses("/dev/sdb", &uses, false, true).is_err());
+    }
+
+    #[test]
+    fn only_busy_partitions_can_be_forced() {
+        let mounts = "/dev/sda1 /mnt/boot vfat rw 0 0\n";
+        let uses = disk_usage(&tree(), "sda", mounts, SWAPS);
+
+        // Adding partitions next to it is fine with --force
+        assert!(check_uses("/dev/sda", &uses, false, false).is_err());
+        assert!(check_uses("/dev/sda", &uses, false, true).is_ok());
+
+        // A new partition table would pull it away
+        assert!(check_uses("/dev/sda", &uses, true, true).is_err());
+
+        // The whole disk in use is never overridable
+        let whole = disk_usage(&tree(), "sdb", "/dev/sdb /mnt/data ext4 rw 0 0\n", SWAPS);
+        assert!(!whole[0].is_partition);
+        assert!(check_uses("/dev/sdb", &whole, false, true).is_err());
+    }
+
+    #[test]
+    fn unescapes_octal_mount_points() {
+        assert_eq!(unescape_mount("/mnt/a\\040b\\011c"), "/mnt/a b\tc");
+        assert_eq!(unescape_mount("/mnt/back\\134slash"), "/mnt/back\\slash");
+    }
+}
@@ -0,0 +1,412 @@
+use anyhow::{Context, Result, bail};
+use dialoguer::Input;
+use std::io::IsTerminal;
+
+use crate::colors;
+use crate::commands::core::disk_setup::devices;
+use crate::commands::core::disk_setup::size;
+use crate::commands::core::disk_setup::structs::DiskSegment;
+use crate::commands::core::disk_setup::structs::ExistingPartition;
+use crate::commands::core::disk_setup::structs::FreeRegion;
+use crate::commands::core::disk_setup::structs::Geometry;
+use crate::commands::core::disk_setup::structs::PartitionPlan;
+use crate::commands::core::disk_setup::structs::SectorRange;
+use crate::commands::core::disk_setup::structs::SegmentKind;
+use crate::helpers::CommandRunner;
+
+/// Characters between the bar's `|` borders
+const BAR_WIDTH: usize = 64;
+/// Gaps smaller than this (alignment slack) are not worth a segment
+const MIN_FREE_BYTES: u64 = 1_048_576;
+
+// ---------------------------------------------------------
+// Disk as it is now: partitions and free regions, by offset
+// ---------------------------------------------------------
+pub fn segments_before(existing: &[ExistingPartition], free: &[FreeRegion]) -> Vec<DiskSegment> {
+    let mut segments: Vec<DiskSegment> = existing
+        .iter()
+        .map(|p| DiskSegment {
+            kind: SegmentKind::Existing,
+            number: Some(p.number),
+            start_bytes: p.start_bytes,
+            size_bytes: p.size_bytes,
+            filesystem: p.filesystem.clone(),
+            label: p.name.clone(),
+        })
+        .chain(
+            free.iter()
+                .map(|r| free_segment(r.start_bytes, r.size_bytes)),
+        )
+        .collect();
+
+    segments.sort_by_key(|s| s.start_bytes);
+    segments
+}
+
+// ---------------------------------------------------------
+// Disk after the plan: the chosen region split into new partitions
+// ---------------------------------------------------------
+pub fn segments_after(
+    before: &[DiskSegment],
+    region: &FreeRegion,
+    plan: &PartitionPlan,
+    ranges: &[SectorRange],
+    numbers: &[u32],
+    geometry: Geometry,
+) -> Vec<DiskSegment> {
+    let sector = geometry.logical;
+    let region_end = region.end_bytes + 1;
+    let holds_region = |s: &DiskSegment| {
+        s.kind == SegmentKind::Free
+            && s.start_bytes <= region.start_bytes
+            && region_end <= s.start_bytes + s.size_bytes
+    };
+
+    // A fresh or wiped disk has no free segment yet, the whole region becomes one
+    let mut segments = before.to_vec();
+    if !segments.iter().any(holds_region) {
+        segments.push(free_segment(
+            region.start_bytes,
+            region_end - region.start_bytes,
+        ));
+        segments.sort_by_key(|s| s.start_bytes);
+    }
+
+    let mut after = Vec::new();
+
+    for segment in &segments {
+        if !holds_region(segment) {
+            after.push(segment.clone());
+            continue;
+        }
+
+        let mut cursor = segment.start_bytes;
+        for ((part, range), number) in plan.partitions.iter().zip(ranges).zip(numbers) {
+            let start = range.start * sector;
+            if start - cursor >= MIN_FREE_BYTES {
+                after.push(free_segment(cursor, start - cursor));
+            }
+
+            after.push(DiskSegment {
+                kind: SegmentKind::New,
+                number: Some(*number),
+                start_bytes: start,
+                size_bytes: range.bytes(geometry),
+                filesystem: part.filesystem.to_string(),
+                label: part.label.clone(),
+            });
+            cursor = (range.end + 1) * sector;
+        }
+
+        let segment_end = segment.start_bytes + segment.size_bytes;
+        if segment_end.saturating_sub(cursor) >= MIN_FREE_BYTES {
+            after.push(free_segment(cursor, segment_end - cursor));
+        }
+    }
+
+    after
+}
+
+fn free_segment(start_bytes: u64, size_bytes: u64) -> DiskSegment {
+    DiskSegment {
+        kind: SegmentKind::Free,
+        number: None,
+        start_bytes,
+        size_bytes,
+        filesystem: String::new(),
+        label: String::new(),
+    }
+}
+
+// ---------------------------------------------------------
+// Segments drawn to scale, every segment at least one character
+// ---------------------------------------------------------
+pub fn render_bar(segments: &[DiskSegment], width: usize) -> String {
+    let total: u64 = segments.iter().map(|s| s.size_bytes).sum();
+    if total == 0 || segments.is_empty() {
+        return String::new();
+    }
+
+    let mut widths: Vec<usize> = segments
+        .iter()
+        .map(|s| ((s.size_bytes as u128 * width as u128 / total as u128) as usize).max(1))
+        .collect();
+
+    // Rounding and the one-character minimum can overshoot; the biggest segments give and take
+    let widest = |widths: &[usize]| {
+        (0..widths.len())
+            .max_by_key(|&i| widths[i])
+            .unwrap_or_default()
+    };
+    while widths.iter().sum::<usize>() > width.max(segments.len()) {
+        let i = widest(&widths);
+        widths[i] -= 1;
+    }
+    while widths.iter().sum::<usize>() < width {
+        let i = widest(&widths);
+        widths[i] += 1;
+    }
+
+    segments
+        .iter()
+        .zip(widths)
+        .map(|(s, w)| s.kind.symbol().to_string().repeat(w))
+        .collect()
+}
+
+// ---------------------------------------------------------
+// Bars and before/after tables
+// ---------------------------------------------------------
+pub fn print_preview(disk_path: &str, before: &[DiskSegment], after: &[DiskSegment]) {
+    say!("{}", colors::header(&format!("Preview of {}", disk_path)));
+    say!(
+        "  {} existing   {} new   {} free",
+        SegmentKind::Existing.symbol(),
+        SegmentKind::New.symbol(),
+        SegmentKind::Free.symbol()
+    );
+    say!();
+
+    say!("  Before |{}|", render_bar(before, BAR_WIDTH));
+    say!(
+        "  After  |{}|",
+        colors::success(&render_bar(after, BAR_WIDTH))
+    );
+    say!();
+
+    say!("{}", colors::info("Before:"));
+    print_segment_table(before);
+    say!("{}", colors::info("After:"));
+    print_segment_table(after);
+}
+
+fn print_segment_table(segments: &[DiskSegment]) {
+    say!("  #    Start        Size         Filesystem Label");
+
+    for segment in segments {
+        let number = match segment.number {
+            Some(n) => n.to_string(),
+            None => "-".to_string(),
+        };
+        let (filesystem, label) = match segment.kind {
+            SegmentKind::Free => ("free", ""),
+            _ => (segment.filesystem.as_str(), segment.label.as_str()),
+        };
+
+        let row = format!(
+            "  {:<4} {:<12} {:<12} {:<10} {}",
+            number,
+            size::format_size(segment.start_bytes),
+            size::format_size(segment.size_bytes),
+            filesystem,
+            label
+        );
+
+        match segment.kind {
+            SegmentKind::New => say!("{}  {}", colors::success(&row), colors::success("(new)")),
+            _ => say!("{}", row),
+        }
+    }
+    say!();
+}
+
+// ---------------------------------------------------------
+// Last gate before the partition table is written
+// ---------------------------------------------------------
+pub fn confirm_write(runner: &dyn CommandRunner, disk_path: &str, assume_yes: bool) -> Result<()> {
+    let name = devices::kernel_name(disk_path)?;
+
+    if runner.is_dry_run() {
+        say!(
+            "{}",
+            colors::info(&format!(
+                "[DRY RUN] Would ask to type `{}` to continue",
+                name
+            ))
+        );
+        return Ok(());
+    }
+
+    if assume_yes {
+        say!(
+            "{}",
+            colors::warn(&format!(
+                "Writing to {} without confirmation (--yes)",
+                disk_path
+            ))
+        );
+        return Ok(());
+    }
+
+    if !std::io::stdin().is_terminal() {
+        bail!(
+            "Refusing to write to {} without confirmation, pass --yes when running non-interactively",
+            disk_path
+        );
+    }
+
+    let typed: String = Input::new()
+        .with_prompt(colors::warn(&format!(
+            "Type `{}` to write the new partitions to {}",
+            name, disk_path
+        )))
+        .allow_empty(true)
+        .interact_text()
+        .context("Failed to get confirmation")?;
+
+    if typed.trim() != name {
+        bail!("Confirmation did not match `{}`, nothing was written", name);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::core::disk_setup::structs::BootMode;
+    use crate::commands::core::disk_setup::structs::Filesystem;
+    use crate::commands::core::disk_setup::structs::PlannedPartition;
+
+    const MIB: u64 = 1_048_576;
+    const GIB: u64 = 1 << 30;
+
+    const GEOMETRY: Geometry = Geometry {
+        logical: 512,
+        physical: 4096,
+    };
+
+    fn existing(number: u32, start: u64, size: u64) -> ExistingPartition {
+        ExistingPartition {
+            number,
+            start_bytes: start,
+            end_bytes: start + size - 1,
+            size_bytes: size,
+            filesystem: "ntfs".to_string(),
+            name: "Windows".to_string(),
+        }
+    }
+
+    // 1 MiB gap, 100 MiB ESP, 40 GiB Windows, then 60 GiB free
+    fn dual_boot() -> (Vec<DiskSegment>, FreeRegion) {
+        let region = FreeRegion {
+            start: String::new(),
+            end: String::new(),
+            size: String::new(),
+            start_bytes: MIB + 100 * MIB + 40 * GIB,
+            end_bytes: MIB + 100 * MIB + 100 * GIB - 1,
+            size_bytes: 60 * GIB,
+        };
+        let partitions = [
+            existing(1, MIB, 100 * MIB),
+            existing(2, 101 * MIB, 40 * GIB),
+        ];
+
+        (
+            segments_before(&partitions, std::slice::from_ref(&region)),
+            region,
+        )
+    }
+
+    fn plan(sizes: &[&str]) -> PartitionPlan {
+        PartitionPlan {
+            partitions: sizes
+                .iter()
+                .map(|size| PlannedPartition {
+                    size: size.parse().unwrap(),
+                    type_code: "8300".to_string(),
+                    label: "ROOT".to_string(),
+                    filesystem: Filesystem::Ext4,
+                    mount_point: None,
+                    mount_options: None,
+                })
+                .collect(),
+            reuse_esp: None,
+            swap: None,
+            lvm: None,
+            boot_mode: BootMode::Uefi,
+        }
+    }
+
+    #[test]
+    fn bar_fills_the_width_and_keeps_small_partitions_visible() {
+        let (before, _) = dual_boot();
+        let bar = render_bar(&before, 40);
+
+        assert_eq!(bar.chars().count(), 40);
+        // The 100 MiB ESP is far below one character but still drawn
+        assert!(bar.starts_with("##"));
+        assert!(bar.ends_with('.'));
+        assert_eq!(bar.chars().filter(|c| *c == '.').count(), 24);
+    }
+
+    #[test]
+    fn new_partitions_replace_the_chosen_region() {
+        let (before, region) = dual_boot();
+        let plan = plan(&["8G", "-10G"]);
+        let ranges = size::solve(&plan.partitions, &region, GEOMETRY).unwrap();
+
+        let after = segments_after(&before, &region, &plan, &ranges, &[3, 4], GEOMETRY);
+        let kinds: Vec<SegmentKind> = after.iter().map(|s| s.kind).collect();
+
+        assert_eq!(
+            kinds,
+            [
+                SegmentKind::Existing,
+                SegmentKind::Existing,
+                SegmentKind::New,
+                SegmentKind::New,
+                SegmentKind::Free
+            ]
+        );
+        assert_eq!(after[2].number, Some(3));
+        assert_eq!(after[2].size_bytes, 8 * GIB);
+        assert_eq!(after[4].size_bytes, 10 * GIB);
+    }
+
+    #[test]
+    fn fresh_disks_show_the_new_partitions_too() {
+        // No partitions and no free region from parted, the region covers the whole disk
+        let region = FreeRegion {
+            start: String::new(),
+            end: String::new(),
+            size: String::new(),
+            start_bytes: MIB,
+            end_bytes: 100 * GIB - MIB - 1,
+            size_bytes: 100 * GIB - 2 * MIB,
+        };
+        let plan = plan(&["1G", "rest"]);
+        let ranges = size::solve(&plan.partitions, &region, GEOMETRY).unwrap();
+
+        let after = segments_after(&[], &region, &plan, &ranges, &[1, 2], GEOMETRY);
+        let kinds: Vec<SegmentKind> = after.iter().map(|s| s.kind).collect();
+
+        assert_eq!(kinds, [SegmentKind::New, SegmentKind::New]);
+        assert_eq!(after[0].start_bytes, MIB);
+        assert_eq!(after[1].size_bytes, 100 * GIB - 2 * MIB - GIB);
+    }
+
+    #[test]
+    fn region_inside_a_larger_free_segment_keeps_the_gap() {
+        let before = [free_segment(17_408, 100 * GIB - 17_408)];
+        let region = FreeRegion {
+            start: String::new(),
+            end: String::new(),
+            size: String::new(),
+            start_bytes: 10 * GIB,
+            end_bytes: 100 * GIB - 1,
+            size_bytes: 90 * GIB,
+        };
+        let plan = plan(&["8G"]);
+        let ranges = size::solve(&plan.partitions, &region, GEOMETRY).unwrap();
+
+        let after = segments_after(&before, &region, &plan, &ranges, &[1], GEOMETRY);
+        let kinds: Vec<SegmentKind> = after.iter().map(|s| s.kind).collect();
+
+        assert_eq!(
+            kinds,
+            [SegmentKind::Free, SegmentKind::New, SegmentKind::Free]
+        );
+        assert_eq!(after[1].start_bytes, 10 * GIB);
+    }
+}
@@ -0,0 +1,528 @@
+use anyhow::{Context, Result, bail};
+use dialoguer::Confirm;
+
+use crate::colors;
+use crate::commands::core::disk_setup::helpers;
+use crate::commands::core::disk_setup::layout;
+use crate::commands::core::disk_setup::preflight;
+use crate::commands::core::disk_setup::size;
+use crate::commands::core::disk_setup::structs::BootMode;
+use crate::commands::core::disk_setup::structs::CreatedPartitions;
+use crate::commands::core::disk_setup::structs::Disk;
+use crate::commands::core::disk_setup::structs::DiskLayout;
+use crate::commands::core::disk_setup::structs::Filesystem;
+use crate::commands::core::disk_setup::structs::FreeRegion;
+use crate::commands::core::disk_setup::structs::Geometry;
+use crate::commands::core::disk_setup::structs::MountTree;
+use crate::commands::core::disk_setup::structs::PartitionPlan;
+use crate::commands::core::disk_setup::structs::RaidMembers;
+use crate::commands::core::disk_setup::structs::RaidProfile;
+use crate::commands::core::disk_setup::structs::RaidSetup;
+use crate::commands::core::disk_setup::structs::SectorRange;
+use crate::commands::core::disk_setup::structs::SizeSpec;
+use crate::commands::core::disk_setup::structs::SwapKind;
+use crate::commands::core::disk_setup::structs::WipeMode;
+use crate::commands::core::disk_setup::subvolumes;
+use crate::commands::core::disk_setup::wipe;
+use crate::helpers::CommandRunner;
+
+/// Copies the primary ESP to the mirrors after every kernel/bootloader change
+const ESP_SYNC_HOOK: &str = "etc/pacman.d/hooks/95-sharch-esp-mirror.hook";
+
+/// Room GPT needs at both ends of a fresh disk, rounded up to the 1 MiB alignment
+const GPT_RESERVED_BYTES: u64 = 1_048_576;
+
+// ---------------------------------------------------------
+// Can this plan be repeated on other disks?
+// ---------------------------------------------------------
+pub fn validate_plan(plan: &PartitionPlan, encrypted: bool) -> Result<()> {
+    if plan.boot_mode != BootMode::Uefi {
+        bail!("RAID needs UEFI, BIOS installs boot from a single disk");
+    }
+
+    let root = plan
+        .partitions
+        .iter()
+        .find(|p| p.mount_point.as_deref() == Some("/"))
+        .context("RAID needs a partition mounted at `/`")?;
+
+    if root.filesystem != Filesystem::Btrfs {
+        bail!(
+            "RAID is only supported for a Btrfs root, not {}",
+            root.filesystem
+        );
+    }
+
+    if plan.reuse_esp.is_some() || !plan.partitions.iter().any(|p| p.is_esp()) {
+        bail!("RAID needs a new ESP on every disk, an existing ESP cannot be reused");
+    }
+
+    if plan.swap.as_ref().is_some_and(|s| s.kind == SwapKind::File) {
+        bail!(
+            "Btrfs swapfiles only work on single-device filesystems, use a swap partition or zram"
+        );
+    }
+
+    if encrypted {
+        bail!("Encrypting a RAID root is not supported yet");
+    }
+
+    Ok(())
+}
+
+// ---------------------------------------------------------
+// Pick the other disks of the array
+// ---------------------------------------------------------
+pub fn select_member_disks(
+    runner: &dyn CommandRunner,
+    primary: &str,
+    layout: Option<&DiskLayout>,
+    show_all: bool,
+) -> Result<Vec<String>> {
+    let mut remaining: Vec<Disk> = helpers::list_block_disks(runner)?
+        .into_iter()
+        .filter(|d| d.path != primary)
+        .collect();
+
+    let mut members = Vec::new();
+
+    match layout.and_then(|l| l.raid.as_ref()) {
+        Some(config) => {
+            for name in &config.disks {
+                let path = layout::resolve_disk_name(name, &remaining).with_context(|| {
+                    format!("RAID disk `{}` (the primary disk cannot be reused)", name)
+                })?;
+                remaining.retain(|d| d.path != path);
+                members.push(path);
+            }
+        }
+        None => loop {
+            say!("{}", colors::info("Select another disk for the array"));
+            let path = helpers::select_disk_simple(&remaining, show_all)?;
+            remaining.retain(|d| d.path != path);
+            members.push(path);
+
+            if remaining.is_empty() {
+                break;
+            }
+
+            let more = Confirm::new()
+                .with_prompt(colors::info("Add another disk to the array?"))
+                .default(false)
+                .interact()
+                .context("Failed to get confirmation")?;
+            if !more {
+                break;
+            }
+        },
+    }
+
+    if members.is_empty() {
+        bail!("RAID needs at least one more disk besides {}", primary);
+    }
+
+    Ok(members)
+}
+
+// ---------------------------------------------------------
+// ESP and root for another disk, sized like the primary's
+// ---------------------------------------------------------
+pub fn member_plan(
+    plan: &PartitionPlan,
+    ranges: &[SectorRange],
+    geometry: Geometry,
+    index: usize,
+) -> PartitionPlan {
+    let partitions = plan
+        .partitions
+        .iter()
+        .zip(ranges)
+        .filter(|(p, _)| p.is_esp() || p.mount_point.as_deref() == Some("/"))
+        .map(|(p, range)| {
+            let mut part = p.clone();
+            part.size = SizeSpec::Bytes(range.bytes(geometry));
+
+            if part.is_esp() {
+                // A missing disk must not stop the boot
+                part.mount_point = Some(esp_mirror_mount(index));
+                part.mount_options = Some(format!(
+                    "{},nofail",
+                    Filesystem::Vfat.default_mount_options()
+                ));
+            } else {
+                // Joins the primary's Btrfs, never mounted on its own
+                part.mount_point = None;
+            }
+            part
+        })
+        .collect();
+
+    PartitionPlan {
+        partitions,
+        reuse_esp: None,
+        swap: None,
+        lvm: None,
+        boot_mode: plan.boot_mode,
+    }
+}
+
+pub fn print_member_plan(disk_path: &str, plan: &PartitionPlan) {
+    say!(
+        "{}",
+        colors::info(&format!("{} gets a fresh GPT with:", disk_path))
+    );
+    for part in &plan.partitions {
+        let role = match &part.mount_point {
+            Some(mp) => format!("-> {}", mp),
+            None => "joins the Btrfs on the first disk".to_string(),
+        };
+        say!("  {:<8}  {}  {}", part.label, part.size, role);
+    }
+    say!();
+}
+
+fn esp_mirror_mount(index: usize) -> String {
+    format!("/boot-mirror{}", index + 1)
+}
+
+// Whole disk after a fresh GPT, without the label areas at both ends
+fn fresh_disk_region(disk_size: u64) -> FreeRegion {
+    let start_bytes = GPT_RESERVED_BYTES;
+    let end_bytes = disk_size.saturating_sub(GPT_RESERVED_BYTES + 1);
+    let size_bytes = end_bytes.saturating_sub(start_bytes) + 1;
+
+    FreeRegion {
+        start: size::format_size(start_bytes),
+        end: size::format_size(end_bytes),
+        size: size::format_size(size_bytes),
+        start_bytes,
+        end_bytes,
+        size_bytes,
+    }
+}
+
+// ---------------------------------------------------------
+// Everything that can fail on a member disk, before any write
+// ---------------------------------------------------------
+// Returns where the member's partitions go on its fresh GPT
+pub fn check_member(
+    runner: &dyn CommandRunner,
+    disk_path: &str,
+    plan: &PartitionPlan,
+    wipe: Option<WipeMode>,
+    force: bool,
+) -> Result<Vec<SectorRange>> {
+    preflight::check_disk(disk_path, true, force)?;
+
+    if helpers::check_partition_table(runner, disk_path)? && wipe.is_none() {
+        bail!(
+            "RAID disk {} already has a partition table, pass --wipe to clear it",
+            disk_path
+        );
+    }
+    if let Some(mode) = wipe {
+        wipe::check_wipe(disk_path, mode)?;
+    }
+
+    let region = fresh_disk_region(helpers::get_disk_size(runner, disk_path)?);
+    let geometry = helpers::get_geometry(runner, disk_path)?;
+    size::solve(&plan.partitions, &region, geometry)
+        .with_context(|| format!("RAID disk {} is too small", disk_path))
+}
+
+// ---------------------------------------------------------
+// Fresh GPT with the member plan
+// ---------------------------------------------------------
+pub fn partition_member(
+    runner: &dyn CommandRunner,
+    disk_path: &str,
+    plan: &PartitionPlan,
+    ranges: &[SectorRange],
+    wipe: Option<WipeMode>,
+    assume_yes: bool,
+) -> Result<CreatedPartitions> {
+    say!("\n{}", colors::header(&format!("RAID Disk {}", disk_path)));
+
+    if let Some(mode) = wipe {
+        wipe::wipe_disk(runner, disk_path, mode, assume_yes)?;
+    }
+
+    helpers::create_partition_table(runner, disk_path, true, plan.boot_mode.table())?;
+
+    let table = plan.boot_mode.table();
+    let numbers = helpers::new_partition_numbers(&[], plan.partitions.len(), table)?;
+    helpers::create_partitions(runner, disk_path, plan, ranges, &numbers)
+}
+
+// ---------------------------------------------------------
+// Join the member partitions to the primary's root and ESP list
+// ---------------------------------------------------------
+pub fn attach_members(
+    partitions: &mut CreatedPartitions,
+    members: Vec<CreatedPartitions>,
+    profile: RaidProfile,
+) -> Result<RaidSetup> {
+    let mut root_devices = Vec::new();
+    let mut esp_mirrors = Vec::new();
+
+    for member in members {
+        for part in member.partitions {
+            if part.spec.is_esp() {
+                esp_mirrors.push((
+                    part.device.clone(),
+                    part.spec.mount_point.clone().unwrap_or_default(),
+                ));
+                partitions.partitions.push(part);
+            } else {
+                root_devices.push(part.device);
+            }
+        }
+    }
+
+    let root = partitions
+        .partitions
+        .iter_mut()
+        .find(|p| p.spec.mount_point.as_deref() == Some("/"))
+        .context("No partition is mounted at `/`")?;
+
+    root.raid = Some(RaidMembers {
+        profile,
+        devices: root_devices.clone(),
+    });
+
+    Ok(RaidSetup {
+        profile,
+        devices: std::iter::once(root.device.clone())
+            .chain(root_devices)
+            .collect(),
+        esp_mirrors,
+    })
+}
+
+// ---------------------------------------------------------
+// pacman hook that copies /boot to every ESP mirror
+// ---------------------------------------------------------
+pub fn install_esp_sync_hook(
+    runner: &dyn CommandRunner,
+    setup: &RaidSetup,
+    tree: &MountTree,
+) -> Result<()> {
+    if setup.esp_mirrors.is_empty() {
+        return Ok(());
+    }
+
+    let path = subvolumes::target_path(&tree.root, &format!("/{}", ESP_SYNC_HOOK));
+    let hook = esp_sync_hook(setup);
+
+    if runner.is_dry_run() {
+        say!(
+            "{}",
+            colors::info(&format!("[DRY RUN] Would write {}:", path))
+        );
+        say!("{}", hook);
+        return Ok(());
+    }
+
+    if let Some(dir) = std::path::Path::new(&path).parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    std::fs::write(&path, hook).with_context(|| format!("Failed to write {}", path))?;
+
+    say!(
+        "{}",
+        colors::success(&format!("✓ ESP mirrors are synced by {}", path))
+    );
+
+    Ok(())
+}
+
+fn esp_sync_hook(setup: &RaidSetup) -> String {
+    let sync: Vec<String> = setup
+        .esp_mirrors
+        .iter()
+        // The mirrors are `nofail`, never fill the empty mount point instead
+        .map(|(_, mount_point)| {
+            format!(
+                "mountpoint -q {0} && rsync -a --delete /boot/ {0}/",
+                mount_point
+            )
+        })
+        .collect();
+
+    format!(
+        "[Trigger]
+Type = Path
+Operation = Install
+Operation = Upgrade
+Operation = Remove
+Target = boot/*
+Target = usr/lib/modules/*/vmlinuz
+
+[Action]
+Description = Copying /boot to the ESP mirrors...
+When = PostTransaction
+Depends = rsync
+Exec = /usr/bin/sh -c '{}'
+",
+        sync.join("; ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::core::disk_setup::structs::CreatedPartition;
+    use crate::commands::core::disk_setup::structs::PlannedPartition;
+
+    const GIB: u64 = 1 << 30;
+
+    const GEOMETRY: Geometry = Geometry {
+        logical: 512,
+        physical: 512,
+    };
+
+    fn planned(
+        size: &str,
+        type_code: &str,
+        fs: Filesystem,
+        mount: Option<&str>,
+    ) -> PlannedPartition {
+        PlannedPartition {
+            size: size.parse().unwrap(),
+            type_code: type_code.to_string(),
+            label: type_code.to_string(),
+            filesystem: fs,
+            mount_point: mount.map(str::to_string),
+            mount_options: None,
+        }
+    }
+
+    fn primary_plan() -> PartitionPlan {
+        PartitionPlan {
+            partitions: vec![
+                planned("1G", "ef00", Filesystem::Vfat, Some("/boot")),
+                planned("8G", "8200", Filesystem::Swap, None),
+                planned("rest", "8300", Filesystem::Btrfs, Some("/")),
+            ],
+            reuse_esp: None,
+            swap: None,
+            lvm: None,
+            boot_mode: BootMode::Uefi,
+        }
+    }
+
+    #[test]
+    fn members_get_the_esp_and_root_with_the_primary_sizes() {
+        let plan = primary_plan();
+        let ranges =
+            size::solve(&plan.partitions, &fresh_disk_region(100 * GIB), GEOMETRY).unwrap();
+
+        let member = member_plan(&plan, &ranges, GEOMETRY, 0);
+
+        assert_eq!(member.partitions.len(), 2);
+        assert_eq!(member.partitions[0].size, SizeSpec::Bytes(GIB));
+        assert_eq!(
+            member.partitions[0].mount_point.as_deref(),
+            Some("/boot-mirror1")
+        );
+        assert_eq!(
+            member.partitions[1].size,
+            SizeSpec::Bytes(ranges[2].bytes(GEOMETRY))
+        );
+        assert_eq!(member.partitions[1].mount_point, None);
+
+        // The swap partition stays on the first disk, ESP and root (~92 GiB) must fit
+        let fits = size::solve(&member.partitions, &fresh_disk_region(93 * GIB), GEOMETRY);
+        assert!(fits.is_ok());
+        let smaller = size::solve(&member.partitions, &fresh_disk_region(90 * GIB), GEOMETRY);
+        assert!(smaller.is_err());
+    }
+
+    #[test]
+    fn rejects_plans_that_cannot_be_mirrored() {
+        let mut ext4_root = primary_plan();
+        ext4_root.partitions[2].filesystem = Filesystem::Ext4;
+        assert!(validate_plan(&ext4_root, false).is_err());
+
+        let mut no_esp = primary_plan();
+        no_esp.partitions.remove(0);
+        assert!(validate_plan(&no_esp, false).is_err());
+
+        assert!(validate_plan(&primary_plan(), true).is_err());
+        assert!(validate_plan(&primary_plan(), false).is_ok());
+    }
+
+    #[test]
+    fn member_roots_join_the_primary_btrfs() {
+        let created = |device: &str, spec: PlannedPartition| CreatedPartition {
+            number: 1,
+            device: device.to_string(),
+            spec,
+            encryption: None,
+            reused: false,
+            raid: None,
+        };
+        let mut primary = CreatedPartitions {
+            partitions: vec![
+                created(
+                    "/dev/nvme0n1p1",
+                    planned("1G", "ef00", Filesystem::Vfat, Some("/boot")),
+                ),
+                created(
+                    "/dev/nvme0n1p2",
+                    planned("rest", "8300", Filesystem::Btrfs, Some("/")),
+                ),
+            ],
+        };
+        let member = CreatedPartitions {
+            partitions: vec![
+                created(
+                    "/dev/nvme1n1p1",
+                    planned("1G", "ef00", Filesystem::Vfat, Some("/boot-mirror1")),
+                ),
+                created(
+                    "/dev/nvme1n1p2",
+                    planned("1G", "8300", Filesystem::Btrfs, None),
+                ),
+            ],
+        };
+
+        let setup = attach_members(&mut primary, vec![member], RaidProfile::Raid1).unwrap();
+
+        assert_eq!(setup.devices, ["/dev/nvme0n1p2", "/dev/nvme1n1p2"]);
+        assert_eq!(
+            setup.esp_mirrors,
+            [("/dev/nvme1n1p1".to_string(), "/boot-mirror1".to_string())]
+        );
+        assert_eq!(setup.degraded_cmdline(), Some("rootflags=degraded"));
+        // The mirror ESP is formatted and mounted, the member root is not
+        assert_eq!(primary.partitions.len(), 3);
+        assert_eq!(
+            primary.partitions[1].raid.as_ref().unwrap().devices,
+            ["/dev/nvme1n1p2"]
+        );
+        assert!(esp_sync_hook(&setup).contains("rsync -a --delete /boot/ /boot-mirror1/"));
+    }
+
+    #[test]
+    fn sync_hook_skips_mirrors_that_are_not_mounted() {
+        let setup = RaidSetup {
+            profile: RaidProfile::Raid1,
+            devices: vec![
+                "/dev/sda2".to_string(),
+                "/dev/sdb2".to_string(),
+                "/dev/sdc2".to_string(),
+            ],
+            esp_mirrors: vec![
+                ("/dev/sdb1".to_string(), "/boot-mirror1".to_string()),
+                ("/dev/sdc1".to_string(), "/boot-mirror2".to_string()),
+            ],
+        };
+
+        assert!(esp_sync_hook(&setup).contains(
+            "Exec = /usr/bin/sh -c 'mountpoint -q /boot-mirror1 && rsync -a --delete /boot/ /boot-mirror1/; \
+             mountpoint -q /boot-mirror2 && rsync -a --delete /boot/ /boot-mirror2/'"
+        ));
+    }
+}
@@ -0,0 +1,382 @@
+use anyhow::{Context, Result, bail};
+use std::str::FromStr;
+
+use crate::commands::core::disk_setup::structs::FreeRegion;
+use crate::commands::core::disk_setup::structs::Geometry;
+use crate::commands::core::disk_setup::structs::PlannedPartition;
+use crate::commands::core::disk_setup::structs::SectorRange;
+use crate::commands::core::disk_setup::structs::SizeSpec;
+
+const MIB: u64 = 1_048_576;
+/// Partitions start on 1 MiB boundaries (or the physical sector, if larger)
+const ALIGNMENT_BYTES: u64 = MIB;
+
+// ---------------------------------------------------------
+// "512M", "1GiB", "1.5G", "500GB" to bytes
+// ---------------------------------------------------------
+/// Binary units for K/M/G/T and KiB..TiB, decimal for KB..TB; bare numbers are MiB
+pub fn parse_bytes(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let (number, unit) = size.split_at(
+        size.find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(size.len()),
+    );
+
+    let number: f64 = number
+        .parse()
+        .with_context(|| format!("Invalid size `{}`", size))?;
+
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "" | "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "t" | "tib" => 1 << 40,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        _ => bail!(
+            "Invalid size unit in `{}` (use K, M, G, T, or KiB/GB/...)",
+            size
+        ),
+    };
+
+    let bytes = (number * multiplier as f64).round() as u64;
+    if bytes == 0 {
+        bail!("Size `{}` must be greater than 0", size);
+    }
+
+    Ok(bytes)
+}
+
+/// `mb` MiB in bytes; `*_mb` values from layouts are unbounded
+pub fn mib_to_bytes(mb: u64) -> Result<u64> {
+    mb.checked_mul(MIB)
+        .with_context(|| format!("{} MB is too large", mb))
+}
+
+/// Like `parse_bytes`, rounded down to whole MiB (swap and XBOOTLDR prompts)
+pub fn parse_mib(size: &str) -> Result<u64> {
+    let mib = parse_bytes(size)? / MIB;
+    if mib == 0 {
+        bail!("Size `{}` must be at least 1M", size.trim());
+    }
+    Ok(mib)
+}
+
+impl FromStr for SizeSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+
+        if s.is_empty() || s.eq_ignore_ascii_case("rest") {
+            return Ok(SizeSpec::Rest);
+        }
+
+        if let Some(percent) = s.strip_suffix('%') {
+            let percent: u8 = percent
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid percentage `{}`", s))?;
+            if !(1..=100).contains(&percent) {
+                bail!("Percentage `{}` must be between 1% and 100%", s);
+            }
+            return Ok(SizeSpec::Percent(percent));
+        }
+
+        if let Some(free) = s.strip_prefix('-') {
+            return Ok(SizeSpec::LeaveFree(parse_bytes(free)?));
+        }
+
+        Ok(SizeSpec::Bytes(parse_bytes(s)?))
+    }
+}
+
+// ---------------------------------------------------------
+// Bytes to "512 MiB", "1.50 GiB"
+// ---------------------------------------------------------
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [(&str, u64); 4] = [
+        ("TiB", 1 << 40),
+        ("GiB", 1 << 30),
+        ("MiB", 1 << 20),
+        ("KiB", 1 << 10),
+    ];
+
+    for (unit, size) in UNITS {
+        if bytes >= size {
+            return if bytes.is_multiple_of(size) {
+                format!("{} {}", bytes / size, unit)
+            } else {
+                format!("{:.2} {}", bytes as f64 / size as f64, unit)
+            };
+        }
+    }
+
+    format!("{} B", bytes)
+}
+
+// ---------------------------------------------------------
+// Place every partition inside the one free region
+// ---------------------------------------------------------
+pub fn solve(
+    partitions: &[PlannedPartition],
+    region: &FreeRegion,
+    geometry: Geometry,
+) -> Result<Vec<SectorRange>> {
+    let sector = geometry.logical;
+    let align = ALIGNMENT_BYTES
+        .max(geometry.physical)
+        .div_ceil(sector)
+        .max(1);
+    let align_up = |s: u64| s.div_ceil(align) * align;
+    let align_down = |s: u64| s / align * align;
+    let sectors_of = |bytes: u64| align_up(bytes.div_ceil(sector));
+
+    // Region bounds in sectors, end inclusive
+    let first = align_up(region.start_bytes.div_ceil(sector));
+    let last = match (region.end_bytes + 1) / sector {
+        0 => 0,
+        n => n - 1,
+    };
+    if last < first + align {
+        bail!(
+            "Free region {} - {} is too small to hold an aligned partition",
+            region.start,
+            region.end
+        );
+    }
+    let usable = last - first + 1;
+
+    let flexible: Vec<&PlannedPartition> =
+        partitions.iter().filter(|p| p.size.is_flexible()).collect();
+    if flexible.len() > 1 {
+        bail!(
+            "Only one partition may use `rest` or `-<size>`, found {}",
+            flexible
+                .iter()
+                .map(|p| p.label.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    // Fixed sizes and percentages first, the flexible partition gets the leftovers
+    let mut sizes: Vec<Option<u64>> = Vec::new();
+    for part in partitions {
+        sizes.push(match part.size {
+            SizeSpec::Bytes(bytes) => Some(sectors_of(bytes)),
+            SizeSpec::Percent(p) => {
+                let sectors = align_down(usable * p as u64 / 100);
+                if sectors == 0 {
+                    bail!("{}% of the free region is too small for {}", p, part.label);
+                }
+                Some(sectors)
+            }
+            SizeSpec::Rest | SizeSpec::LeaveFree(_) => None,
+        });
+    }
+
+    let fixed: u64 = sizes.iter().flatten().sum();
+    if fixed > usable {
+        bail!(
+            "Partitions need {} but the free region {} - {} only has {}",
+            format_size(fixed * sector),
+            region.start,
+            region.end,
+            format_size(usable * sector)
+        );
+    }
+
+    if let Some(flex) = flexible.first() {
+        let leave = match flex.size {
+            SizeSpec::LeaveFree(bytes) => sectors_of(bytes),
+            _ => 0,
+        };
+
+        let left = (usable - fixed).saturating_sub(leave);
+        if left < align {
+            bail!(
+                "No space is left for `{}` ({} free after the other partitions)",
+                flex.label,
+                format_size((usable - fixed) * sector)
+            );
+        }
+
+        // A trailing flexible partition may end unaligned, exactly at the region's end
+        let index = partitions
+            .iter()
+            .position(|p| p.size.is_flexible())
+            .unwrap_or_default();
+        sizes[index] = Some(if index + 1 == partitions.len() {
+            left
+        } else {
+            align_down(left)
+        });
+    }
+
+    let mut cursor = first;
+    let mut ranges = Vec::new();
+    for size in sizes.into_iter().flatten() {
+        ranges.push(SectorRange {
+            start: cursor,
+            end: cursor + size - 1,
+        });
+        cursor += size;
+    }
+
+    Ok(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::core::disk_setup::structs::Filesystem;
+
+    const GIB: u64 = 1 << 30;
+
+    fn part(label: &str, size: &str) -> PlannedPartition {
+        PlannedPartition {
+            size: size.parse().unwrap(),
+            type_code: "8300".to_string(),
+            label: label.to_string(),
+            filesystem: Filesystem::Ext4,
+            mount_point: None,
+            mount_options: None,
+        }
+    }
+
+    // 100 GiB starting right after the GPT header area
+    fn region() -> FreeRegion {
+        FreeRegion {
+            start: "17.4kB".to_string(),
+            end: "100GiB".to_string(),
+            size: "100GiB".to_string(),
+            start_bytes: 17_408,
+            end_bytes: 100 * GIB - 1,
+            size_bytes: 100 * GIB - 17_408,
+        }
+    }
+
+    const SECTOR_512: Geometry = Geometry {
+        logical: 512,
+        physical: 4096,
+    };
+
+    #[test]
+    fn parses_size_expressions() {
+        assert_eq!(
+            "512M".parse::<SizeSpec>().unwrap(),
+            SizeSpec::Bytes(512 * MIB)
+        );
+        assert_eq!("1GiB".parse::<SizeSpec>().unwrap(), SizeSpec::Bytes(GIB));
+        assert_eq!(
+            "1.5g".parse::<SizeSpec>().unwrap(),
+            SizeSpec::Bytes(3 * GIB / 2)
+        );
+        assert_eq!(
+            "500GB".parse::<SizeSpec>().unwrap(),
+            SizeSpec::Bytes(500_000_000_000)
+        );
+        assert_eq!(
+            "2048".parse::<SizeSpec>().unwrap(),
+            SizeSpec::Bytes(2 * GIB)
+        );
+        assert_eq!("40%".parse::<SizeSpec>().unwrap(), SizeSpec::Percent(40));
+        assert_eq!("rest".parse::<SizeSpec>().unwrap(), SizeSpec::Rest);
+        assert_eq!(
+            "-8G".parse::<SizeSpec>().unwrap(),
+            SizeSpec::LeaveFree(8 * GIB)
+        );
+
+        for bad in ["0", "0%", "101%", "12X", "-", "M"] {
+            assert!(bad.parse::<SizeSpec>().is_err(), "{} should not parse", bad);
+        }
+    }
+
+    #[test]
+    fn aligns_every_partition_to_one_mib() {
+        let parts = [part("EFI", "1G"), part("SWAP", "8G"), part("ROOT", "rest")];
+        let ranges = solve(&parts, &region(), SECTOR_512).unwrap();
+
+        assert_eq!(
+            ranges[0],
+            SectorRange {
+                start: 2048,
+                end: 2048 + 2_097_152 - 1
+            }
+        );
+        assert_eq!(ranges[1].start, 2048 + 2_097_152);
+        assert_eq!(ranges[1].bytes(SECTOR_512), 8 * GIB);
+        // Rest runs to the last sector of the region
+        assert_eq!(ranges[2].end, 100 * GIB / 512 - 1);
+        assert!(ranges.iter().all(|r| r.start % 2048 == 0));
+    }
+
+    #[test]
+    fn leaves_space_free_at_the_end() {
+        let parts = [part("ROOT", "-8G")];
+        let ranges = solve(&parts, &region(), SECTOR_512).unwrap();
+
+        let free_after = (100 * GIB / 512 - 1 - ranges[0].end) * 512;
+        assert_eq!(free_after, 8 * GIB);
+    }
+
+    #[test]
+    fn rest_in_the_middle_keeps_the_next_partition_aligned() {
+        let parts = [part("ROOT", "rest"), part("HOME", "40%")];
+        let ranges = solve(&parts, &region(), SECTOR_512).unwrap();
+
+        assert_eq!(ranges[1].start, ranges[0].end + 1);
+        assert_eq!(ranges[1].start % 2048, 0);
+        assert!(ranges[1].end < 100 * GIB / 512);
+    }
+
+    #[test]
+    fn uses_the_physical_sector_for_alignment_on_4k_disks() {
+        // 512e: 512 byte sectors for the kernel, 4 KiB on the platter
+        let geometry = Geometry {
+            logical: 512,
+            physical: 4096,
+        };
+        let ranges = solve(
+            &[part("ROOT", "512M"), part("HOME", "rest")],
+            &region(),
+            geometry,
+        )
+        .unwrap();
+
+        for range in &ranges {
+            assert_eq!(range.start * geometry.logical % geometry.physical, 0);
+        }
+        // 1 MiB = 2048 sectors of 512 bytes, 512 MiB = 1048576 of them
+        assert_eq!(
+            ranges[0],
+            SectorRange {
+                start: 2048,
+                end: 2048 + 1_048_576 - 1
+            }
+        );
+        assert_eq!(
+            (ranges[0].end + 1) * geometry.logical % geometry.physical,
+            0
+        );
+    }
+
+    #[test]
+    fn rejects_plans_that_do_not_fit() {
+        let too_big = [part("ROOT", "101G")];
+        assert!(solve(&too_big, &region(), SECTOR_512).is_err());
+
+        let over_full = [part("WIN", "60%"), part("DATA", "50%")];
+        assert!(solve(&over_full, &region(), SECTOR_512).is_err());
+
+        let nothing_left = [part("DATA", "99G"), part("ROOT", "-1G")];
+        assert!(solve(&nothing_left, &region(), SECTOR_512).is_err());
+
+        let two_rests = [part("ROOT", "rest"), part("HOME", "-1G")];
+        assert!(solve(&two_rests, &region(), SECTOR_512).is_err());
+    }
+}
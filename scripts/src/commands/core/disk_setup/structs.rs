@@ -1,4 +1,7 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::commands::core::disk_setup::size;
 
 #[derive(Debug, Deserialize)]
 pub struct LsblkNode {
@@ -9,38 +12,1096 @@ pub struct LsblkNode {
     pub dev_type: String,
     #[serde(default)]
     pub model: Option<String>,
+    /// Transport: nvme, sata, usb, ...
+    #[serde(default)]
+    pub tran: Option<String>,
+    #[serde(default, deserialize_with = "lsblk_flag")]
+    pub rota: bool,
+    #[serde(default, deserialize_with = "lsblk_flag")]
+    pub rm: bool,
+    #[serde(default)]
+    pub serial: Option<String>,
+    #[serde(default)]
+    pub wwn: Option<String>,
+    #[serde(default)]
+    pub pttype: Option<String>,
+    /// Partition type GUID (GPT) or `0x..` byte (msdos)
+    #[serde(default)]
+    pub parttype: Option<String>,
+    #[serde(default)]
+    pub fstype: Option<String>,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub uuid: Option<String>,
+    /// Unmounted devices report `[null]`
+    #[serde(default)]
+    pub mountpoints: Vec<Option<String>>,
+    #[serde(default)]
+    pub children: Vec<LsblkNode>,
 }
 
-#[derive(Debug, Clone)]
+// Newer lsblk prints true/false, older versions "1"/"0"
+fn lsblk_flag<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    let value = Option::<serde_json::Value>::deserialize(deserializer)?;
+    Ok(match value {
+        Some(serde_json::Value::Bool(b)) => b,
+        Some(serde_json::Value::String(s)) => s == "1",
+        Some(serde_json::Value::Number(n)) => n.as_u64() == Some(1),
+        _ => false,
+    })
+}
+
+// ---------------------------------------------------------
+// Disk shown in the selector
+// ---------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiskRole {
+    Regular,
+    Zram,
+    Loop,
+    /// Holds the running system or live ISO
+    BootMedium,
+}
+
+impl DiskRole {
+    /// Why the disk is hidden from the selector by default
+    pub fn note(&self) -> Option<&'static str> {
+        match self {
+            DiskRole::Regular => None,
+            DiskRole::Zram => Some("zram"),
+            DiskRole::Loop => Some("loop device"),
+            DiskRole::BootMedium => Some("live/boot medium"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Disk {
     pub name: String,
     pub path: String,
     pub size: String,
     pub model: String,
+    pub transport: Option<String>,
+    pub rotational: bool,
+    pub removable: bool,
+    pub serial: Option<String>,
+    pub wwn: Option<String>,
+    /// `gpt`, `dos` or `None` without a partition table
+    pub pttype: Option<String>,
+    /// Stable /dev/disk/by-id/... path
+    pub by_id: Option<String>,
+    pub role: DiskRole,
+    pub children: Vec<DiskChild>,
 }
 
-#[derive(Debug, Clone)]
+impl Disk {
+    /// "nvme SSD", "usb HDD removable"
+    pub fn kind(&self) -> String {
+        match self.role {
+            DiskRole::Loop => return "loop".to_string(),
+            DiskRole::Zram => return "zram".to_string(),
+            _ => {}
+        }
+
+        let mut words = Vec::new();
+        if let Some(tran) = &self.transport {
+            words.push(tran.as_str());
+        }
+        words.push(if self.rotational { "HDD" } else { "SSD" });
+        if self.removable {
+            words.push("removable");
+        }
+        words.join(" ")
+    }
+}
+
+/// Partition (or LUKS/LVM volume stacked on one) below a disk
+#[derive(Debug, Clone, Serialize)]
+pub struct DiskChild {
+    pub name: String,
+    pub size: String,
+    pub fstype: Option<String>,
+    pub label: Option<String>,
+    pub mountpoints: Vec<String>,
+    pub children: Vec<DiskChild>,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct FreeRegion {
     pub start: String,
     pub end: String,
     pub size: String,
+    /// First byte of the region
+    pub start_bytes: u64,
+    /// Last byte of the region (inclusive, as parted reports it)
+    pub end_bytes: u64,
     pub size_bytes: u64,
 }
 
 // ---------------------------------------------------------
-// Partition plan from user input
+// Partition already on the disk (parted -m print)
 // ---------------------------------------------------------
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExistingPartition {
+    pub number: u32,
+    pub start_bytes: u64,
+    pub end_bytes: u64,
+    pub size_bytes: u64,
+    /// As parted reports it, empty if unknown
+    pub filesystem: String,
+    /// GPT partition name
+    pub name: String,
+}
+
+// ---------------------------------------------------------
+// One stretch of the disk in the plan preview
+// ---------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentKind {
+    Existing,
+    New,
+    Free,
+}
+
+impl SegmentKind {
+    /// Fill character in the preview bar
+    pub fn symbol(&self) -> char {
+        match self {
+            SegmentKind::Existing => '#',
+            SegmentKind::New => '=',
+            SegmentKind::Free => '.',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskSegment {
+    pub kind: SegmentKind,
+    /// None for free space
+    pub number: Option<u32>,
+    pub start_bytes: u64,
+    pub size_bytes: u64,
+    pub filesystem: String,
+    pub label: String,
+}
+
+// ---------------------------------------------------------
+// Filesystems sharch can create
+// ---------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Filesystem {
+    Btrfs,
+    Ext4,
+    Xfs,
+    F2fs,
+    Bcachefs,
+    /// FAT32, only for ESP/XBOOTLDR partitions
+    #[serde(alias = "fat32")]
+    #[value(skip)]
+    Vfat,
+    /// Swap partition (mkswap), never mounted
+    #[value(skip)]
+    Swap,
+    /// LVM physical volume holding the `[lvm]` volumes, never mounted
+    #[value(skip)]
+    Lvm,
+    /// BIOS boot partition (ef02) GRUB embeds its core image in, left unformatted
+    #[serde(rename = "bios_grub")]
+    #[value(skip)]
+    BiosBoot,
+}
+
+impl Filesystem {
+    /// Choices offered for the Linux partition
+    pub const ROOT_CHOICES: [Filesystem; 5] = [
+        Filesystem::Btrfs,
+        Filesystem::Ext4,
+        Filesystem::Xfs,
+        Filesystem::F2fs,
+        Filesystem::Bcachefs,
+    ];
+
+    /// Name used by `mount -t` and fstab
+    pub fn fstype(&self) -> &'static str {
+        match self {
+            Filesystem::Btrfs => "btrfs",
+            Filesystem::Ext4 => "ext4",
+            Filesystem::Xfs => "xfs",
+            Filesystem::F2fs => "f2fs",
+            Filesystem::Bcachefs => "bcachefs",
+            Filesystem::Vfat => "vfat",
+            Filesystem::Swap => "swap",
+            Filesystem::Lvm => "LVM2_member",
+            Filesystem::BiosBoot => "bios_grub",
+        }
+    }
+
+    /// Binary that creates the filesystem, `None` if the partition stays raw
+    pub fn mkfs_tool(&self) -> Option<&'static str> {
+        match self {
+            Filesystem::Btrfs => Some("mkfs.btrfs"),
+            Filesystem::Ext4 => Some("mkfs.ext4"),
+            Filesystem::Xfs => Some("mkfs.xfs"),
+            Filesystem::F2fs => Some("mkfs.f2fs"),
+            Filesystem::Bcachefs => Some("bcachefs"),
+            Filesystem::Vfat => Some("mkfs.fat"),
+            Filesystem::Swap => Some("mkswap"),
+            Filesystem::Lvm => Some("pvcreate"),
+            Filesystem::BiosBoot => None,
+        }
+    }
+
+    /// Arguments for `mkfs_tool()`, forcing over stale signatures where supported
+    pub fn mkfs_args(&self, label: &str, device: &str) -> Vec<String> {
+        let args: Vec<&str> = match self {
+            Filesystem::Btrfs => vec!["-f", "-L", label, device],
+            Filesystem::Ext4 => vec!["-F", "-L", label, device],
+            Filesystem::Xfs => vec!["-f", "-L", label, device],
+            Filesystem::F2fs => vec!["-f", "-l", label, device],
+            Filesystem::Bcachefs => vec!["format", "-L", label, device],
+            Filesystem::Vfat => vec!["-F", "32", "-n", label, device],
+            Filesystem::Swap => vec!["-L", label, device],
+            // PVs have no label, the partition name still carries it
+            Filesystem::Lvm => vec!["-ff", "--yes", device],
+            Filesystem::BiosBoot => vec![],
+        };
+        args.into_iter().map(str::to_string).collect()
+    }
+
+    /// Longest label mkfs accepts
+    pub fn label_max_len(&self) -> usize {
+        match self {
+            Filesystem::Btrfs => 255,
+            Filesystem::Ext4 => 16,
+            Filesystem::Xfs => 12,
+            Filesystem::F2fs => 512,
+            Filesystem::Bcachefs => 32,
+            Filesystem::Vfat => 11,
+            Filesystem::Swap => 15,
+            Filesystem::Lvm | Filesystem::BiosBoot => 36,
+        }
+    }
+
+    /// Mount options used when the plan does not set any
+    pub fn default_mount_options(&self) -> &'static str {
+        match self {
+            Filesystem::Btrfs => "noatime,compress=zstd,space_cache=v2,discard=async",
+            Filesystem::Ext4 | Filesystem::Xfs | Filesystem::Bcachefs => "noatime",
+            Filesystem::F2fs => "noatime,lazytime",
+            // Keeps the random seed and loader files private
+            Filesystem::Vfat => "umask=0077",
+            Filesystem::Swap | Filesystem::Lvm | Filesystem::BiosBoot => "defaults",
+        }
+    }
+}
+
+impl std::fmt::Display for Filesystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.fstype())
+    }
+}
+
+// ---------------------------------------------------------
+// Partition plan from user input (or a layout file)
+// ---------------------------------------------------------
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlannedPartition {
+    /// `512M`, `1GiB`, `40%`, `rest` or `-8G`; bare numbers are MiB (old `size_mb`)
+    #[serde(default, alias = "size_mb")]
+    pub size: SizeSpec,
+    /// sgdisk type code, e.g. `ef00` or `8300`
+    #[serde(rename = "type")]
+    pub type_code: String,
+    pub label: String,
+    pub filesystem: Filesystem,
+    #[serde(default)]
+    pub mount_point: Option<String>,
+    #[serde(default)]
+    pub mount_options: Option<String>,
+}
+
+impl PlannedPartition {
+    pub fn is_esp(&self) -> bool {
+        self.type_code.eq_ignore_ascii_case("ef00")
+    }
+
+    pub fn is_bios_boot(&self) -> bool {
+        self.type_code.eq_ignore_ascii_case("ef02")
+    }
+}
+
+// ---------------------------------------------------------
+// Partition sizes and where they end up on disk
+// ---------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SizeSpec {
+    /// Fixed size in bytes
+    Bytes(u64),
+    /// Share of the free region (1-100)
+    Percent(u8),
+    /// Everything that is left
+    #[default]
+    Rest,
+    /// Everything that is left, minus this many bytes
+    LeaveFree(u64),
+}
+
+impl SizeSpec {
+    pub fn is_flexible(&self) -> bool {
+        matches!(self, SizeSpec::Rest | SizeSpec::LeaveFree(_))
+    }
+
+    pub fn fixed_bytes(&self) -> Option<u64> {
+        match self {
+            SizeSpec::Bytes(bytes) => Some(*bytes),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for SizeSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SizeSpec::Bytes(bytes) => write!(f, "{}", size::format_size(*bytes)),
+            SizeSpec::Percent(p) => write!(f, "{}%", p),
+            SizeSpec::Rest => write!(f, "rest"),
+            SizeSpec::LeaveFree(bytes) => {
+                write!(f, "rest, leaving {} free", size::format_size(*bytes))
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SizeSpec {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            /// `size_mb = 512` from older layouts
+            Mib(u64),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Mib(mb) => size::mib_to_bytes(mb)
+                .map(SizeSpec::Bytes)
+                .map_err(serde::de::Error::custom),
+            Raw::Text(s) => s.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// Logical and physical sector size of a disk
+#[derive(Debug, Clone, Copy)]
+pub struct Geometry {
+    pub logical: u64,
+    pub physical: u64,
+}
+
+/// Sectors of one partition, both ends inclusive (as sgdisk takes them)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SectorRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl SectorRange {
+    pub fn bytes(&self, geometry: Geometry) -> u64 {
+        (self.end - self.start + 1) * geometry.logical
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub struct PartitionPlan {
-    pub efi_size_mb: u64,
-    pub linux_size_mb: u64,
+    /// Partitions to create, in order
+    pub partitions: Vec<PlannedPartition>,
+    /// Existing ESP that is mounted as-is instead of creating one
+    pub reuse_esp: Option<ExistingEsp>,
+    /// Swap strategy (a swap partition is also listed in `partitions`)
+    pub swap: Option<SwapConfig>,
+    /// Volume group inside the partition with `filesystem = "lvm"`
+    pub lvm: Option<LvmConfig>,
+    /// Decides the partition table and how partitions are written
+    pub boot_mode: BootMode,
+}
+
+// ---------------------------------------------------------
+// Firmware of the running machine and how the install boots
+// ---------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Firmware {
+    Uefi,
+    Bios,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum BootMode {
+    /// GPT with an EFI System Partition
+    Uefi,
+    /// GPT with a 1 MiB bios_grub partition for GRUB
+    BiosGpt,
+    /// Classic msdos label, at most 4 primary partitions and 2 TiB
+    BiosMbr,
+}
+
+impl BootMode {
+    pub fn table(&self) -> PartitionTable {
+        match self {
+            BootMode::Uefi | BootMode::BiosGpt => PartitionTable::Gpt,
+            BootMode::BiosMbr => PartitionTable::Msdos,
+        }
+    }
+
+    /// `grub-install --target=` for this mode
+    pub fn grub_target(&self) -> &'static str {
+        match self {
+            BootMode::Uefi => "x86_64-efi",
+            BootMode::BiosGpt | BootMode::BiosMbr => "i386-pc",
+        }
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            BootMode::Uefi => "UEFI (GPT + ESP)",
+            BootMode::BiosGpt => "BIOS (GPT + bios_grub)",
+            BootMode::BiosMbr => "BIOS (msdos)",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionTable {
+    Gpt,
+    Msdos,
+}
+
+impl PartitionTable {
+    /// `parted mklabel` argument
+    pub fn parted_label(&self) -> &'static str {
+        match self {
+            PartitionTable::Gpt => "gpt",
+            PartitionTable::Msdos => "msdos",
+        }
+    }
+
+    /// Primary partitions only, no extended/logical partitions on msdos
+    pub fn max_partitions(&self) -> u32 {
+        match self {
+            PartitionTable::Gpt => 128,
+            PartitionTable::Msdos => 4,
+        }
+    }
+
+    /// msdos stores 32-bit sector numbers
+    pub fn max_sector(&self) -> u64 {
+        match self {
+            PartitionTable::Gpt => u64::MAX,
+            PartitionTable::Msdos => u32::MAX as u64,
+        }
+    }
+}
+
+impl std::fmt::Display for PartitionTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PartitionTable::Gpt => "GPT",
+            PartitionTable::Msdos => "msdos",
+        })
+    }
+}
+
+// ---------------------------------------------------------
+// How the disk is cleared before partitioning (--wipe)
+// ---------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum WipeMode {
+    /// wipefs -a on the disk and every partition
+    Signatures,
+    /// Discard (TRIM) the whole disk, SSDs only
+    Discard,
+    /// Overwrite the whole disk with zeros
+    Zero,
+    /// NVMe format with user data erase
+    NvmeFormat,
+}
+
+impl WipeMode {
+    /// What the confirmation prompt says will happen
+    pub fn describe(&self) -> &'static str {
+        match self {
+            WipeMode::Signatures => {
+                "erase all filesystem, RAID and LUKS signatures on the disk and its partitions"
+            }
+            WipeMode::Discard => "discard every block of the disk, the data cannot be recovered",
+            WipeMode::Zero => "overwrite the whole disk with zeros, this can take hours",
+            WipeMode::NvmeFormat => "NVMe-format the namespace, erasing all user data",
+        }
+    }
+
+    /// Tools the mode needs besides lsblk
+    pub fn tools(&self) -> &'static [&'static str] {
+        match self {
+            WipeMode::Signatures => &["wipefs"],
+            WipeMode::Discard => &["wipefs", "blkdiscard"],
+            WipeMode::Zero => &[],
+            WipeMode::NvmeFormat => &["nvme"],
+        }
+    }
+}
+
+// ---------------------------------------------------------
+// Swap strategy
+// ---------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SwapKind {
+    None,
+    /// Dedicated swap partition
+    Partition,
+    /// Swapfile in the @swap subvolume (or /swapfile)
+    File,
+    /// Compressed swap in RAM via zram-generator
+    Zram,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SwapConfig {
+    pub kind: SwapKind,
+    /// Defaults to the amount of RAM (zram: zram-generator's default)
+    #[serde(default)]
+    pub size_mb: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SwapSetup {
+    pub kind: SwapKind,
+    /// `UUID=...` or path inside the installed system; `None` for zram
+    pub source: Option<String>,
+}
+
+impl SwapSetup {
+    /// Matching /etc/fstab line (zram is set up by zram-generator instead)
+    pub fn fstab_line(&self) -> Option<String> {
+        self.source
+            .as_ref()
+            .map(|source| format!("{} none swap defaults 0 0", source))
+    }
+}
+
+// ---------------------------------------------------------
+// Existing EFI System Partitions (dual boot)
+// ---------------------------------------------------------
+#[derive(Debug, Clone, Serialize)]
+pub struct ExistingEsp {
+    pub device: String,
+    pub number: u32,
+    pub size_bytes: u64,
+    /// `None` if the filesystem could not be inspected
+    pub free_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EspConfig {
+    /// Existing ESP to reuse (e.g. `/dev/nvme0n1p1`); omit to create a new one
+    #[serde(default)]
+    pub reuse: Option<String>,
+    /// Below this size a reused ESP gets an XBOOTLDR partition next to it
+    #[serde(default = "default_esp_min_size_mb")]
+    pub min_size_mb: u64,
+    /// XBOOTLDR partition added next to a small reused ESP, 0 to not add one
+    #[serde(default = "default_xbootldr_size_mb")]
+    pub xbootldr_size_mb: u64,
+}
+
+impl EspConfig {
+    pub const DEFAULT_MIN_SIZE_MB: u64 = 512;
+    pub const DEFAULT_XBOOTLDR_SIZE_MB: u64 = 1024;
+}
+
+fn default_esp_min_size_mb() -> u64 {
+    EspConfig::DEFAULT_MIN_SIZE_MB
+}
+
+fn default_xbootldr_size_mb() -> u64 {
+    EspConfig::DEFAULT_XBOOTLDR_SIZE_MB
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EspChoice {
+    pub reuse: Option<ExistingEsp>,
+    /// Size of the XBOOTLDR partition to create (only with a small reused ESP)
+    pub xbootldr_size_mb: Option<u64>,
+}
+
+// ---------------------------------------------------------
+// Declarative layout file (--layout)
+// ---------------------------------------------------------
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiskLayout {
+    /// Target disk, either `nvme0n1` or `/dev/nvme0n1`
+    pub disk: String,
+    /// Create a partition table if the disk has none (answers the confirmation prompt)
+    #[serde(default)]
+    pub create_partition_table: bool,
+    /// `uefi`, `bios-gpt` or `bios-mbr`; detected from the firmware when omitted
+    #[serde(default)]
+    pub boot_mode: Option<BootMode>,
+    /// Free region to partition, numbered as in the "Free Space Regions" table (default: largest)
+    #[serde(default)]
+    pub free_region: Option<usize>,
+    #[serde(rename = "partition", default)]
+    pub partitions: Vec<PlannedPartition>,
+    /// Btrfs subvolumes for the root partition; empty means the default set
+    #[serde(rename = "subvolume", default)]
+    pub subvolumes: Vec<Subvolume>,
+    /// Encrypt the root partition with LUKS2
+    #[serde(default)]
+    pub encryption: Option<EncryptionConfig>,
+    /// Reuse an existing EFI System Partition
+    #[serde(default)]
+    pub esp: Option<EspConfig>,
+    /// Swap file or zram; swap partitions are `[[partition]]` entries with `filesystem = "swap"`
+    #[serde(default)]
+    pub swap: Option<SwapConfig>,
+    /// Spread the Btrfs root over more disks
+    #[serde(default)]
+    pub raid: Option<RaidConfig>,
+    /// Logical volumes inside the partition with `filesystem = "lvm"`
+    /// (only layouts set up LVM, the prompts never do)
+    #[serde(default)]
+    pub lvm: Option<LvmConfig>,
+}
+
+// ---------------------------------------------------------
+// Btrfs subvolume on the root partition
+// ---------------------------------------------------------
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Subvolume {
+    /// Subvolume name, e.g. `@home`
+    pub name: String,
+    /// Mount point inside the installed system, e.g. `/home`
+    pub mount_point: String,
+    /// Overrides the root partition's mount options (`subvol=` is added automatically)
+    #[serde(default)]
+    pub mount_options: Option<String>,
+}
+
+// ---------------------------------------------------------
+// LUKS2 encryption of the root partition
+// ---------------------------------------------------------
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EncryptionConfig {
+    /// Name under /dev/mapper
+    #[serde(default = "default_mapper_name")]
+    pub mapper_name: String,
+    /// Read the passphrase from this file instead of prompting
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
+}
+
+fn default_mapper_name() -> String {
+    "cryptroot".to_string()
+}
+
+// ---------------------------------------------------------
+// LVM volume group (usually inside LUKS)
+// ---------------------------------------------------------
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LvmConfig {
+    #[serde(default = "default_vg_name")]
+    pub vg_name: String,
+    #[serde(rename = "volume", default)]
+    pub volumes: Vec<LogicalVolume>,
+}
+
+fn default_vg_name() -> String {
+    "vg0".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogicalVolume {
+    /// LV name, the device becomes /dev/<vg_name>/<name>
+    pub name: String,
+    /// `40G`, `40%` (of the VG), `rest` or `-8G` (left free in the VG)
+    #[serde(default)]
+    pub size: SizeSpec,
+    pub filesystem: Filesystem,
+    #[serde(default)]
+    pub mount_point: Option<String>,
+    #[serde(default)]
+    pub mount_options: Option<String>,
+}
+
+// ---------------------------------------------------------
+// What later stages (crypttab, mkinitcpio, cmdline) need from LVM
+// ---------------------------------------------------------
+#[derive(Debug, Clone, Serialize)]
+pub struct LvmSetup {
+    pub vg_name: String,
+    /// Device the PV was created on (the LUKS mapper when encrypted)
+    pub pv_device: String,
+    /// (LV name, /dev/<vg>/<lv>, mount point)
+    pub volumes: Vec<(String, String, Option<String>)>,
+}
+
+impl LvmSetup {
+    /// Activates the VG in the initramfs (after `sd-encrypt`)
+    pub const MKINITCPIO_HOOK: &'static str = "lvm2";
+
+    pub fn root_device(&self) -> Option<&str> {
+        self.volumes
+            .iter()
+            .find(|(_, _, mp)| mp.as_deref() == Some("/"))
+            .map(|(_, device, _)| device.as_str())
+    }
+
+    /// `root=` for the kernel cmdline
+    pub fn kernel_cmdline(&self) -> Option<String> {
+        self.root_device().map(|device| format!("root={}", device))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EncryptedVolume {
+    /// LUKS UUID (what crypttab and rd.luks.name refer to)
+    pub luks_uuid: String,
+    pub mapper_name: String,
+}
+
+impl EncryptedVolume {
+    pub fn mapper_path(&self) -> String {
+        format!("/dev/mapper/{}", self.mapper_name)
+    }
+
+    /// `rd.luks.name=` for the `sd-encrypt` mkinitcpio hook
+    pub fn luks_cmdline(&self) -> String {
+        format!("rd.luks.name={}={}", self.luks_uuid, self.mapper_name)
+    }
+
+    /// Kernel parameters when the filesystem sits directly in the container
+    pub fn kernel_cmdline(&self) -> String {
+        format!("{} root={}", self.luks_cmdline(), self.mapper_path())
+    }
 }
 
 // ---------------------------------------------------------
 // Created partitions info
 // ---------------------------------------------------------
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
+pub struct CreatedPartition {
+    /// Partition number, 0 for LVM logical volumes
+    pub number: u32,
+    pub device: String,
+    pub spec: PlannedPartition,
+    /// Set when the partition is a LUKS container
+    pub encryption: Option<EncryptedVolume>,
+    /// Existing partition that is mounted but not formatted (reused ESP)
+    pub reused: bool,
+    /// Partitions on other disks that join this partition's Btrfs
+    pub raid: Option<RaidMembers>,
+}
+
+impl CreatedPartition {
+    /// Device that carries the filesystem (the mapper for LUKS partitions)
+    pub fn fs_device(&self) -> String {
+        match &self.encryption {
+            Some(vol) => vol.mapper_path(),
+            None => self.device.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub struct CreatedPartitions {
-    pub efi_partition: String,
-    pub linux_partition: String,
+    pub partitions: Vec<CreatedPartition>,
+}
+
+// ---------------------------------------------------------
+// Multi-disk Btrfs
+// ---------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum RaidProfile {
+    /// Every block on two disks, survives losing one
+    Raid1,
+    /// Striped, no redundancy
+    Raid0,
+    /// Disks concatenated, no redundancy
+    Single,
+}
+
+impl RaidProfile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RaidProfile::Raid1 => "raid1",
+            RaidProfile::Raid0 => "raid0",
+            RaidProfile::Single => "single",
+        }
+    }
+
+    /// Disks that can fail without losing the filesystem
+    pub fn tolerated_failures(&self) -> usize {
+        match self {
+            RaidProfile::Raid1 => 1,
+            RaidProfile::Raid0 | RaidProfile::Single => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RaidConfig {
+    pub profile: RaidProfile,
+    /// Other disks, same forms as `disk`; each gets a fresh GPT
+    pub disks: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RaidMembers {
+    pub profile: RaidProfile,
+    /// Root partitions on the other disks
+    pub devices: Vec<String>,
+}
+
+impl RaidMembers {
+    /// Profile flags for mkfs.btrfs; metadata is always mirrored
+    pub fn mkfs_args(&self) -> Vec<String> {
+        ["-d", self.profile.as_str(), "-m", "raid1"]
+            .into_iter()
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+// ---------------------------------------------------------
+// What later stages (fstab, bootloader) need to know about the array
+// ---------------------------------------------------------
+#[derive(Debug, Clone, Serialize)]
+pub struct RaidSetup {
+    pub profile: RaidProfile,
+    /// Every partition of the Btrfs, primary first
+    pub devices: Vec<String>,
+    /// ESP copies on the other disks, as (device, mount point)
+    pub esp_mirrors: Vec<(String, String)>,
+}
+
+impl RaidSetup {
+    /// Without systemd in the initramfs, the `btrfs` hook scans for the other members
+    pub const MKINITCPIO_HOOK: &'static str = "btrfs";
+
+    /// Kernel parameters for a fallback boot entry, only when a disk may be missing.
+    /// Never for the default entry or fstab: a silently degraded mount splits the mirror.
+    pub fn degraded_cmdline(&self) -> Option<&'static str> {
+        (self.profile.tolerated_failures() > 0).then_some("rootflags=degraded")
+    }
+}
+
+// ---------------------------------------------------------
+// Mounted target tree (what ended up under /mnt)
+// ---------------------------------------------------------
+#[derive(Debug, Clone)]
+pub struct MountEntry {
+    pub source: String,
+    /// Mount point inside the installed system, e.g. `/var/log`
+    pub mount_point: String,
+    pub fstype: String,
+    /// Full option string passed to `mount -o`, including `subvol=`
+    pub options: String,
+}
+
+#[derive(Debug)]
+pub struct MountTree {
+    /// Where the target system is mounted, e.g. `/mnt`
+    pub root: String,
+    /// In mount order (parents before children)
+    pub mounts: Vec<MountEntry>,
+}
+
+// ---------------------------------------------------------
+// Stable identifiers of a device (blkid -o export)
+// ---------------------------------------------------------
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceIds {
+    /// Filesystem (or LUKS/swap) UUID
+    pub uuid: Option<String>,
+    /// GPT partition UUID, also set for msdos (`<disk id>-<number>`)
+    pub partuuid: Option<String>,
+}
+
+impl DeviceIds {
+    /// `UUID=...`, or `PARTUUID=...` when there is no filesystem UUID
+    pub fn fstab_spec(&self) -> Option<String> {
+        match (&self.uuid, &self.partuuid) {
+            (Some(uuid), _) => Some(format!("UUID={}", uuid)),
+            (None, Some(partuuid)) => Some(format!("PARTUUID={}", partuuid)),
+            (None, None) => None,
+        }
+    }
+}
+
+// ---------------------------------------------------------
+// Something on the disk that is in use (pre-flight checks)
+// ---------------------------------------------------------
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiskUseKind {
+    /// The running system or live ISO lives here
+    BootMedium(String),
+    Mounted(String),
+    Swap,
+    /// md array, LVM PV, LUKS or any other device-mapper user
+    Holder(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct DiskUse {
+    /// Kernel name of the disk or partition, e.g. `sda2`
+    pub device: String,
+    /// False if the whole disk itself is in use
+    pub is_partition: bool,
+    pub kind: DiskUseKind,
+}
+
+impl DiskUse {
+    /// Adding partitions into free space leaves busy partitions alone,
+    /// anything else would pull the disk out from under its user
+    pub fn overridable(&self, new_table: bool) -> bool {
+        self.is_partition && !new_table && !matches!(self.kind, DiskUseKind::BootMedium(_))
+    }
+
+    pub fn describe(&self) -> String {
+        match &self.kind {
+            DiskUseKind::BootMedium(mp) => format!("holds the running system ({})", mp),
+            DiskUseKind::Mounted(mp) => format!("is mounted at {}", mp),
+            DiskUseKind::Swap => "is active swap".to_string(),
+            DiskUseKind::Holder(holder) => format!("is in use by {}", holder),
+        }
+    }
+}
+
+// ---------------------------------------------------------
+// Run journal: finished steps and what they left on disk
+// ---------------------------------------------------------
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum InstallStep {
+    Wiped { mode: String },
+    TableBackedUp { path: PathBuf },
+    TableCreated { table: String },
+    PartitionsCreated { devices: Vec<String> },
+    MembersPartitioned { devices: Vec<String> },
+    Encrypted { device: String, mapper: String },
+    VolumesCreated { vg: String, volumes: Vec<String> },
+    Formatted { devices: Vec<String> },
+    Mounted { target: String },
+    SwapEnabled { kind: String },
+    FilesWritten { paths: Vec<String> },
+}
+
+impl InstallStep {
+    /// State of the disk once this step (and the ones before it) finished
+    pub fn disk_state(&self) -> String {
+        match self {
+            InstallStep::Wiped { mode } => {
+                format!("wiped ({}), no partition table", mode)
+            }
+            InstallStep::TableBackedUp { path } => {
+                format!("unchanged, partition table backed up to {}", path.display())
+            }
+            InstallStep::TableCreated { table } => {
+                format!("empty {} partition table, no partitions", table)
+            }
+            InstallStep::PartitionsCreated { devices } => format!(
+                "partitions {} created, nothing encrypted or formatted",
+                devices.join(", ")
+            ),
+            InstallStep::MembersPartitioned { devices } => format!(
+                "RAID member partitions {} created, nothing formatted",
+                devices.join(", ")
+            ),
+            InstallStep::Encrypted { device, mapper } => format!(
+                "{} is a LUKS2 container (opened as {}), nothing formatted inside",
+                device, mapper
+            ),
+            InstallStep::VolumesCreated { vg, volumes } => format!(
+                "volume group {} with {} created, nothing formatted",
+                vg,
+                volumes.join(", ")
+            ),
+            InstallStep::Formatted { devices } => format!(
+                "filesystems created on {}, nothing mounted",
+                devices.join(", ")
+            ),
+            InstallStep::Mounted { target } => {
+                format!("formatted and mounted under {}, no fstab yet", target)
+            }
+            InstallStep::SwapEnabled { kind } => {
+                format!("formatted and mounted, {} swap set up, no fstab yet", kind)
+            }
+            InstallStep::FilesWritten { paths } => {
+                format!("complete, {} written", paths.join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StepRecord {
+    pub finished_at: String,
+    #[serde(flatten)]
+    pub step: InstallStep,
+    pub disk_state: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", content = "error", rename_all = "snake_case")]
+pub enum RunOutcome {
+    Running,
+    Completed,
+    Interrupted(String),
+    Failed(String),
+}
+
+/// Undone in reverse order when the run is interrupted
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "action", content = "device", rename_all = "snake_case")]
+pub enum CleanupAction {
+    /// Target root, unmounted recursively
+    Unmount(String),
+    Swapoff(String),
+    DeactivateVg(String),
+    CloseLuks(String),
+    /// Loop device of `--image`, detached last
+    DetachLoop(String),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CleanupRecord {
+    #[serde(flatten)]
+    pub action: CleanupAction,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunRecord {
+    pub disk: String,
+    pub target: String,
+    pub started_at: String,
+    pub outcome: RunOutcome,
+    /// `disk_state` of the last finished step
+    pub disk_state: String,
+    pub steps: Vec<StepRecord>,
+    pub cleanup: Vec<CleanupRecord>,
 }
@@ -0,0 +1,442 @@
+use anyhow::{Context, Result, bail};
+use dialoguer::Confirm;
+use std::collections::HashSet;
+use std::process::Command;
+
+use crate::colors;
+use crate::commands::core::disk_setup::structs::CreatedPartitions;
+use crate::commands::core::disk_setup::structs::Filesystem;
+use crate::commands::core::disk_setup::structs::MountEntry;
+use crate::commands::core::disk_setup::structs::MountTree;
+use crate::commands::core::disk_setup::structs::Subvolume;
+use crate::helpers::CommandRunner;
+use crate::helpers::run_show;
+
+/// Btrfs defaults without compression, swapfiles must not be compressed
+pub const BTRFS_SWAP_MOUNT_OPTIONS: &str = "noatime,space_cache=v2,discard=async";
+
+// ---------------------------------------------------------
+// Default subvolume set (@, @home, @log, @pkg, @swap)
+// ---------------------------------------------------------
+pub fn default_subvolumes() -> Vec<Subvolume> {
+    let subvol = |name: &str, mount_point: &str, options: Option<&str>| Subvolume {
+        name: name.to_string(),
+        mount_point: mount_point.to_string(),
+        mount_options: options.map(str::to_string),
+    };
+
+    vec![
+        subvol("@", "/", None),
+        subvol("@home", "/home", None),
+        subvol("@log", "/var/log", None),
+        subvol("@pkg", "/var/cache/pacman/pkg", None),
+        subvol("@swap", "/swap", Some(BTRFS_SWAP_MOUNT_OPTIONS)),
+    ]
+}
+
+// ---------------------------------------------------------
+// Validate subvolume set
+// ---------------------------------------------------------
+pub fn validate_subvolumes(subvolumes: &[Subvolume]) -> Result<()> {
+    let mut names = HashSet::new();
+    let mut mount_points = HashSet::new();
+
+    for sv in subvolumes {
+        if sv.name.is_empty() || sv.name.contains('/') {
+            bail!("Subvolume name `{}` must be non-empty without `/`", sv.name);
+        }
+        if !sv.mount_point.starts_with('/') {
+            bail!(
+                "Subvolume {}: mount_point `{}` must be absolute",
+                sv.name,
+                sv.mount_point
+            );
+        }
+        if !names.insert(sv.name.as_str()) {
+            bail!("Subvolume `{}` is defined twice", sv.name);
+        }
+        if !mount_points.insert(sv.mount_point.as_str()) {
+            bail!("Subvolume mount_point `{}` is used twice", sv.mount_point);
+        }
+    }
+
+    if !mount_points.contains("/") {
+        bail!("No subvolume is mounted at `/`");
+    }
+
+    Ok(())
+}
+
+// ---------------------------------------------------------
+// Create subvolumes and mount the target tree
+// ---------------------------------------------------------
+pub fn mount_target(
+    runner: &dyn CommandRunner,
+    partitions: &CreatedPartitions,
+    subvolumes: Option<&[Subvolume]>,
+    target: &str,
+) -> Result<MountTree> {
+    say!("{}", colors::header("Mounting Target System"));
+
+    let root = partitions
+        .partitions
+        .iter()
+        .find(|p| p.spec.mount_point.as_deref() == Some("/"))
+        .context("No partition is mounted at `/`")?;
+
+    let mut mounts = Vec::new();
+
+    if root.spec.filesystem == Filesystem::Btrfs {
+        let subvolumes = match subvolumes {
+            Some(list) if !list.is_empty() => list.to_vec(),
+            Some(_) => default_subvolumes(),
+            None => {
+                let defaults = default_subvolumes();
+                print_subvolumes(&defaults);
+
+                let confirmed = Confirm::new()
+                    .with_prompt(colors::info(&format!(
+                        "Create these subvolumes and mount them under {}?",
+                        target
+                    )))
+                    .default(true)
+                    .interact()
+                    .context("Failed to get confirmation")?;
+
+                if !confirmed {
+                    bail!("User declined to create Btrfs subvolumes");
+                }
+
+                defaults
+            }
+        };
+
+        validate_subvolumes(&subvolumes)?;
+
+        create_subvolumes(runner, &root.fs_device(), &subvolumes, target)?;
+
+        let base_options = root
+            .spec
+            .mount_options
+            .as_deref()
+            .unwrap_or(Filesystem::Btrfs.default_mount_options());
+
+        for sv in &subvolumes {
+            let options = sv.mount_options.as_deref().unwrap_or(base_options);
+            mounts.push(MountEntry {
+                source: root.fs_device(),
+                mount_point: sv.mount_point.clone(),
+                fstype: Filesystem::Btrfs.fstype().to_string(),
+                options: format!("{},subvol={}", options, sv.name),
+            });
+        }
+    } else {
+        mounts.push(MountEntry {
+            source: root.fs_device(),
+            mount_point: "/".to_string(),
+            fstype: root.spec.filesystem.fstype().to_string(),
+            options: root
+                .spec
+                .mount_options
+                .clone()
+                .unwrap_or_else(|| root.spec.filesystem.default_mount_options().to_string()),
+        });
+    }
+
+    // Every other partition with a mount point (the ESP at /boot, ...)
+    for part in &partitions.partitions {
+        let Some(mp) = part.spec.mount_point.as_deref() else {
+            continue;
+        };
+        if mp == "/" {
+            continue;
+        }
+        if mounts.iter().any(|m| m.mount_point == mp) {
+            bail!(
+                "Partition {} and a subvolume are both mounted at `{}`",
+                part.spec.label,
+                mp
+            );
+        }
+
+        mounts.push(MountEntry {
+            source: part.fs_device(),
+            mount_point: mp.to_string(),
+            fstype: part.spec.filesystem.fstype().to_string(),
+            options: part
+                .spec
+                .mount_options
+                .clone()
+                .unwrap_or_else(|| part.spec.filesystem.default_mount_options().to_string()),
+        });
+    }
+
+    mount_entries(runner, &mut mounts, target)?;
+
+    say!(
+        "{}",
+        colors::success(&format!("✓ Target mounted at {}", target))
+    );
+    say!();
+
+    Ok(MountTree {
+        root: target.to_string(),
+        mounts,
+    })
+}
+
+// ---------------------------------------------------------
+// Mount entries under the target, parents first
+// ---------------------------------------------------------
+pub fn mount_entries(
+    runner: &dyn CommandRunner,
+    mounts: &mut [MountEntry],
+    target: &str,
+) -> Result<()> {
+    // Parents before children: "/" < "/var" < "/var/log"
+    mounts.sort_by_key(|m| mount_depth(&m.mount_point));
+
+    for m in mounts.iter() {
+        let path = target_path(target, &m.mount_point);
+
+        if runner.is_dry_run() {
+            say!("> mkdir -p {}", path);
+        } else {
+            std::fs::create_dir_all(&path)
+                .with_context(|| format!("Failed to create mount point {}", path))?;
+        }
+
+        let mut cmd = Command::new("mount");
+        cmd.args(["-t", &m.fstype, "-o", &m.options, &m.source, &path]);
+        run_show(runner, &mut cmd).with_context(|| format!("Failed to mount {}", path))?;
+    }
+
+    Ok(())
+}
+
+// ---------------------------------------------------------
+// Create subvolumes on the top-level Btrfs volume
+// ---------------------------------------------------------
+fn create_subvolumes(
+    runner: &dyn CommandRunner,
+    device: &str,
+    subvolumes: &[Subvolume],
+    target: &str,
+) -> Result<()> {
+    say!(
+        "{}",
+        colors::info(&format!("Creating Btrfs subvolumes on {}...", device))
+    );
+
+    if !runner.is_dry_run() {
+        std::fs::create_dir_all(target).with_context(|| format!("Failed to create {}", target))?;
+    }
+
+    // Mount the top-level volume (subvolid=5) temporarily
+    run_show(
+        runner,
+        Command::new("mount").args(["-o", "subvolid=5", device, target]),
+    )
+    .context("Failed to mount Btrfs top-level volume")?;
+
+    let result = subvolumes.iter().try_for_each(|sv| {
+        run_show(
+            runner,
+            Command::new("btrfs").args([
+                "subvolume",
+                "create",
+                &format!("{}/{}", target.trim_end_matches('/'), sv.name),
+            ]),
+        )
+        .map(|_| ())
+        .with_context(|| format!("Failed to create subvolume {}", sv.name))
+    });
+
+    // Always unmount the top-level volume, even if a subvolume failed
+    run_show(runner, Command::new("umount").arg(target))
+        .context("Failed to unmount Btrfs top-level volume")?;
+
+    result?;
+
+    say!("{}", colors::success("✓ Btrfs subvolumes created"));
+
+    Ok(())
+}
+
+// ---------------------------------------------------------
+// Print subvolume set
+// ---------------------------------------------------------
+pub fn print_subvolumes(subvolumes: &[Subvolume]) {
+    say!("\n{}\n", colors::header("Btrfs Subvolumes"));
+
+    let name_width = subvolumes
+        .iter()
+        .map(|s| s.name.len())
+        .max()
+        .unwrap_or(4)
+        .max(4);
+
+    for sv in subvolumes {
+        say!(
+            "  {:<name_w$}  {}  [{}]",
+            sv.name,
+            sv.mount_point,
+            sv.mount_options
+                .as_deref()
+                .unwrap_or(Filesystem::Btrfs.default_mount_options()),
+            name_w = name_width
+        );
+    }
+    say!();
+}
+
+// ---------------------------------------------------------
+// Path helpers
+// ---------------------------------------------------------
+pub fn target_path(target: &str, mount_point: &str) -> String {
+    if mount_point == "/" {
+        target.to_string()
+    } else {
+        format!("{}{}", target.trim_end_matches('/'), mount_point)
+    }
+}
+
+fn mount_depth(mount_point: &str) -> usize {
+    mount_point.split('/').filter(|c| !c.is_empty()).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::core::disk_setup::structs::CreatedPartition;
+    use crate::commands::core::disk_setup::structs::PlannedPartition;
+    use crate::commands::core::disk_setup::structs::SizeSpec;
+    use crate::helpers::runner::FakeRunner;
+
+    fn partition(number: u32, fs: Filesystem, mount_point: &str) -> CreatedPartition {
+        CreatedPartition {
+            number,
+            device: format!("/dev/sda{}", number),
+            spec: PlannedPartition {
+                size: SizeSpec::Rest,
+                type_code: String::new(),
+                label: format!("P{}", number),
+                filesystem: fs,
+                mount_point: Some(mount_point.to_string()),
+                mount_options: None,
+            },
+            encryption: None,
+            reused: false,
+            raid: None,
+        }
+    }
+
+    fn partitions() -> CreatedPartitions {
+        CreatedPartitions {
+            partitions: vec![
+                partition(1, Filesystem::Vfat, "/boot"),
+                partition(2, Filesystem::Btrfs, "/"),
+            ],
+        }
+    }
+
+    // Mount points are created for real, so the target is a scratch directory
+    fn target(test: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("sharch-test-{}-{}", test, std::process::id()));
+        dir.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn creates_each_subvolume_on_the_top_level_volume() {
+        let target = target("subvolumes");
+        let runner = FakeRunner::new();
+
+        let tree = mount_target(&runner, &partitions(), Some(&[]), &target);
+        std::fs::remove_dir_all(&target).unwrap();
+        let tree = tree.unwrap();
+
+        let calls = runner.calls();
+        assert_eq!(
+            calls[0],
+            format!("mount -o subvolid=5 /dev/sda2 {}", target)
+        );
+        let creates = runner.calls_to("btrfs");
+        assert_eq!(creates.len(), 5);
+        assert_eq!(creates[0], format!("btrfs subvolume create {}/@", target));
+        assert_eq!(calls[6], format!("umount {}", target));
+
+        // Parents first, each subvolume by name, @swap without compression
+        let mounts: Vec<&str> = tree.mounts.iter().map(|m| m.mount_point.as_str()).collect();
+        assert_eq!(
+            mounts,
+            [
+                "/",
+                "/home",
+                "/swap",
+                "/boot",
+                "/var/log",
+                "/var/cache/pacman/pkg"
+            ]
+        );
+        assert_eq!(
+            runner.calls_to("mount")[1],
+            format!(
+                "mount -t btrfs -o noatime,compress=zstd,space_cache=v2,discard=async,subvol=@ /dev/sda2 {}",
+                target
+            )
+        );
+        let swap = tree
+            .mounts
+            .iter()
+            .find(|m| m.mount_point == "/swap")
+            .unwrap();
+        assert_eq!(
+            swap.options,
+            format!("{},subvol=@swap", BTRFS_SWAP_MOUNT_OPTIONS)
+        );
+        assert!(!swap.options.contains("compress"));
+    }
+
+    #[test]
+    fn unmounts_the_top_level_volume_when_a_subvolume_fails() {
+        let target = target("subvolume-fails");
+        let runner = FakeRunner::new().fail(
+            &format!("btrfs subvolume create {}/@log", target),
+            1,
+            "ERROR: target path already exists",
+        );
+
+        let result = mount_target(&runner, &partitions(), Some(&[]), &target);
+        std::fs::remove_dir_all(&target).unwrap();
+
+        assert!(format!("{:#}", result.unwrap_err()).contains("@log"));
+        assert_eq!(runner.calls_to("btrfs").len(), 3);
+        assert_eq!(
+            runner.calls().last().unwrap(),
+            &format!("umount {}", target)
+        );
+        // Nothing was mounted below the target
+        assert_eq!(runner.calls_to("mount").len(), 1);
+    }
+
+    #[test]
+    fn other_filesystems_are_mounted_without_subvolumes() {
+        let target = target("ext4");
+        let runner = FakeRunner::new();
+        let mut partitions = partitions();
+        partitions.partitions[1].spec.filesystem = Filesystem::Ext4;
+
+        let tree = mount_target(&runner, &partitions, None, &target);
+        std::fs::remove_dir_all(&target).unwrap();
+
+        assert_eq!(tree.unwrap().mounts.len(), 2);
+        assert!(runner.calls_to("btrfs").is_empty());
+        assert_eq!(
+            runner.calls(),
+            [
+                format!("mount -t ext4 -o noatime /dev/sda2 {}", target),
+                format!("mount -t vfat -o umask=0077 /dev/sda1 {}/boot", target),
+            ]
+        );
+    }
+}
@@ -0,0 +1,477 @@
+use anyhow::{Context, Result, bail};
+use dialoguer::{Input, Select};
+use std::process::Command;
+
+use crate::colors;
+use crate::commands::core::disk_setup::size;
+use crate::commands::core::disk_setup::structs::CreatedPartitions;
+use crate::commands::core::disk_setup::structs::DiskLayout;
+use crate::commands::core::disk_setup::structs::Filesystem;
+use crate::commands::core::disk_setup::structs::MountTree;
+use crate::commands::core::disk_setup::structs::PartitionPlan;
+use crate::commands::core::disk_setup::structs::PlannedPartition;
+use crate::commands::core::disk_setup::structs::SizeSpec;
+use crate::commands::core::disk_setup::structs::SwapConfig;
+use crate::commands::core::disk_setup::structs::SwapKind;
+use crate::commands::core::disk_setup::structs::SwapSetup;
+use crate::commands::core::disk_setup::subvolumes;
+use crate::helpers::CommandRunner;
+use crate::helpers::{run_out, run_show};
+
+/// Swapfile name inside the swap directory
+pub const SWAPFILE_NAME: &str = "swapfile";
+
+// ---------------------------------------------------------
+// Installed RAM in MB (from /proc/meminfo)
+// ---------------------------------------------------------
+pub fn ram_mb() -> Result<u64> {
+    let meminfo =
+        std::fs::read_to_string("/proc/meminfo").context("Failed to read /proc/meminfo")?;
+
+    let kb = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|v| v.parse::<u64>().ok())
+        .context("MemTotal missing from /proc/meminfo")?;
+
+    Ok(kb.div_ceil(1024))
+}
+
+// ---------------------------------------------------------
+// Ask for the swap strategy
+// ---------------------------------------------------------
+pub fn choose_swap(root_fs: Filesystem, cli_kind: Option<SwapKind>) -> Result<Option<SwapConfig>> {
+    let kinds = [
+        SwapKind::File,
+        SwapKind::Partition,
+        SwapKind::Zram,
+        SwapKind::None,
+    ];
+
+    let kind = match cli_kind {
+        Some(kind) => kind,
+        None => {
+            let items = [
+                "Swapfile (in the @swap subvolume on Btrfs)",
+                "Swap partition",
+                "zram (compressed RAM, no disk space)",
+                "No swap",
+            ];
+
+            let selection = Select::new()
+                .with_prompt(colors::info("Swap"))
+                .items(items)
+                // Partitions are easier to get right outside of Btrfs
+                .default(if root_fs == Filesystem::Btrfs { 0 } else { 1 })
+                .interact()
+                .context("Swap selection aborted")?;
+
+            kinds[selection]
+        }
+    };
+
+    if kind == SwapKind::None {
+        return Ok(None);
+    }
+
+    let default_mb = if kind == SwapKind::Zram {
+        None
+    } else {
+        Some(ram_mb()?)
+    };
+
+    let prompt = match default_mb {
+        Some(_) => "Swap size, e.g. 8G (default: RAM size in MB)",
+        None => "zram size, e.g. 4G (empty: min(ram / 2, 4096 MB))",
+    };
+
+    let size: String = Input::new()
+        .with_prompt(colors::info(prompt))
+        .default(default_mb.map(|mb| mb.to_string()).unwrap_or_default())
+        .allow_empty(true)
+        .interact_text()
+        .context("Failed to get swap size")?;
+
+    let size_mb = if size.trim().is_empty() {
+        None
+    } else {
+        Some(size::parse_mib(&size).context("Invalid swap size")?)
+    };
+
+    Ok(Some(SwapConfig { kind, size_mb }))
+}
+
+// ---------------------------------------------------------
+// Swap config from the layout
+// ---------------------------------------------------------
+pub fn swap_from_layout(
+    layout: &DiskLayout,
+    partitions: &[PlannedPartition],
+) -> Result<Option<SwapConfig>> {
+    let swap_parts: Vec<&PlannedPartition> = partitions
+        .iter()
+        .filter(|p| p.filesystem == Filesystem::Swap)
+        .collect();
+
+    if swap_parts.len() > 1 {
+        bail!("Layout defines more than one swap partition");
+    }
+
+    if let Some(part) = swap_parts.first() {
+        if layout
+            .swap
+            .as_ref()
+            .is_some_and(|s| s.kind != SwapKind::Partition)
+        {
+            bail!("Layout has a swap partition and a different [swap] kind");
+        }
+
+        return Ok(Some(SwapConfig {
+            kind: SwapKind::Partition,
+            size_mb: part.size.fixed_bytes().map(|b| b / 1_048_576),
+        }));
+    }
+
+    match &layout.swap {
+        Some(config) if config.kind == SwapKind::Partition => {
+            bail!("[swap] kind = \"partition\" needs a [[partition]] with filesystem = \"swap\"")
+        }
+        Some(config) if config.kind == SwapKind::None => Ok(None),
+        Some(config) => {
+            match config.size_mb {
+                Some(0) => bail!("[swap] size_mb must be greater than 0"),
+                Some(mb) => {
+                    size::mib_to_bytes(mb).context("Invalid [swap] size_mb")?;
+                }
+                None => {}
+            }
+            Ok(Some(config.clone()))
+        }
+        None => Ok(None),
+    }
+}
+
+// ---------------------------------------------------------
+// Planned swap partition
+// ---------------------------------------------------------
+pub fn swap_partition(size_mb: u64) -> Result<PlannedPartition> {
+    Ok(PlannedPartition {
+        size: SizeSpec::Bytes(size::mib_to_bytes(size_mb).context("Invalid swap size")?),
+        type_code: "8200".to_string(),
+        label: "SWAP".to_string(),
+        filesystem: Filesystem::Swap,
+        mount_point: None,
+        mount_options: None,
+    })
+}
+
+// ---------------------------------------------------------
+// Activate swap after formatting/mounting
+// ---------------------------------------------------------
+pub fn setup_swap(
+    runner: &dyn CommandRunner,
+    plan: &PartitionPlan,
+    partitions: &CreatedPartitions,
+    tree: Option<&MountTree>,
+) -> Result<Option<SwapSetup>> {
+    let Some(config) = &plan.swap else {
+        return Ok(None);
+    };
+
+    say!("{}", colors::header("Setting Up Swap"));
+
+    let setup = match config.kind {
+        SwapKind::None => return Ok(None),
+        SwapKind::Partition => setup_partition(runner, partitions)?,
+        SwapKind::File => {
+            let tree = tree.context("A swapfile needs the target system to be mounted")?;
+            setup_file(runner, config, tree)?
+        }
+        SwapKind::Zram => {
+            let tree = tree.context("zram config needs the target system to be mounted")?;
+            setup_zram(runner, config, tree)?
+        }
+    };
+
+    if let Some(line) = setup.fstab_line() {
+        say!("{}", colors::info(&format!("fstab: {}", line)));
+    }
+    say!();
+
+    Ok(Some(setup))
+}
+
+// mkswap already ran in format_partitions, enable it for the install
+fn setup_partition(
+    runner: &dyn CommandRunner,
+    partitions: &CreatedPartitions,
+) -> Result<SwapSetup> {
+    let part = partitions
+        .partitions
+        .iter()
+        .find(|p| p.spec.filesystem == Filesystem::Swap)
+        .context("No swap partition was created")?;
+
+    run_show(runner, Command::new("swapon").arg(&part.device))
+        .context("Failed to enable swap partition")?;
+
+    let source = if runner.is_dry_run() {
+        part.device.clone()
+    } else {
+        let uuid = run_out(
+            runner,
+            Command::new("blkid").args(["-s", "UUID", "-o", "value", &part.device]),
+        )
+        .context("Failed to read swap partition UUID")?;
+        format!("UUID={}", uuid.trim())
+    };
+
+    say!(
+        "{}",
+        colors::success(&format!("✓ Swap partition {} enabled", part.device))
+    );
+
+    Ok(SwapSetup {
+        kind: SwapKind::Partition,
+        source: Some(source),
+    })
+}
+
+fn setup_file(
+    runner: &dyn CommandRunner,
+    config: &SwapConfig,
+    tree: &MountTree,
+) -> Result<SwapSetup> {
+    let size_mb = match config.size_mb {
+        Some(mb) => mb,
+        None => ram_mb()?,
+    };
+
+    // Prefer the dedicated swap subvolume, fall back to the root filesystem
+    let (dir, fstype) = match tree.mounts.iter().find(|m| m.mount_point == "/swap") {
+        Some(m) => ("/swap", m.fstype.as_str()),
+        None => {
+            let root = tree
+                .mounts
+                .iter()
+                .find(|m| m.mount_point == "/")
+                .context("Nothing is mounted at `/`")?;
+            ("/", root.fstype.as_str())
+        }
+    };
+
+    let file = format!("{}/{}", dir.trim_end_matches('/'), SWAPFILE_NAME);
+    let target_file = subvolumes::target_path(&tree.root, &file);
+
+    // `btrfs` itself was checked with the other tools before the disk was touched
+    if fstype == Filesystem::Btrfs.fstype() {
+        // No copy-on-write for anything created in the swap directory
+        if dir != "/" {
+            run_show(
+                runner,
+                Command::new("chattr").args(["+C", &subvolumes::target_path(&tree.root, dir)]),
+            )
+            .context("Failed to disable copy-on-write for the swap subvolume")?;
+        }
+
+        run_show(
+            runner,
+            Command::new("btrfs").args([
+                "filesystem",
+                "mkswapfile",
+                "--size",
+                &format!("{}m", size_mb),
+                &target_file,
+            ]),
+        )
+        .context("Failed to create Btrfs swapfile")?;
+    } else {
+        run_show(
+            runner,
+            Command::new("mkswap").args([
+                "--file",
+                &target_file,
+                "--size",
+                &format!("{}M", size_mb),
+            ]),
+        )
+        .context("Failed to create swapfile")?;
+    }
+
+    say!(
+        "{}",
+        colors::success(&format!("✓ {} MB swapfile created at {}", size_mb, file))
+    );
+
+    Ok(SwapSetup {
+        kind: SwapKind::File,
+        source: Some(file),
+    })
+}
+
+fn setup_zram(
+    runner: &dyn CommandRunner,
+    config: &SwapConfig,
+    tree: &MountTree,
+) -> Result<SwapSetup> {
+    let size = match config.size_mb {
+        Some(mb) => mb.to_string(),
+        None => "min(ram / 2, 4096)".to_string(),
+    };
+
+    let content = format!(
+        "[zram0]\nzram-size = {}\ncompression-algorithm = zstd\n",
+        size
+    );
+
+    let dir = subvolumes::target_path(&tree.root, "/etc/systemd");
+    let path = format!("{}/zram-generator.conf", dir);
+
+    if runner.is_dry_run() {
+        say!("> write {}:\n{}", path, content);
+    } else {
+        std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir))?;
+        std::fs::write(&path, &content).with_context(|| format!("Failed to write {}", path))?;
+    }
+
+    say!(
+        "{}",
+        colors::success(&format!("✓ zram configured in {}", path))
+    );
+    say!(
+        "{}",
+        colors::info("Install the `zram-generator` package in the new system to use it")
+    );
+
+    Ok(SwapSetup {
+        kind: SwapKind::Zram,
+        source: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::core::disk_setup::structs::CreatedPartition;
+    use crate::commands::core::disk_setup::structs::MountEntry;
+    use crate::helpers::runner::FakeRunner;
+
+    fn mount(mount_point: &str, fstype: &str) -> MountEntry {
+        MountEntry {
+            source: "/dev/sda2".to_string(),
+            mount_point: mount_point.to_string(),
+            fstype: fstype.to_string(),
+            options: String::new(),
+        }
+    }
+
+    fn file_config() -> SwapConfig {
+        SwapConfig {
+            kind: SwapKind::File,
+            size_mb: Some(8192),
+        }
+    }
+
+    #[test]
+    fn btrfs_swapfile_goes_into_the_nocow_swap_subvolume() {
+        let runner = FakeRunner::new();
+        let tree = MountTree {
+            root: "/mnt".to_string(),
+            mounts: vec![mount("/", "btrfs"), mount("/swap", "btrfs")],
+        };
+
+        let setup = setup_file(&runner, &file_config(), &tree).unwrap();
+
+        assert_eq!(
+            runner.calls(),
+            [
+                "chattr +C /mnt/swap",
+                "btrfs filesystem mkswapfile --size 8192m /mnt/swap/swapfile",
+            ]
+        );
+        assert_eq!(
+            setup.fstab_line().as_deref(),
+            Some("/swap/swapfile none swap defaults 0 0")
+        );
+    }
+
+    #[test]
+    fn other_filesystems_get_a_swapfile_in_the_root() {
+        let runner = FakeRunner::new();
+        let tree = MountTree {
+            root: "/mnt/".to_string(),
+            mounts: vec![mount("/", "ext4"), mount("/boot", "vfat")],
+        };
+
+        let setup = setup_file(&runner, &file_config(), &tree).unwrap();
+
+        assert_eq!(runner.calls(), ["mkswap --file /mnt/swapfile --size 8192M"]);
+        assert_eq!(setup.kind, SwapKind::File);
+        assert_eq!(
+            setup.fstab_line().as_deref(),
+            Some("/swapfile none swap defaults 0 0")
+        );
+    }
+
+    #[test]
+    fn swap_partition_is_enabled_and_listed_by_uuid() {
+        let runner = FakeRunner::new().respond("blkid", "0a1b2c3d-0000-4000-8000-000000000000\n");
+        let partitions = CreatedPartitions {
+            partitions: vec![CreatedPartition {
+                number: 2,
+                device: "/dev/sda2".to_string(),
+                spec: swap_partition(4096).unwrap(),
+                encryption: None,
+                reused: false,
+                raid: None,
+            }],
+        };
+
+        let setup = setup_partition(&runner, &partitions).unwrap();
+
+        assert_eq!(runner.calls()[0], "swapon /dev/sda2");
+        assert_eq!(
+            setup.fstab_line().as_deref(),
+            Some("UUID=0a1b2c3d-0000-4000-8000-000000000000 none swap defaults 0 0")
+        );
+    }
+
+    #[test]
+    fn layout_swap_settings_must_agree() {
+        let swap_partition = r#"
+            [[partition]]
+            size = "8G"
+            type = "8200"
+            label = "SWAP"
+            filesystem = "swap"
+        "#;
+        let from_layout = |toml: &str| {
+            let layout: DiskLayout = toml::from_str(&format!("disk = \"sda\"\n{}", toml)).unwrap();
+            swap_from_layout(&layout, &layout.partitions)
+        };
+
+        let config = from_layout(swap_partition).unwrap().unwrap();
+        assert_eq!(config.kind, SwapKind::Partition);
+        assert_eq!(config.size_mb, Some(8192));
+
+        let file = "[swap]\nkind = \"file\"\nsize_mb = 4096\n";
+        assert_eq!(from_layout(file).unwrap().unwrap().size_mb, Some(4096));
+        assert!(from_layout("[swap]\nkind = \"none\"\n").unwrap().is_none());
+
+        // A swap partition next to a different [swap] kind
+        assert!(from_layout(&format!("{}{}", file, swap_partition)).is_err());
+        // Two swap partitions
+        assert!(from_layout(&format!("{}{}", swap_partition, swap_partition)).is_err());
+        // kind = "partition" without the partition
+        assert!(from_layout("[swap]\nkind = \"partition\"\n").is_err());
+        assert!(from_layout("[swap]\nkind = \"file\"\nsize_mb = 0\n").is_err());
+        assert!(
+            from_layout(&format!(
+                "[swap]\nkind = \"file\"\nsize_mb = {}\n",
+                u64::MAX / 1024
+            ))
+            .is_err()
+        );
+    }
+}
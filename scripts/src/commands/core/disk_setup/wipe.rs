@@ -0,0 +1,275 @@
+use anyhow::{Context, Result, bail};
+use dialoguer::Confirm;
+use std::io::{IsTerminal, Write};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use crate::colors;
+use crate::commands::core::disk_setup::devices;
+use crate::commands::core::disk_setup::helpers;
+use crate::commands::core::disk_setup::size;
+use crate::commands::core::disk_setup::structs::WipeMode;
+use crate::helpers::CommandRunner;
+use crate::helpers::interrupt;
+use crate::helpers::{ensure_tool_exists, run_out, run_show};
+use crate::output;
+
+/// Bytes written per call while zeroing
+const ZERO_CHUNK: usize = 4 * 1_048_576;
+
+// ---------------------------------------------------------
+// Clear the disk before it gets a new partition table
+// ---------------------------------------------------------
+pub fn wipe_disk(
+    runner: &dyn CommandRunner,
+    disk_path: &str,
+    mode: WipeMode,
+    assume_yes: bool,
+) -> Result<()> {
+    say!("{}", colors::header(&format!("Wiping {}", disk_path)));
+
+    check_wipe(disk_path, mode)?;
+    confirm_wipe(runner, disk_path, mode, assume_yes)?;
+
+    match mode {
+        WipeMode::Signatures => wipe_signatures(runner, disk_path)?,
+        WipeMode::Discard => {
+            // Discarded blocks do not always read back as zeros, so signatures go first
+            wipe_signatures(runner, disk_path)?;
+            run_show(runner, Command::new("blkdiscard").args(["-f", disk_path]))
+                .context("Failed to discard the disk")?;
+        }
+        WipeMode::Zero => zero_disk(runner, disk_path)?,
+        WipeMode::NvmeFormat => {
+            // --ses=1: user data erase
+            run_show(
+                runner,
+                Command::new("nvme").args(["format", disk_path, "--ses=1", "--force"]),
+            )
+            .context("Failed to format the NVMe namespace")?;
+        }
+    }
+
+    if !runner.is_dry_run() {
+        devices::reread_partitions(runner, disk_path);
+        say!("{}\n", colors::success(&format!("✓ {} wiped", disk_path)));
+    }
+
+    Ok(())
+}
+
+// Refuse early, before planning or asking, if the disk cannot do what was asked
+pub fn check_wipe(disk_path: &str, mode: WipeMode) -> Result<()> {
+    for tool in mode.tools() {
+        ensure_tool_exists(tool)?;
+    }
+
+    match mode {
+        WipeMode::Discard if !supports_discard(disk_path)? => bail!(
+            "{} does not support discard (discard_granularity is 0), use --wipe zero instead",
+            disk_path
+        ),
+        WipeMode::NvmeFormat if !devices::kernel_name(disk_path)?.starts_with("nvme") => {
+            bail!("{} is not an NVMe namespace", disk_path)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn confirm_wipe(
+    runner: &dyn CommandRunner,
+    disk_path: &str,
+    mode: WipeMode,
+    assume_yes: bool,
+) -> Result<()> {
+    if runner.is_dry_run() {
+        say!(
+            "{}",
+            colors::info(&format!(
+                "[DRY RUN] Would {} ({})",
+                mode.describe(),
+                disk_path
+            ))
+        );
+        return Ok(());
+    }
+
+    if assume_yes {
+        say!(
+            "{}",
+            colors::warn(&format!(
+                "About to {} ({}), confirmed by --yes",
+                mode.describe(),
+                disk_path
+            ))
+        );
+        return Ok(());
+    }
+
+    if !std::io::stdin().is_terminal() {
+        bail!(
+            "Refusing to wipe {} without confirmation, pass --yes when running non-interactively",
+            disk_path
+        );
+    }
+
+    let confirmed = Confirm::new()
+        .with_prompt(colors::warn(&format!(
+            "{}: {}. Continue?",
+            disk_path,
+            mode.describe()
+        )))
+        .default(false)
+        .interact()
+        .context("Failed to get confirmation")?;
+
+    if !confirmed {
+        bail!("User declined to wipe {}", disk_path);
+    }
+
+    Ok(())
+}
+
+// ---------------------------------------------------------
+// wipefs -a on every partition, then on the disk itself
+// ---------------------------------------------------------
+pub fn wipe_signatures(runner: &dyn CommandRunner, disk_path: &str) -> Result<()> {
+    let out = run_out(
+        runner,
+        Command::new("lsblk").args(["-lnpo", "NAME,TYPE", disk_path]),
+    )
+    .context("Failed to list partitions")?;
+
+    // Partitions first: once the table is gone their device nodes disappear
+    let partitions: Vec<&str> = out
+        .lines()
+        .filter_map(
+            |line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [name, "part"] => Some(name),
+                _ => None,
+            },
+        )
+        .collect();
+
+    for device in partitions.iter().rev().copied().chain([disk_path]) {
+        run_show(runner, Command::new("wipefs").args(["-a", device]))
+            .with_context(|| format!("Failed to wipe signatures on {}", device))?;
+    }
+
+    Ok(())
+}
+
+// Discard support as the kernel reports it (0 = none)
+fn supports_discard(disk_path: &str) -> Result<bool> {
+    let name = devices::kernel_name(disk_path)?;
+    let path = format!("/sys/class/block/{}/queue/discard_granularity", name);
+
+    let granularity =
+        std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path))?;
+
+    Ok(granularity.trim().parse::<u64>().unwrap_or(0) > 0)
+}
+
+// ---------------------------------------------------------
+// Overwrite the disk with zeros, printing progress
+// ---------------------------------------------------------
+fn zero_disk(runner: &dyn CommandRunner, disk_path: &str) -> Result<()> {
+    let total = helpers::get_disk_size(runner, disk_path)?;
+
+    if runner.is_dry_run() {
+        say!(
+            "{}",
+            colors::info(&format!(
+                "[DRY RUN] Would write {} of zeros to {}",
+                size::format_size(total),
+                disk_path
+            ))
+        );
+        return Ok(());
+    }
+
+    let mut disk = std::fs::OpenOptions::new()
+        .write(true)
+        .open(disk_path)
+        .with_context(|| format!("Failed to open {} for writing", disk_path))?;
+
+    let zeros = vec![0u8; ZERO_CHUNK];
+    let started = Instant::now();
+    let mut last_report = Instant::now();
+    let mut written: u64 = 0;
+
+    while written < total {
+        // Ctrl-C stops between chunks instead of after the whole disk
+        if let Err(e) = interrupt::check() {
+            say!();
+            return Err(e.context(format!(
+                "Stopped zeroing {} after {} of {}",
+                disk_path,
+                size::format_size(written),
+                size::format_size(total)
+            )));
+        }
+
+        let len = (total - written).min(ZERO_CHUNK as u64) as usize;
+        disk.write_all(&zeros[..len])
+            .with_context(|| format!("Failed writing zeros at offset {}", written))?;
+        written += len as u64;
+
+        if last_report.elapsed() >= Duration::from_secs(1) || written == total {
+            last_report = Instant::now();
+            let rate = written as f64 / started.elapsed().as_secs_f64().max(0.001);
+            output::print_human(format_args!(
+                "\r  {} / {} ({}%), {}/s   ",
+                size::format_size(written),
+                size::format_size(total),
+                written * 100 / total,
+                size::format_size(rate as u64)
+            ));
+        }
+    }
+    say!();
+
+    disk.sync_all()
+        .with_context(|| format!("Failed to flush {}", disk_path))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::runner::FakeRunner;
+
+    #[test]
+    fn wipes_partitions_before_the_disk() {
+        let runner = FakeRunner::new().respond(
+            "lsblk -lnpo NAME,TYPE /dev/sda",
+            "/dev/sda   disk\n/dev/sda1  part\n/dev/sda2  part\n/dev/dm-0 crypt\n",
+        );
+
+        wipe_signatures(&runner, "/dev/sda").unwrap();
+
+        assert_eq!(
+            runner.calls_to("wipefs"),
+            [
+                "wipefs -a /dev/sda2",
+                "wipefs -a /dev/sda1",
+                "wipefs -a /dev/sda"
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_when_a_partition_cannot_be_wiped() {
+        let runner = FakeRunner::new()
+            .respond("lsblk", "/dev/sda disk\n/dev/sda1 part\n")
+            .fail(
+                "wipefs -a /dev/sda1",
+                1,
+                "wipefs: error: /dev/sda1: probing initialization failed: Device or resource busy",
+            );
+
+        assert!(wipe_signatures(&runner, "/dev/sda").is_err());
+        assert_eq!(runner.calls_to("wipefs"), ["wipefs -a /dev/sda1"]);
+    }
+}
@@ -0,0 +1,498 @@
+use anyhow::{Context, Result, bail};
+use std::process::Command;
+
+use crate::colors;
+use crate::commands::core::disk_setup::subvolumes::target_path;
+use crate::commands::core::hibernate::structs::HibernateReport;
+use crate::commands::core::hibernate::structs::ResumeParams;
+use crate::commands::core::hibernate::structs::SwapTarget;
+use crate::helpers::CommandRunner;
+use crate::helpers::run_out;
+
+// ---------------------------------------------------------
+// Find the swap device or file
+// ---------------------------------------------------------
+pub fn find_swap(
+    runner: &dyn CommandRunner,
+    root: &str,
+    explicit: Option<&str>,
+) -> Result<SwapTarget> {
+    if let Some(swap) = explicit {
+        return classify_swap(runner, swap);
+    }
+
+    // The installed system's fstab is the source of truth
+    let fstab_path = target_path(root, "/etc/fstab");
+    if let Ok(fstab) = std::fs::read_to_string(&fstab_path) {
+        for line in fstab.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() >= 3 && fields[2] == "swap" {
+                return classify_swap(runner, fields[0]);
+            }
+        }
+    }
+
+    // Fall back to whatever is active right now (only meaningful with --root /)
+    if root == "/" {
+        let swaps = std::fs::read_to_string("/proc/swaps").context("Failed to read /proc/swaps")?;
+        for line in swaps.lines().skip(1) {
+            let mut fields = line.split_whitespace();
+            if let (Some(name), Some(kind)) = (fields.next(), fields.next()) {
+                // zram is useless for hibernation
+                if !name.starts_with("/dev/zram") {
+                    return Ok(if kind == "file" {
+                        SwapTarget::File(name.to_string())
+                    } else {
+                        SwapTarget::Device(name.to_string())
+                    });
+                }
+            }
+        }
+    }
+
+    bail!(
+        "No swap found in {} or /proc/swaps; pass --swap <device|file>",
+        fstab_path
+    );
+}
+
+fn classify_swap(runner: &dyn CommandRunner, spec: &str) -> Result<SwapTarget> {
+    if let Some((key, value)) = spec.split_once('=') {
+        let device = run_out(
+            runner,
+            Command::new("findfs").arg(format!("{}={}", key, value)),
+        )
+        .with_context(|| format!("Failed to resolve {}", spec))?;
+        return Ok(SwapTarget::Device(device.trim().to_string()));
+    }
+
+    if spec.starts_with("/dev/") {
+        Ok(SwapTarget::Device(spec.to_string()))
+    } else {
+        Ok(SwapTarget::File(spec.to_string()))
+    }
+}
+
+// ---------------------------------------------------------
+// Swap size in bytes
+// ---------------------------------------------------------
+pub fn swap_size(runner: &dyn CommandRunner, root: &str, swap: &SwapTarget) -> Result<u64> {
+    match swap {
+        SwapTarget::Device(dev) => {
+            let out = run_out(runner, Command::new("blockdev").args(["--getsize64", dev]))
+                .context("Failed to get swap partition size")?;
+            out.trim()
+                .parse()
+                .context("Failed to parse swap partition size")
+        }
+        SwapTarget::File(file) => {
+            let path = target_path(root, file);
+            Ok(std::fs::metadata(&path)
+                .with_context(|| format!("Failed to stat {}", path))?
+                .len())
+        }
+    }
+}
+
+// ---------------------------------------------------------
+// resume= and resume_offset=
+// ---------------------------------------------------------
+pub fn resume_params(
+    runner: &dyn CommandRunner,
+    root: &str,
+    swap: &SwapTarget,
+) -> Result<ResumeParams> {
+    match swap {
+        SwapTarget::Device(dev) => {
+            let uuid = run_out(
+                runner,
+                Command::new("blkid").args(["-s", "UUID", "-o", "value", dev]),
+            )
+            .context("Failed to read swap UUID")?;
+
+            Ok(ResumeParams {
+                uuid: uuid.trim().to_string(),
+                offset: None,
+            })
+        }
+        SwapTarget::File(file) => {
+            let path = target_path(root, file);
+
+            let out = run_out(
+                runner,
+                Command::new("findmnt").args(["-no", "UUID,FSTYPE", "-T", &path]),
+            )
+            .context("Failed to find the filesystem holding the swapfile")?;
+
+            let mut fields = out.split_whitespace();
+            let (Some(uuid), Some(fstype)) = (fields.next(), fields.next()) else {
+                bail!("findmnt returned no UUID for {}", path);
+            };
+
+            let offset = if fstype == "btrfs" {
+                let out = run_out(
+                    runner,
+                    Command::new("btrfs").args(["inspect-internal", "map-swapfile", "-r", &path]),
+                )
+                .context("Failed to compute swapfile offset")?;
+
+                out.trim()
+                    .parse::<u64>()
+                    .context("Failed to parse btrfs map-swapfile output")?
+            } else {
+                filefrag_offset(runner, &path)?
+            };
+
+            Ok(ResumeParams {
+                uuid: uuid.to_string(),
+                offset: Some(offset),
+            })
+        }
+    }
+}
+
+// First physical extent from `filefrag -v` (ext4, xfs, f2fs)
+fn filefrag_offset(runner: &dyn CommandRunner, path: &str) -> Result<u64> {
+    let out = run_out(runner, Command::new("filefrag").args(["-v", path]))
+        .context("Failed to run filefrag")?;
+
+    parse_filefrag(&out).context("Could not read the swapfile's first extent from filefrag")
+}
+
+// "   0:        0..       0:      34816..     34816:      1:"
+fn parse_filefrag(out: &str) -> Option<u64> {
+    out.lines()
+        .find(|line| line.trim_start().starts_with("0:"))
+        .and_then(|line| line.split(':').nth(2))
+        .and_then(|field| field.trim().split("..").next())
+        .and_then(|start| start.trim().parse().ok())
+}
+
+// ---------------------------------------------------------
+// Replace resume parameters in a kernel command line
+// ---------------------------------------------------------
+pub fn with_resume_params(cmdline: &str, params: &[String]) -> String {
+    let mut words: Vec<&str> = cmdline
+        .split_whitespace()
+        .filter(|w| !w.starts_with("resume=") && !w.starts_with("resume_offset="))
+        .collect();
+
+    words.extend(params.iter().map(String::as_str));
+    words.join(" ")
+}
+
+// ---------------------------------------------------------
+// systemd-boot entries and GRUB defaults
+// ---------------------------------------------------------
+pub fn update_bootloader(
+    runner: &dyn CommandRunner,
+    root: &str,
+    params: &[String],
+) -> Result<Vec<String>> {
+    let mut updated = Vec::new();
+
+    // systemd-boot: the ESP can be at /boot or /efi (with XBOOTLDR at /boot)
+    for dir in ["/boot/loader/entries", "/efi/loader/entries"] {
+        let dir = target_path(root, dir);
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("conf") {
+                continue;
+            }
+
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+
+            let mut found = false;
+            let lines: Vec<String> = content
+                .lines()
+                .map(|line| match line.strip_prefix("options") {
+                    Some(rest) if rest.starts_with(char::is_whitespace) => {
+                        found = true;
+                        format!("options {}", with_resume_params(rest, params))
+                    }
+                    _ => line.to_string(),
+                })
+                .collect();
+
+            // Entries without an options line are left alone (e.g. Windows chainloads)
+            if !found {
+                continue;
+            }
+
+            write_file(
+                runner,
+                &path.display().to_string(),
+                &(lines.join("\n") + "\n"),
+            )?;
+            updated.push(path.display().to_string());
+        }
+    }
+
+    // GRUB: GRUB_CMDLINE_LINUX_DEFAULT="..."
+    let grub = target_path(root, "/etc/default/grub");
+    if let Ok(content) = std::fs::read_to_string(&grub) {
+        let new_content = grub_with_resume_params(&content, params);
+        if new_content != content {
+            write_file(runner, &grub, &new_content)?;
+        }
+        updated.push(grub);
+    }
+
+    Ok(updated)
+}
+
+// Rewrites GRUB_CMDLINE_LINUX_DEFAULT, or appends it if the file has none
+fn grub_with_resume_params(content: &str, params: &[String]) -> String {
+    const KEY: &str = "GRUB_CMDLINE_LINUX_DEFAULT=";
+
+    let mut found = false;
+    let mut lines: Vec<String> = content
+        .lines()
+        .map(|line| {
+            let indent = &line[..line.len() - line.trim_start().len()];
+            let Some(value) = line.trim_start().strip_prefix(KEY) else {
+                return line.to_string();
+            };
+
+            found = true;
+            let (cmdline, rest) = split_quoted(value);
+            format!(
+                "{}{}\"{}\"{}",
+                indent,
+                KEY,
+                with_resume_params(cmdline, params),
+                rest
+            )
+        })
+        .collect();
+
+    if !found {
+        lines.push(format!("{}\"{}\"", KEY, with_resume_params("", params)));
+    }
+
+    lines.join("\n") + "\n"
+}
+
+// `"quiet splash" # comment` -> (`quiet splash`, ` # comment`), one pair of quotes only
+fn split_quoted(value: &str) -> (&str, &str) {
+    match value.chars().next() {
+        Some(quote @ ('"' | '\'')) => match value[1..].find(quote) {
+            Some(end) => (&value[1..end + 1], &value[end + 2..]),
+            None => (&value[1..], ""),
+        },
+        _ => value
+            .split_once(char::is_whitespace)
+            .map_or((value, ""), |(word, _)| (word, &value[word.len()..])),
+    }
+}
+
+// ---------------------------------------------------------
+// mkinitcpio resume hook
+// ---------------------------------------------------------
+pub fn update_mkinitcpio(runner: &dyn CommandRunner, root: &str) -> Result<String> {
+    let path = target_path(root, "/etc/mkinitcpio.conf");
+    let content =
+        std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path))?;
+
+    let Some((new_content, outcome)) = hooks_with_resume(&content) else {
+        bail!("No HOOKS=(...) line found in {}", path);
+    };
+
+    if new_content != content {
+        write_file(runner, &path, &new_content)?;
+    }
+
+    Ok(outcome.to_string())
+}
+
+// Adds `resume` to the HOOKS line, keeping indentation and trailing comments
+fn hooks_with_resume(content: &str) -> Option<(String, &'static str)> {
+    let mut outcome = None;
+
+    let lines: Vec<String> = content
+        .lines()
+        .map(|line| {
+            let indent = &line[..line.len() - line.trim_start().len()];
+            let Some((hooks, rest)) = line
+                .trim_start()
+                .strip_prefix("HOOKS=(")
+                .and_then(|value| value.split_once(')'))
+            else {
+                return line.to_string();
+            };
+
+            let mut hooks: Vec<&str> = hooks.split_whitespace().collect();
+
+            if hooks.contains(&"systemd") {
+                outcome = Some("systemd initramfs resumes on its own, no hook needed");
+                return line.to_string();
+            }
+            if hooks.contains(&"resume") {
+                outcome = Some("`resume` hook already present");
+                return line.to_string();
+            }
+
+            // After udev/encrypt/lvm2, before filesystems
+            let pos = hooks
+                .iter()
+                .position(|h| *h == "filesystems")
+                .unwrap_or(hooks.len());
+            hooks.insert(pos, "resume");
+
+            outcome = Some("added `resume` hook, run `mkinitcpio -P` in the new system");
+            format!("{}HOOKS=({}){}", indent, hooks.join(" "), rest)
+        })
+        .collect();
+
+    outcome.map(|outcome| (lines.join("\n") + "\n", outcome))
+}
+
+fn write_file(runner: &dyn CommandRunner, path: &str, content: &str) -> Result<()> {
+    if runner.is_dry_run() {
+        say!("> write {}", path);
+        return Ok(());
+    }
+
+    std::fs::write(path, content).with_context(|| format!("Failed to write {}", path))
+}
+
+// ---------------------------------------------------------
+// Verification report
+// ---------------------------------------------------------
+pub fn print_report(report: &HibernateReport) {
+    say!("\n{}\n", colors::header("Hibernation Report"));
+
+    let gib = |bytes: u64| bytes as f64 / 1_073_741_824.0;
+    let big_enough = report.swap_bytes >= report.ram_bytes;
+
+    say!("  Swap:        {}", report.swap);
+    say!(
+        "  Size:        {:.2} GiB (RAM {:.2} GiB) {}",
+        gib(report.swap_bytes),
+        gib(report.ram_bytes),
+        if big_enough {
+            colors::success("✓")
+        } else {
+            colors::error("✗")
+        }
+    );
+    say!("  Parameters:  {}", report.params.join(" "));
+
+    if report.updated_entries.is_empty() {
+        say!(
+            "  Bootloader:  {}",
+            colors::warn(
+                "no systemd-boot entries or GRUB config found, add the parameters by hand"
+            )
+        );
+    } else {
+        for entry in &report.updated_entries {
+            say!("  Bootloader:  {} {}", entry, colors::success("✓"));
+        }
+        if report.updated_entries.iter().any(|e| e.ends_with("grub")) {
+            say!(
+                "               {}",
+                colors::info("run `grub-mkconfig -o /boot/grub/grub.cfg` in the new system")
+            );
+        }
+    }
+
+    say!("  mkinitcpio:  {}", report.mkinitcpio);
+    say!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> Vec<String> {
+        vec![
+            "resume=UUID=1234".to_string(),
+            "resume_offset=34816".to_string(),
+        ]
+    }
+
+    #[test]
+    fn replaces_old_resume_params() {
+        assert_eq!(
+            with_resume_params("rw quiet resume=UUID=old resume_offset=1", &params()),
+            "rw quiet resume=UUID=1234 resume_offset=34816"
+        );
+        assert_eq!(
+            with_resume_params("", &params()),
+            "resume=UUID=1234 resume_offset=34816"
+        );
+    }
+
+    #[test]
+    fn reads_the_first_extent_from_filefrag() {
+        let out = "\
+Filesystem type is: ef53
+File size of /swapfile is 8589934592 (2097152 blocks of 4096 bytes)
+ ext:     logical_offset:        physical_offset: length:   expected: flags:
+   0:        0..   32767:      34816..     67583:  32768:
+   1:    32768..   65535:     100352..    133119:  32768:      67584:
+/swapfile: 2 extents found
+";
+        assert_eq!(parse_filefrag(out), Some(34816));
+        assert_eq!(parse_filefrag("Filesystem type is: ef53\n"), None);
+    }
+
+    #[test]
+    fn rewrites_the_grub_cmdline() {
+        let grub =
+            "GRUB_TIMEOUT=5\nGRUB_CMDLINE_LINUX_DEFAULT=\"loglevel=3 quiet\" # set by sharch\n";
+        assert_eq!(
+            grub_with_resume_params(grub, &params()),
+            "GRUB_TIMEOUT=5\nGRUB_CMDLINE_LINUX_DEFAULT=\"loglevel=3 quiet resume=UUID=1234 resume_offset=34816\" # set by sharch\n"
+        );
+
+        // Only the surrounding quotes go, quotes inside the value stay
+        let quoted = "GRUB_CMDLINE_LINUX_DEFAULT='acpi_osi=\"Windows 2015\"'\n";
+        assert_eq!(
+            grub_with_resume_params(quoted, &params()),
+            "GRUB_CMDLINE_LINUX_DEFAULT=\"acpi_osi=\"Windows 2015\" resume=UUID=1234 resume_offset=34816\"\n"
+        );
+    }
+
+    #[test]
+    fn appends_a_missing_grub_cmdline() {
+        assert_eq!(
+            grub_with_resume_params("GRUB_TIMEOUT=5\n", &params()),
+            "GRUB_TIMEOUT=5\nGRUB_CMDLINE_LINUX_DEFAULT=\"resume=UUID=1234 resume_offset=34816\"\n"
+        );
+    }
+
+    #[test]
+    fn adds_the_resume_hook_before_filesystems() {
+        let conf = "# HOOKS=(base udev)\n  HOOKS=(base udev autodetect block filesystems fsck) # default\n";
+        let (content, outcome) = hooks_with_resume(conf).unwrap();
+
+        assert_eq!(
+            content,
+            "# HOOKS=(base udev)\n  HOOKS=(base udev autodetect block resume filesystems fsck) # default\n"
+        );
+        assert!(outcome.starts_with("added"));
+    }
+
+    #[test]
+    fn leaves_systemd_and_existing_resume_hooks_alone() {
+        let systemd = "HOOKS=(base systemd autodetect sd-encrypt filesystems)\n";
+        assert_eq!(hooks_with_resume(systemd).unwrap().0, systemd);
+
+        let resume = "HOOKS=(base udev resume filesystems)\n";
+        assert_eq!(hooks_with_resume(resume).unwrap().0, resume);
+
+        assert!(hooks_with_resume("# HOOKS=(base udev)\nMODULES=()\n").is_none());
+    }
+}
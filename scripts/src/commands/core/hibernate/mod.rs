@@ -0,0 +1,103 @@
+pub mod helpers;
+pub mod structs;
+
+use anyhow::{Context, bail};
+
+use crate::colors;
+use crate::commands::core::disk_setup::swap::ram_mb;
+use crate::commands::core::hibernate::structs::HibernateReport;
+use crate::commands::core::hibernate::structs::SwapTarget;
+use crate::helpers::runner::{CommandRunner, DryRunRunner, RealRunner};
+
+#[derive(clap::Args, Debug)]
+/// Configure resume-from-swap (hibernation)
+pub struct HibernateArgs {
+    #[command(subcommand)]
+    pub command: HibernateCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum HibernateCommand {
+    /// Compute resume parameters and write them into the bootloader and mkinitcpio.conf
+    Setup(HibernateSetupArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct HibernateSetupArgs {
+    /// Root of the installed system (e.g. /mnt from the live ISO)
+    #[clap(long, default_value = "/")]
+    pub root: String,
+
+    /// Swap device or swapfile path inside the installed system (default: from fstab)
+    #[clap(long)]
+    pub swap: Option<String>,
+
+    /// Configure even if swap is smaller than RAM
+    #[clap(long)]
+    pub force: bool,
+
+    /// If set, do not perform any changes, just simulate
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+pub fn handle(args: HibernateArgs) -> anyhow::Result<()> {
+    match args.command {
+        HibernateCommand::Setup(args) => setup(args),
+    }
+}
+
+fn setup(args: HibernateSetupArgs) -> anyhow::Result<()> {
+    say!("{}", colors::header("Hibernation Setup"));
+
+    let runner: Box<dyn CommandRunner> = if args.dry_run {
+        Box::new(DryRunRunner)
+    } else {
+        Box::new(RealRunner)
+    };
+    let runner = runner.as_ref();
+
+    let swap = helpers::find_swap(runner, &args.root, args.swap.as_deref())?;
+    let swap_name = match &swap {
+        SwapTarget::Device(dev) => dev.clone(),
+        SwapTarget::File(file) => file.clone(),
+    };
+    say!(
+        "{}",
+        colors::info(&format!("Swap: {}", colors::highlight(&swap_name)))
+    );
+
+    // Hibernation writes the whole RAM image into swap
+    let swap_bytes = helpers::swap_size(runner, &args.root, &swap)?;
+    let ram_bytes = ram_mb()? * 1_048_576;
+
+    if swap_bytes < ram_bytes {
+        let msg = format!(
+            "Swap ({} MB) is smaller than RAM ({} MB), hibernation can fail",
+            swap_bytes / 1_048_576,
+            ram_bytes / 1_048_576
+        );
+        if !args.force {
+            bail!("{}; grow the swap or pass --force", msg);
+        }
+        say!("{}", colors::warn(&msg));
+    }
+
+    let params = helpers::resume_params(runner, &args.root, &swap)
+        .context("Failed to compute resume parameters")?
+        .cmdline();
+
+    let updated_entries = helpers::update_bootloader(runner, &args.root, &params)?;
+    let mkinitcpio = helpers::update_mkinitcpio(runner, &args.root)?;
+
+    helpers::print_report(&HibernateReport {
+        swap: swap_name,
+        swap_bytes,
+        ram_bytes,
+        params,
+        updated_entries,
+        mkinitcpio,
+    });
+
+    Ok(())
+}
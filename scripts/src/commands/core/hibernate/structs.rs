@@ -0,0 +1,46 @@
+// ---------------------------------------------------------
+// Swap that the system resumes from
+// ---------------------------------------------------------
+#[derive(Debug, Clone)]
+pub enum SwapTarget {
+    /// Swap partition (resolved device node)
+    Device(String),
+    /// Swapfile, path inside the installed system
+    File(String),
+}
+
+// ---------------------------------------------------------
+// Kernel parameters for resuming
+// ---------------------------------------------------------
+#[derive(Debug, Clone)]
+pub struct ResumeParams {
+    /// Filesystem UUID of the swap partition, or of the filesystem holding the swapfile
+    pub uuid: String,
+    /// Physical offset of the swapfile in pages (swapfiles only)
+    pub offset: Option<u64>,
+}
+
+impl ResumeParams {
+    pub fn cmdline(&self) -> Vec<String> {
+        let mut params = vec![format!("resume=UUID={}", self.uuid)];
+        if let Some(offset) = self.offset {
+            params.push(format!("resume_offset={}", offset));
+        }
+        params
+    }
+}
+
+// ---------------------------------------------------------
+// Verification report
+// ---------------------------------------------------------
+#[derive(Debug, Default)]
+pub struct HibernateReport {
+    pub swap: String,
+    pub swap_bytes: u64,
+    pub ram_bytes: u64,
+    pub params: Vec<String>,
+    /// Bootloader files that now contain the parameters
+    pub updated_entries: Vec<String>,
+    /// Human readable mkinitcpio outcome
+    pub mkinitcpio: String,
+}
@@ -1 +1,4 @@
+pub mod disk;
 pub mod disk_setup;
+pub mod hibernate;
+pub mod rescue;
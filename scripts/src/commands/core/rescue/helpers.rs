@@ -0,0 +1,491 @@
+use anyhow::{Context, Result, bail};
+use dialoguer::{Password, Select};
+use std::process::Command;
+
+use crate::colors;
+use crate::commands::core::disk_setup::esp::{ESP_TYPE_GUID, XBOOTLDR_TYPE_GUID};
+use crate::commands::core::disk_setup::structs::Filesystem;
+use crate::commands::core::disk_setup::structs::LsblkNode;
+use crate::commands::core::disk_setup::structs::MountEntry;
+use crate::commands::core::disk_setup::subvolumes;
+use crate::commands::core::rescue::structs::BlockDevice;
+use crate::helpers::CommandRunner;
+use crate::helpers::{run_out, run_show, run_show_with_input, run_status};
+
+/// msdos partition type byte of an ESP
+const ESP_MBR_TYPE: &str = "0xef";
+
+// ---------------------------------------------------------
+// Every block device, flattened, with the disk it sits on
+// ---------------------------------------------------------
+pub fn scan_devices(runner: &dyn CommandRunner) -> Result<Vec<BlockDevice>> {
+    let out = run_out(
+        runner,
+        Command::new("lsblk").args([
+            "-J",
+            "-p",
+            "-o",
+            "NAME,TYPE,FSTYPE,LABEL,UUID,PARTTYPE,MOUNTPOINTS",
+        ]),
+    )
+    .context("Failed running lsblk")?;
+
+    let root: serde_json::Value =
+        serde_json::from_str(&out).context("Failed parsing lsblk JSON")?;
+
+    let mut devices = Vec::new();
+
+    if let Some(devs) = root.get("blockdevices").and_then(|v| v.as_array()) {
+        for d in devs {
+            let node: LsblkNode =
+                serde_json::from_value(d.clone()).context("Failed parsing lsblk node")?;
+            flatten(&node, &node.name, &mut devices);
+        }
+    }
+
+    Ok(devices)
+}
+
+// LUKS mappers and LVs show up once below every device they span
+fn flatten(node: &LsblkNode, disk: &str, out: &mut Vec<BlockDevice>) {
+    if !out.iter().any(|d| d.path == node.name) {
+        out.push(BlockDevice {
+            path: node.name.clone(),
+            dev_type: node.dev_type.clone(),
+            fstype: node.fstype.clone(),
+            label: node.label.clone(),
+            uuid: node.uuid.clone(),
+            parttype: node.parttype.clone(),
+            disk: disk.to_string(),
+            mountpoints: node.mountpoints.iter().flatten().cloned().collect(),
+            has_children: !node.children.is_empty(),
+        });
+    }
+
+    for child in &node.children {
+        flatten(child, disk, out);
+    }
+}
+
+// ---------------------------------------------------------
+// Root filesystem by label (a multi-disk Btrfs counts once)
+// ---------------------------------------------------------
+// An LVM root is labelled with its LV name, so `ROOT` also finds an LV named `root`
+pub fn find_root(devices: &[BlockDevice], label: &str) -> Result<Option<BlockDevice>> {
+    let filesystems = || {
+        devices
+            .iter()
+            .filter(|d| !matches!(d.fstype.as_deref(), None | Some("crypto_LUKS" | "swap")))
+    };
+
+    let mut candidates: Vec<&BlockDevice> = filesystems()
+        .filter(|d| d.label.as_deref() == Some(label))
+        .collect();
+    if candidates.is_empty() {
+        candidates = filesystems()
+            .filter(|d| d.dev_type == "lvm")
+            .filter(|d| {
+                d.label
+                    .as_deref()
+                    .is_some_and(|l| l.eq_ignore_ascii_case(label))
+            })
+            .collect();
+    }
+
+    // Every member of a Btrfs RAID carries the label and the filesystem UUID
+    let mut seen = Vec::new();
+    candidates.retain(|d| match &d.uuid {
+        Some(uuid) if seen.contains(uuid) => false,
+        Some(uuid) => {
+            seen.push(uuid.clone());
+            true
+        }
+        None => true,
+    });
+
+    match candidates[..] {
+        [] => Ok(None),
+        [root] => Ok(Some(root.clone())),
+        _ => {
+            let paths: Vec<&str> = candidates.iter().map(|d| d.path.as_str()).collect();
+            bail!(
+                "Several filesystems are labelled {}: {}; pass --label to pick one",
+                label,
+                paths.join(", ")
+            );
+        }
+    }
+}
+
+// ---------------------------------------------------------
+// Open the LUKS container the root is in
+// ---------------------------------------------------------
+pub fn choose_locked_luks(devices: &[BlockDevice]) -> Result<Option<BlockDevice>> {
+    let locked: Vec<&BlockDevice> = devices
+        .iter()
+        .filter(|d| d.fstype.as_deref() == Some("crypto_LUKS") && !d.has_children)
+        .collect();
+
+    match locked[..] {
+        [] => Ok(None),
+        [device] => Ok(Some(device.clone())),
+        _ => {
+            let items: Vec<&str> = locked.iter().map(|d| d.path.as_str()).collect();
+            let selection = Select::new()
+                .with_prompt(colors::info("Which LUKS container holds the system?"))
+                .items(&items)
+                .default(0)
+                .interact()
+                .context("LUKS selection aborted")?;
+            Ok(Some(locked[selection].clone()))
+        }
+    }
+}
+
+pub fn open_luks(runner: &dyn CommandRunner, device: &str, mapper_name: &str) -> Result<()> {
+    if std::path::Path::new(&format!("/dev/mapper/{}", mapper_name)).exists() {
+        bail!(
+            "/dev/mapper/{} already exists, pass --mapper-name to use another name",
+            mapper_name
+        );
+    }
+
+    let passphrase = Password::new()
+        .with_prompt(colors::info(&format!("Passphrase for {}", device)))
+        .interact()
+        .context("Failed to read passphrase")?;
+
+    run_show_with_input(
+        runner,
+        Command::new("cryptsetup").args(["open", "--key-file=-", device, mapper_name]),
+        passphrase.as_bytes(),
+    )
+    .context("Failed to open LUKS container")?;
+
+    Ok(())
+}
+
+// LVs inside the container only appear once their VG is active
+pub fn activate_lvm(runner: &dyn CommandRunner, devices: &[BlockDevice]) -> Result<bool> {
+    let inactive = devices
+        .iter()
+        .any(|d| d.fstype.as_deref() == Some("LVM2_member") && !d.has_children);
+
+    if inactive {
+        run_show(runner, Command::new("vgchange").arg("-ay"))
+            .context("Failed to activate LVM volume groups")?;
+    }
+
+    Ok(inactive)
+}
+
+// ---------------------------------------------------------
+// Btrfs subvolumes and the installed fstab (mounts the top level for a moment)
+// ---------------------------------------------------------
+pub fn read_top_level(
+    runner: &dyn CommandRunner,
+    device: &str,
+    target: &str,
+) -> Result<(Vec<String>, Option<String>)> {
+    std::fs::create_dir_all(target).with_context(|| format!("Failed to create {}", target))?;
+
+    run_show(
+        runner,
+        Command::new("mount").args(["-o", "subvolid=5,ro", device, target]),
+    )
+    .context("Failed to mount Btrfs top-level volume")?;
+
+    let listed = run_out(
+        runner,
+        Command::new("btrfs").args(["subvolume", "list", target]),
+    )
+    .context("Failed to list Btrfs subvolumes");
+    // `@` is a directory of the top level, its fstab names the other subvolumes
+    let fstab =
+        std::fs::read_to_string(format!("{}/@/etc/fstab", target.trim_end_matches('/'))).ok();
+
+    // Always unmount the top-level volume, even if listing failed
+    run_show(runner, Command::new("umount").arg(target))
+        .context("Failed to unmount Btrfs top-level volume")?;
+
+    Ok((parse_subvolume_list(&listed?), fstab))
+}
+
+// "ID 257 gen 12 top level 5 path @home"
+pub fn parse_subvolume_list(out: &str) -> Vec<String> {
+    out.lines()
+        .filter_map(|line| line.split_once(" path "))
+        .map(|(_, path)| path.trim().to_string())
+        .collect()
+}
+
+// ---------------------------------------------------------
+// ESP and XBOOTLDR, preferring the root's own disk
+// ---------------------------------------------------------
+pub fn find_boot_partitions(
+    devices: &[BlockDevice],
+    root_disk: &str,
+) -> (Option<BlockDevice>, Option<BlockDevice>) {
+    let find = |is_type: &dyn Fn(&str) -> bool| {
+        let matching: Vec<&BlockDevice> = devices
+            .iter()
+            .filter(|d| d.dev_type == "part" && d.parttype.as_deref().is_some_and(is_type))
+            .collect();
+
+        matching
+            .iter()
+            .find(|d| d.disk == root_disk)
+            .or(matching.first())
+            .map(|d| (*d).clone())
+    };
+
+    let esp =
+        find(&|t| t.eq_ignore_ascii_case(ESP_TYPE_GUID) || t.eq_ignore_ascii_case(ESP_MBR_TYPE));
+    let xbootldr = find(&|t| t.eq_ignore_ascii_case(XBOOTLDR_TYPE_GUID));
+
+    (esp, xbootldr)
+}
+
+// ---------------------------------------------------------
+// Mounts from the installed fstab (or sharch's default subvolumes);
+// subvolumes neither knows are left out
+// ---------------------------------------------------------
+pub fn plan_mounts(
+    root: &BlockDevice,
+    subvolume_names: &[String],
+    fstab: Option<&str>,
+    esp: Option<&BlockDevice>,
+    xbootldr: Option<&BlockDevice>,
+) -> Result<(Vec<MountEntry>, Vec<String>)> {
+    let mut mounts = Vec::new();
+    let mut skipped = Vec::new();
+    let fstype = root.fstype.clone().unwrap_or_default();
+
+    if root.is_btrfs() && !subvolume_names.is_empty() {
+        if !subvolume_names.iter().any(|n| n == "@") {
+            bail!(
+                "{} has no `@` subvolume, this is not a sharch layout; mount it by hand",
+                root.path
+            );
+        }
+
+        // A layout's own [[subvolume]] set is only known to the fstab
+        let listed = fstab
+            .map(|f| fstab_subvolumes(f, subvolume_names))
+            .filter(|list| list.iter().any(|(_, mount_point, _)| mount_point == "/"));
+        let planned = listed.unwrap_or_else(|| {
+            subvolumes::default_subvolumes()
+                .into_iter()
+                .filter(|sv| subvolume_names.contains(&sv.name))
+                .map(|sv| {
+                    let options = sv
+                        .mount_options
+                        .as_deref()
+                        .unwrap_or(Filesystem::Btrfs.default_mount_options());
+                    let options = format!("{},subvol={}", options, sv.name);
+                    (sv.name, sv.mount_point, options)
+                })
+                .collect()
+        });
+
+        skipped.extend(
+            subvolume_names
+                .iter()
+                .filter(|n| !planned.iter().any(|(name, _, _)| name == *n))
+                .cloned(),
+        );
+
+        for (_, mount_point, options) in planned {
+            mounts.push(MountEntry {
+                source: root.path.clone(),
+                mount_point,
+                fstype: fstype.clone(),
+                options,
+            });
+        }
+    } else {
+        mounts.push(MountEntry {
+            source: root.path.clone(),
+            mount_point: "/".to_string(),
+            fstype,
+            options: "defaults".to_string(),
+        });
+    }
+
+    // With XBOOTLDR the kernels live at /boot and the ESP moves to /efi
+    let boot = [
+        (xbootldr, "/boot"),
+        (esp, if xbootldr.is_some() { "/efi" } else { "/boot" }),
+    ];
+    for (device, mount_point) in boot {
+        if let Some(device) = device {
+            mounts.push(MountEntry {
+                source: device.path.clone(),
+                mount_point: mount_point.to_string(),
+                fstype: Filesystem::Vfat.fstype().to_string(),
+                options: Filesystem::Vfat.default_mount_options().to_string(),
+            });
+        }
+    }
+
+    Ok((mounts, skipped))
+}
+
+// Btrfs lines of an fstab as (subvolume, mount point, options), only existing subvolumes
+fn fstab_subvolumes(fstab: &str, subvolume_names: &[String]) -> Vec<(String, String, String)> {
+    fstab
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let [_, mount_point, "btrfs", options, ..] =
+                line.split_whitespace().collect::<Vec<_>>()[..]
+            else {
+                return None;
+            };
+            let name = options
+                .split(',')
+                .find_map(|o| o.strip_prefix("subvol="))?
+                .trim_start_matches('/');
+
+            subvolume_names.iter().any(|n| n == name).then(|| {
+                (
+                    name.to_string(),
+                    mount_point.to_string(),
+                    options.to_string(),
+                )
+            })
+        })
+        .collect()
+}
+
+// ---------------------------------------------------------
+// Is something mounted at the target already?
+// ---------------------------------------------------------
+pub fn is_mountpoint(runner: &dyn CommandRunner, target: &str) -> Result<bool> {
+    run_status(
+        runner,
+        Command::new("findmnt").args(["-n", "--mountpoint", target]),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(path: &str, fstype: &str, label: &str, uuid: &str) -> BlockDevice {
+        BlockDevice {
+            path: path.to_string(),
+            dev_type: "part".to_string(),
+            fstype: Some(fstype.to_string()),
+            label: Some(label.to_string()),
+            uuid: Some(uuid.to_string()),
+            disk: "/dev/nvme0n1".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn btrfs_raid_members_are_one_root() {
+        let devices = [
+            device("/dev/nvme0n1p2", "btrfs", "ROOT", "7d1e"),
+            device("/dev/nvme1n1p2", "btrfs", "ROOT", "7d1e"),
+            device("/dev/nvme0n1p3", "swap", "ROOT", "a001"),
+        ];
+
+        let root = find_root(&devices, "ROOT").unwrap().unwrap();
+        assert_eq!(root.path, "/dev/nvme0n1p2");
+        assert!(find_root(&devices, "HOME").unwrap().is_none());
+    }
+
+    #[test]
+    fn lvm_root_is_found_by_its_lv_name() {
+        let mut lv = device("/dev/mapper/vg0-root", "ext4", "root", "5e2f");
+        lv.dev_type = "lvm".to_string();
+        let mut home = device("/dev/mapper/vg0-home", "ext4", "home", "6a30");
+        home.dev_type = "lvm".to_string();
+        let devices = [lv, home, device("/dev/sda3", "ext4", "Root", "0b11")];
+
+        assert_eq!(
+            find_root(&devices, "ROOT").unwrap().unwrap().path,
+            "/dev/mapper/vg0-root"
+        );
+        // An exact label wins over the LV name
+        assert_eq!(
+            find_root(&devices, "Root").unwrap().unwrap().path,
+            "/dev/sda3"
+        );
+    }
+
+    #[test]
+    fn mounts_the_subvolumes_the_installed_fstab_lists() {
+        let root = device("/dev/mapper/cryptroot", "btrfs", "ROOT", "7d1e");
+        let names = parse_subvolume_list(
+            "ID 256 gen 20 top level 5 path @\n\
+             ID 257 gen 20 top level 5 path @home\n\
+             ID 258 gen 18 top level 5 path @docker\n\
+             ID 259 gen 18 top level 5 path @snapshots\n",
+        );
+        let fstab = "# Generated by sharch disk-setup\n\
+            UUID=7d1e\t/\tbtrfs\tnoatime,compress=zstd:1,subvol=@\t0 0\n\
+            UUID=7d1e\t/home\tbtrfs\tnoatime,subvol=/@home\t0 0\n\
+            UUID=7d1e\t/var/lib/docker\tbtrfs\tnoatime,nodatacow,subvol=@docker\t0 0\n\
+            UUID=7d1e\t/var/log\tbtrfs\tnoatime,subvol=@log\t0 0\n\
+            UUID=A1B2-C3D4\t/boot\tvfat\tumask=0077\t0 2\n";
+
+        let (mounts, skipped) = plan_mounts(&root, &names, Some(fstab), None, None).unwrap();
+        let points: Vec<(&str, &str)> = mounts
+            .iter()
+            .map(|m| (m.mount_point.as_str(), m.options.as_str()))
+            .collect();
+
+        assert_eq!(
+            points,
+            [
+                ("/", "noatime,compress=zstd:1,subvol=@"),
+                ("/home", "noatime,subvol=/@home"),
+                ("/var/lib/docker", "noatime,nodatacow,subvol=@docker"),
+            ]
+        );
+        assert_eq!(skipped, ["@snapshots"]);
+
+        // Without `/` in the fstab the defaults apply
+        let (mounts, _) = plan_mounts(&root, &names, Some("# empty\n"), None, None).unwrap();
+        assert_eq!(mounts[0].options.rsplit(',').next(), Some("subvol=@"));
+        assert_eq!(mounts.len(), 2);
+    }
+
+    #[test]
+    fn mounts_known_subvolumes_and_the_esp() {
+        let root = device("/dev/mapper/cryptroot", "btrfs", "ROOT", "7d1e");
+        let esp = device("/dev/nvme0n1p1", "vfat", "EFI", "A1B2-C3D4");
+        let names = parse_subvolume_list(
+            "ID 256 gen 20 top level 5 path @\n\
+             ID 257 gen 20 top level 5 path @home\n\
+             ID 258 gen 18 top level 5 path @pkg\n\
+             ID 260 gen 9 top level 5 path @snapshots\n",
+        );
+
+        let (mounts, skipped) = plan_mounts(&root, &names, None, Some(&esp), None).unwrap();
+        let points: Vec<(&str, &str)> = mounts
+            .iter()
+            .map(|m| {
+                (
+                    m.mount_point.as_str(),
+                    m.options.rsplit(',').next().unwrap(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            points,
+            [
+                ("/", "subvol=@"),
+                ("/home", "subvol=@home"),
+                ("/var/cache/pacman/pkg", "subvol=@pkg"),
+                ("/boot", "umask=0077"),
+            ]
+        );
+        assert_eq!(skipped, ["@snapshots"]);
+    }
+}
@@ -0,0 +1,244 @@
+pub mod helpers;
+pub mod structs;
+pub mod teardown;
+
+use anyhow::{Context, bail};
+use std::process::Command;
+
+use crate::colors;
+use crate::commands::core::disk_setup::subvolumes;
+use crate::helpers::ensure_tool_exists;
+use crate::helpers::runner::{CommandRunner, DryRunRunner, RealRunner, command_line};
+
+#[derive(clap::Args, Debug)]
+/// Mount an existing sharch install (e.g. from the live ISO to repair it)
+pub struct MountArgs {
+    /// Where to mount the installed system
+    #[clap(long, default_value = "/mnt")]
+    pub target: String,
+
+    /// Filesystem label of the root filesystem (an LVM root also matches its LV name, e.g. `root`)
+    #[clap(long, default_value = "ROOT")]
+    pub label: String,
+
+    /// Mapper name for the opened LUKS container
+    #[clap(long, default_value = "cryptroot")]
+    pub mapper_name: String,
+}
+
+#[derive(clap::Args, Debug)]
+/// Enter a system mounted by `sharch mount`
+pub struct ChrootArgs {
+    /// Where the installed system is mounted
+    #[clap(long, default_value = "/mnt")]
+    pub target: String,
+
+    /// Command to run inside instead of a shell
+    #[clap(trailing_var_arg = true)]
+    pub command: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+/// Unmount, swapoff and close everything set up under a target root
+pub struct TeardownArgs {
+    /// Root to tear down
+    #[clap(long, default_value = "/mnt")]
+    pub target: String,
+
+    /// If set, do not perform any changes, just simulate
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+pub fn handle_mount(args: MountArgs) -> anyhow::Result<()> {
+    say!("{}", colors::header("Mount Installed System"));
+
+    let runner = &RealRunner;
+
+    if helpers::is_mountpoint(runner, &args.target)? {
+        bail!(
+            "Something is already mounted at {}; release it with `sharch teardown --target {}`",
+            args.target,
+            args.target
+        );
+    }
+
+    let mut devices = helpers::scan_devices(runner)?;
+
+    // The label sits inside the container, so an unopened one has to be tried first
+    if helpers::find_root(&devices, &args.label)?.is_none() {
+        if let Some(luks) = helpers::choose_locked_luks(&devices)? {
+            say!(
+                "{}",
+                colors::info(&format!(
+                    "Opening LUKS container {}",
+                    colors::highlight(&luks.path)
+                ))
+            );
+            helpers::open_luks(runner, &luks.path, &args.mapper_name)?;
+            devices = helpers::scan_devices(runner)?;
+        }
+
+        if helpers::activate_lvm(runner, &devices)? {
+            devices = helpers::scan_devices(runner)?;
+        }
+    }
+
+    let Some(root) = helpers::find_root(&devices, &args.label)? else {
+        bail!(
+            "No filesystem labelled {} found; pass --label if the root is named differently",
+            args.label
+        );
+    };
+
+    if let Some(mp) = root.mountpoints.first() {
+        bail!("{} is already mounted at {}", root.path, mp);
+    }
+
+    say!(
+        "{}",
+        colors::info(&format!(
+            "Root: {} ({})",
+            colors::highlight(&root.path),
+            root.fstype.as_deref().unwrap_or("unknown")
+        ))
+    );
+
+    let (subvolume_names, fstab) = if root.is_btrfs() {
+        helpers::read_top_level(runner, &root.path, &args.target)?
+    } else {
+        (Vec::new(), None)
+    };
+
+    let (esp, xbootldr) = helpers::find_boot_partitions(&devices, &root.disk);
+    if esp.is_none() {
+        say!(
+            "{}",
+            colors::warn("No EFI System Partition found, /boot is left unmounted")
+        );
+    }
+
+    let (mut mounts, skipped) = helpers::plan_mounts(
+        &root,
+        &subvolume_names,
+        fstab.as_deref(),
+        esp.as_ref(),
+        xbootldr.as_ref(),
+    )?;
+
+    say!();
+    subvolumes::mount_entries(runner, &mut mounts, &args.target)?;
+    say!();
+
+    for m in &mounts {
+        say!(
+            "  {:<24} {} ({})",
+            subvolumes::target_path(&args.target, &m.mount_point),
+            m.source,
+            m.options
+        );
+    }
+
+    if !skipped.is_empty() {
+        say!(
+            "{}",
+            colors::warn(&format!(
+                "Not mounted (unknown subvolumes): {}",
+                skipped.join(", ")
+            ))
+        );
+    }
+
+    say!();
+    say!(
+        "{}",
+        colors::success(&format!("✓ Installed system mounted at {}", args.target))
+    );
+    say!(
+        "{}",
+        colors::info(&format!(
+            "Enter it with `sharch chroot --target {}`, release it with `sharch teardown --target {}`",
+            args.target, args.target
+        ))
+    );
+
+    Ok(())
+}
+
+pub fn handle_chroot(args: ChrootArgs) -> anyhow::Result<()> {
+    ensure_tool_exists("arch-chroot")?;
+
+    if !helpers::is_mountpoint(&RealRunner, &args.target)? {
+        bail!(
+            "Nothing is mounted at {}; run `sharch mount --target {}` first",
+            args.target,
+            args.target
+        );
+    }
+
+    // Interactive, so stdin/stdout stay attached instead of going through the runner
+    let mut cmd = Command::new("arch-chroot");
+    cmd.arg(&args.target).args(&args.command);
+    say!("> {}", command_line(&cmd));
+
+    let status = cmd.status().context("Failed to run arch-chroot")?;
+
+    // A shell's exit code is whatever the last command returned
+    if !args.command.is_empty() && !status.success() {
+        bail!("`{}` exited with {}", args.command.join(" "), status);
+    }
+
+    Ok(())
+}
+
+pub fn handle_teardown(args: TeardownArgs) -> anyhow::Result<()> {
+    say!("{}", colors::header("Teardown"));
+
+    let runner: Box<dyn CommandRunner> = if args.dry_run {
+        Box::new(DryRunRunner)
+    } else {
+        Box::new(RealRunner)
+    };
+    let runner = runner.as_ref();
+
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")
+        .context("Failed to read /proc/self/mountinfo")?;
+    let swaps = std::fs::read_to_string("/proc/swaps").context("Failed to read /proc/swaps")?;
+
+    let plan = teardown::build_plan(runner, &args.target, &mountinfo, &swaps)?;
+    if plan.is_empty() {
+        say!(
+            "{}",
+            colors::info(&format!("Nothing is set up under {}", args.target))
+        );
+        return Ok(());
+    }
+
+    let busy = teardown::execute(runner, &plan);
+    say!();
+
+    if busy.is_empty() {
+        say!(
+            "{}",
+            colors::success(&format!("✓ {} torn down", args.target))
+        );
+        return Ok(());
+    }
+
+    say!("{}", colors::error("Still busy:"));
+    for b in &busy {
+        say!("  {}", colors::highlight(&b.what));
+        // The last line holds the tool's own message, e.g. "target is busy"
+        let reason = b.error.lines().last().unwrap_or_default();
+        say!("    {}", reason.trim_start_matches("stderr: ").trim());
+        for holder in teardown::holders(&b.path) {
+            say!("    held by {} ({})", holder.pid, holder.command);
+        }
+    }
+    say!();
+
+    bail!(
+        "{} item(s) could not be released; stop the processes above and run teardown again",
+        busy.len()
+    );
+}
@@ -0,0 +1,101 @@
+// ---------------------------------------------------------
+// Block device seen while looking for an installation
+// ---------------------------------------------------------
+#[derive(Debug, Clone, Default)]
+pub struct BlockDevice {
+    /// Full path, e.g. `/dev/nvme0n1p2` or `/dev/mapper/cryptroot`
+    pub path: String,
+    pub dev_type: String,
+    pub fstype: Option<String>,
+    pub label: Option<String>,
+    pub uuid: Option<String>,
+    /// Partition type GUID (GPT) or `0x..` byte (msdos)
+    pub parttype: Option<String>,
+    /// Whole disk underneath, also for LUKS mappers and LVs
+    pub disk: String,
+    pub mountpoints: Vec<String>,
+    /// Something sits on top of it (an opened LUKS container, active LVs)
+    pub has_children: bool,
+}
+
+impl BlockDevice {
+    pub fn is_btrfs(&self) -> bool {
+        self.fstype.as_deref() == Some("btrfs")
+    }
+}
+
+// ---------------------------------------------------------
+// Mount found in /proc/self/mountinfo
+// ---------------------------------------------------------
+#[derive(Debug, Clone, PartialEq)]
+pub struct MountInfo {
+    /// Absolute path on the running system, e.g. `/mnt/var/log`
+    pub mount_point: String,
+    pub fstype: String,
+    /// Device or pseudo source (`proc`, `tmpfs`, ...)
+    pub source: String,
+}
+
+// ---------------------------------------------------------
+// Device layers released after unmounting, innermost first
+// ---------------------------------------------------------
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReleaseStep {
+    /// Volume group name
+    DeactivateVg(String),
+    /// Mapper path, e.g. `/dev/mapper/cryptroot`
+    CloseLuks(String),
+    /// e.g. `/dev/loop0`
+    DetachLoop(String),
+}
+
+impl ReleaseStep {
+    pub fn describe(&self) -> String {
+        match self {
+            ReleaseStep::DeactivateVg(vg) => format!("volume group {}", vg),
+            ReleaseStep::CloseLuks(path) => format!("LUKS mapping {}", path),
+            ReleaseStep::DetachLoop(path) => format!("loop device {}", path),
+        }
+    }
+
+    /// Path to look for holding processes
+    pub fn device(&self) -> String {
+        match self {
+            ReleaseStep::DeactivateVg(vg) => format!("/dev/{}", vg),
+            ReleaseStep::CloseLuks(path) | ReleaseStep::DetachLoop(path) => path.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TeardownPlan {
+    /// Swap files and devices to `swapoff`
+    pub swaps: Vec<String>,
+    /// Deepest mount first
+    pub mounts: Vec<MountInfo>,
+    pub releases: Vec<ReleaseStep>,
+}
+
+impl TeardownPlan {
+    pub fn is_empty(&self) -> bool {
+        self.swaps.is_empty() && self.mounts.is_empty() && self.releases.is_empty()
+    }
+}
+
+// ---------------------------------------------------------
+// Something teardown could not release
+// ---------------------------------------------------------
+#[derive(Debug)]
+pub struct Busy {
+    /// e.g. `/mnt/home` or `LUKS mapping /dev/mapper/cryptroot`
+    pub what: String,
+    /// Mount point or device the holders are looked up for
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Holder {
+    pub pid: u32,
+    pub command: String,
+}
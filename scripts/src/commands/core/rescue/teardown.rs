@@ -0,0 +1,371 @@
+use anyhow::{Context, Result, bail};
+use std::process::Command;
+
+use crate::commands::core::disk_setup::structs::LsblkNode;
+use crate::commands::core::rescue::structs::Busy;
+use crate::commands::core::rescue::structs::Holder;
+use crate::commands::core::rescue::structs::MountInfo;
+use crate::commands::core::rescue::structs::ReleaseStep;
+use crate::commands::core::rescue::structs::TeardownPlan;
+use crate::helpers::CommandRunner;
+use crate::helpers::{run_out, run_show};
+
+// ---------------------------------------------------------
+// Everything under the target, from mountinfo and /proc/swaps
+// ---------------------------------------------------------
+pub fn build_plan(
+    runner: &dyn CommandRunner,
+    target: &str,
+    mountinfo: &str,
+    swaps: &str,
+) -> Result<TeardownPlan> {
+    let target = target.trim_end_matches('/');
+    if target.is_empty() {
+        bail!("Refusing to tear down the running system's /");
+    }
+
+    let mut mounts = parse_mountinfo(mountinfo, target);
+    mounts.sort_by_key(|m| std::cmp::Reverse(m.mount_point.matches('/').count()));
+
+    // Device stacks below the mounts: LV -> LUKS -> partition -> disk
+    let mut stacks = Vec::new();
+    for source in mounts.iter().map(|m| &m.source) {
+        if source.starts_with("/dev/") {
+            stacks.push(device_stack(runner, source)?);
+        }
+    }
+
+    // Swap files inside the target, and swap devices on the same disks
+    let disks: Vec<String> = stacks.iter().filter_map(|s| top_device(s)).collect();
+    let mut swap_list = Vec::new();
+    for (path, kind) in parse_swaps(swaps) {
+        if kind == "file" {
+            if is_under(&path, target) {
+                swap_list.push(path);
+            }
+            continue;
+        }
+
+        let stack = device_stack(runner, &path)?;
+        if top_device(&stack).is_some_and(|d| disks.contains(&d)) {
+            swap_list.push(path);
+            stacks.push(stack);
+        }
+    }
+
+    Ok(TeardownPlan {
+        swaps: swap_list,
+        mounts,
+        releases: release_steps(runner, &stacks)?,
+    })
+}
+
+// "36 35 98:0 /mnt1 /mnt/parent rw,noatime master:1 - ext3 /dev/root rw"
+pub fn parse_mountinfo(text: &str, target: &str) -> Vec<MountInfo> {
+    text.lines()
+        .filter_map(|line| {
+            let (left, right) = line.split_once(" - ")?;
+            let mount_point = unescape(left.split(' ').nth(4)?);
+            let mut right = right.split(' ');
+            let fstype = right.next()?.to_string();
+            let source = unescape(right.next()?);
+
+            is_under(&mount_point, target).then_some(MountInfo {
+                mount_point,
+                fstype,
+                source,
+            })
+        })
+        .collect()
+}
+
+// "/swap/swapfile file 8388604 0 -2" after a header line
+fn parse_swaps(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some((unescape(fields.next()?), fields.next()?.to_string()))
+        })
+        .collect()
+}
+
+fn is_under(path: &str, target: &str) -> bool {
+    path == target
+        || path
+            .strip_prefix(target)
+            .is_some_and(|p| p.starts_with('/'))
+}
+
+// The kernel writes space, tab, newline and backslash as \040 style octal
+fn unescape(field: &str) -> String {
+    let mut out = String::new();
+    let mut rest = field;
+
+    while let Some(pos) = rest.find('\\') {
+        out.push_str(&rest[..pos]);
+        let code = rest.get(pos + 1..pos + 4);
+        match code.and_then(|c| u8::from_str_radix(c, 8).ok()) {
+            Some(byte) => {
+                out.push(byte as char);
+                rest = &rest[pos + 4..];
+            }
+            None => {
+                out.push('\\');
+                rest = &rest[pos + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+
+    out
+}
+
+// ---------------------------------------------------------
+// A device and everything it sits on (lsblk --inverse)
+// ---------------------------------------------------------
+fn device_stack(runner: &dyn CommandRunner, device: &str) -> Result<Vec<(String, String)>> {
+    let out = run_out(
+        runner,
+        Command::new("lsblk").args(["-J", "-s", "-p", "-o", "NAME,TYPE", device]),
+    )
+    .with_context(|| format!("Failed to read the device stack of {}", device))?;
+
+    let root: serde_json::Value =
+        serde_json::from_str(&out).context("Failed parsing lsblk JSON")?;
+
+    let mut stack = Vec::new();
+    if let Some(devs) = root.get("blockdevices").and_then(|v| v.as_array()) {
+        for d in devs {
+            let node: LsblkNode =
+                serde_json::from_value(d.clone()).context("Failed parsing lsblk node")?;
+            flatten(&node, &mut stack);
+        }
+    }
+
+    Ok(stack)
+}
+
+// Inverted tree: children are the layers underneath
+fn flatten(node: &LsblkNode, out: &mut Vec<(String, String)>) {
+    out.push((node.name.clone(), node.dev_type.clone()));
+    for child in &node.children {
+        flatten(child, out);
+    }
+}
+
+fn top_device(stack: &[(String, String)]) -> Option<String> {
+    stack
+        .iter()
+        .rev()
+        .find(|(_, kind)| kind == "disk" || kind == "loop")
+        .map(|(name, _)| name.clone())
+}
+
+// Layers closer to the filesystem go first, each one once
+fn release_steps(
+    runner: &dyn CommandRunner,
+    stacks: &[Vec<(String, String)>],
+) -> Result<Vec<ReleaseStep>> {
+    let mut steps: Vec<(usize, ReleaseStep)> = Vec::new();
+
+    for stack in stacks {
+        for (depth, (name, kind)) in stack.iter().enumerate() {
+            let step = match kind.as_str() {
+                "lvm" => ReleaseStep::DeactivateVg(volume_group(runner, name)?),
+                "crypt" => ReleaseStep::CloseLuks(name.clone()),
+                "loop" => ReleaseStep::DetachLoop(name.clone()),
+                _ => continue,
+            };
+
+            match steps.iter_mut().find(|(_, s)| *s == step) {
+                Some(existing) => existing.0 = existing.0.max(depth),
+                None => steps.push((depth, step)),
+            }
+        }
+    }
+
+    steps.sort_by_key(|(depth, _)| *depth);
+
+    Ok(steps.into_iter().map(|(_, step)| step).collect())
+}
+
+fn volume_group(runner: &dyn CommandRunner, lv: &str) -> Result<String> {
+    let out = run_out(
+        runner,
+        Command::new("lvs").args(["--noheadings", "-o", "vg_name", lv]),
+    )
+    .with_context(|| format!("Failed to find the volume group of {}", lv))?;
+
+    Ok(out.trim().to_string())
+}
+
+// ---------------------------------------------------------
+// Run the plan; keep going past failures, return what stayed
+// ---------------------------------------------------------
+pub fn execute(runner: &dyn CommandRunner, plan: &TeardownPlan) -> Vec<Busy> {
+    let mut busy = Vec::new();
+    let mut attempt = |what: String, path: String, cmd: &mut Command| {
+        if let Err(e) = run_show(runner, cmd) {
+            busy.push(Busy {
+                what,
+                path,
+                error: format!("{:#}", e),
+            });
+        }
+    };
+
+    for swap in &plan.swaps {
+        attempt(
+            format!("swap {}", swap),
+            swap.clone(),
+            Command::new("swapoff").arg(swap),
+        );
+    }
+
+    for m in &plan.mounts {
+        attempt(
+            m.mount_point.clone(),
+            m.mount_point.clone(),
+            Command::new("umount").arg(&m.mount_point),
+        );
+    }
+
+    for step in &plan.releases {
+        let mut cmd = match step {
+            ReleaseStep::DeactivateVg(vg) => {
+                let mut cmd = Command::new("vgchange");
+                cmd.args(["-an", vg]);
+                cmd
+            }
+            ReleaseStep::CloseLuks(path) => {
+                let mut cmd = Command::new("cryptsetup");
+                cmd.args(["close", path]);
+                cmd
+            }
+            ReleaseStep::DetachLoop(path) => {
+                let mut cmd = Command::new("losetup");
+                cmd.args(["-d", path]);
+                cmd
+            }
+        };
+        attempt(step.describe(), step.device(), &mut cmd);
+    }
+
+    busy
+}
+
+// ---------------------------------------------------------
+// Processes with a cwd, root, binary or open file under a path
+// ---------------------------------------------------------
+pub fn holders(path: &str) -> Vec<Holder> {
+    // /dev/mapper/x and /dev/vg are symlinks to /dev/dm-N
+    let path = std::fs::canonicalize(path)
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|_| path.to_string());
+
+    let Ok(procs) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+
+    let mut found = Vec::new();
+    for entry in procs.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|s| s.parse().ok()) else {
+            continue;
+        };
+        let dir = entry.path();
+
+        let mut links: Vec<std::path::PathBuf> =
+            ["cwd", "root", "exe"].iter().map(|l| dir.join(l)).collect();
+        if let Ok(fds) = std::fs::read_dir(dir.join("fd")) {
+            links.extend(fds.flatten().map(|fd| fd.path()));
+        }
+
+        let holds = links.iter().any(|link| {
+            std::fs::read_link(link).is_ok_and(|t| is_under(&t.to_string_lossy(), &path))
+        });
+
+        if holds {
+            let command = std::fs::read_to_string(dir.join("comm")).unwrap_or_default();
+            found.push(Holder {
+                pid,
+                command: command.trim().to_string(),
+            });
+        }
+    }
+
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::runner::FakeRunner;
+
+    const MOUNTINFO: &str = "\
+22 1 0:21 / / rw,relatime - ext4 /dev/sda1 rw
+40 22 254:1 /@ /mnt rw,noatime - btrfs /dev/mapper/vg-root rw,subvol=/@
+41 40 254:1 /@home /mnt/home rw,noatime - btrfs /dev/mapper/vg-root rw,subvol=/@home
+42 40 259:1 / /mnt/boot rw,relatime - vfat /dev/nvme0n1p1 rw
+43 41 0:50 / /mnt/home/my\\040files rw - tmpfs tmpfs rw
+44 22 0:51 / /mnt2 rw - tmpfs tmpfs rw
+";
+
+    #[test]
+    fn mountinfo_keeps_mounts_under_the_target() {
+        let points: Vec<String> = parse_mountinfo(MOUNTINFO, "/mnt")
+            .into_iter()
+            .map(|m| m.mount_point)
+            .collect();
+
+        assert_eq!(
+            points,
+            ["/mnt", "/mnt/home", "/mnt/boot", "/mnt/home/my files"]
+        );
+    }
+
+    #[test]
+    fn plan_releases_lvm_before_luks() {
+        let lv = |name: &str| {
+            format!(
+                r#"{{"blockdevices":[{{"name":"/dev/mapper/{}","type":"lvm","children":[
+                    {{"name":"/dev/mapper/cryptroot","type":"crypt","children":[
+                        {{"name":"/dev/nvme0n1p2","type":"part","children":[
+                            {{"name":"/dev/nvme0n1","type":"disk"}}]}}]}}]}}]}}"#,
+                name
+            )
+        };
+        let runner = FakeRunner::new()
+            .respond(
+                "lsblk -J -s -p -o NAME,TYPE /dev/mapper/vg-root",
+                &lv("vg-root"),
+            )
+            .respond("lsblk -J -s -p -o NAME,TYPE /dev/dm-2", &lv("vg-swap"))
+            .respond(
+                "lsblk -J -s -p -o NAME,TYPE /dev/nvme0n1p1",
+                r#"{"blockdevices":[{"name":"/dev/nvme0n1p1","type":"part","children":[
+                    {"name":"/dev/nvme0n1","type":"disk"}]}]}"#,
+            )
+            .respond(
+                "lsblk -J -s -p -o NAME,TYPE /dev/zram0",
+                r#"{"blockdevices":[{"name":"/dev/zram0","type":"disk"}]}"#,
+            )
+            .respond("lvs", "  vg\n");
+        let swaps = "Filename\tType\tSize\tUsed\tPriority\n\
+                     /dev/dm-2\tpartition\t8388604\t0\t-2\n\
+                     /dev/zram0\tpartition\t4194300\t0\t100\n";
+
+        let plan = build_plan(&runner, "/mnt/", MOUNTINFO, swaps).unwrap();
+
+        assert_eq!(plan.swaps, ["/dev/dm-2"]);
+        assert_eq!(plan.mounts[0].mount_point, "/mnt/home/my files");
+        assert_eq!(plan.mounts.last().unwrap().mount_point, "/mnt");
+        assert_eq!(
+            plan.releases,
+            [
+                ReleaseStep::DeactivateVg("vg".to_string()),
+                ReleaseStep::CloseLuks("/dev/mapper/cryptroot".to_string()),
+            ]
+        );
+    }
+}
@@ -6,4 +6,14 @@ pub mod core;
 pub enum Commands {
     /// Says hello
     DiskSetup(core::disk_setup::DiskSetupArgs),
+    /// Restore partition table backups
+    Disk(core::disk::DiskArgs),
+    /// Set up hibernation (resume from swap)
+    Hibernate(core::hibernate::HibernateArgs),
+    /// Mount an existing install under /mnt (opens LUKS, mounts subvolumes and the ESP)
+    Mount(core::rescue::MountArgs),
+    /// Enter the system mounted by `sharch mount`
+    Chroot(core::rescue::ChrootArgs),
+    /// Unmount, swapoff and close everything under /mnt after a run
+    Teardown(core::rescue::TeardownArgs),
 }
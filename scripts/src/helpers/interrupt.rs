@@ -0,0 +1,141 @@
+use anyhow::{Context, Result};
+use std::cell::Cell;
+use std::process::Command;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+use crate::colors;
+
+static ARMED: AtomicBool = AtomicBool::new(false);
+static PRESSES: AtomicUsize = AtomicUsize::new(0);
+static CLEANING_UP: AtomicBool = AtomicBool::new(false);
+/// pid of the external command running right now, 0 if none
+static CHILD: AtomicU32 = AtomicU32::new(0);
+/// Runs on the second Ctrl-C when no command is running (e.g. stuck in a prompt)
+static ON_ABORT: OnceLock<Box<dyn Fn() + Send + Sync>> = OnceLock::new();
+
+thread_local! {
+    /// Set on the one thread that runs the cleanup, every other thread stays stopped
+    static CLEANUP_THREAD: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Returned instead of running commands once Ctrl-C was pressed.
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Interrupted by Ctrl-C")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+// ---------------------------------------------------------
+// Take over Ctrl-C for the rest of the process
+// ---------------------------------------------------------
+// First press: the running command finishes, nothing new starts.
+// Second press: the running command gets SIGTERM, or `on_abort` runs.
+pub fn arm(on_abort: impl Fn() + Send + Sync + 'static) -> Result<()> {
+    if ARMED.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    let _ = ON_ABORT.set(Box::new(on_abort));
+    ctrlc::set_handler(on_signal).context("Failed to install the Ctrl-C handler")
+}
+
+/// Commands get their own process group, so the terminal's SIGINT only reaches us
+pub fn is_armed() -> bool {
+    ARMED.load(Ordering::SeqCst)
+}
+
+pub fn interrupted() -> bool {
+    PRESSES.load(Ordering::SeqCst) > 0
+}
+
+/// Fails once Ctrl-C was pressed, except on the thread running the cleanup
+pub fn check() -> Result<()> {
+    if interrupted() && !CLEANUP_THREAD.with(Cell::get) {
+        return Err(Interrupted.into());
+    }
+
+    Ok(())
+}
+
+/// True if `err` or one of its causes is an interruption
+pub fn is_interrupt(err: &anyhow::Error) -> bool {
+    err.chain().any(|e| e.is::<Interrupted>())
+}
+
+/// Lets this thread's cleanup commands through; false if cleanup already started elsewhere
+pub fn begin_cleanup() -> bool {
+    if CLEANING_UP.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+
+    CLEANUP_THREAD.with(|c| c.set(true));
+    true
+}
+
+pub fn set_child(pid: u32) {
+    CHILD.store(pid, Ordering::SeqCst);
+}
+
+pub fn clear_child() {
+    CHILD.store(0, Ordering::SeqCst);
+}
+
+fn on_signal() {
+    let presses = PRESSES.fetch_add(1, Ordering::SeqCst) + 1;
+    let child = CHILD.load(Ordering::SeqCst);
+
+    if presses > 1 && CLEANING_UP.load(Ordering::SeqCst) {
+        say!("{}", colors::warn("Cleaning up, please wait"));
+        return;
+    }
+
+    match (presses, child) {
+        (1, 0) => say!(
+            "{}",
+            colors::warn("Interrupted, stopping after the current step (Ctrl-C again to stop now)")
+        ),
+        (1, _) => say!(
+            "{}",
+            colors::warn(
+                "Interrupted, letting the running command finish (Ctrl-C again to kill it)"
+            )
+        ),
+        (_, 0) => {
+            if let Some(abort) = ON_ABORT.get() {
+                abort();
+            }
+            std::process::exit(130);
+        }
+        (_, pid) => {
+            say!(
+                "{}",
+                colors::warn(&format!("Stopping the running command (pid {})", pid))
+            );
+            let _ = Command::new("kill")
+                .args(["-TERM", &pid.to_string()])
+                .status();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cleanup_only_lets_its_own_thread_through() {
+        let cleaner = std::thread::spawn(|| (begin_cleanup(), CLEANUP_THREAD.with(Cell::get)));
+
+        assert_eq!(cleaner.join().unwrap(), (true, true));
+        assert!(!CLEANUP_THREAD.with(Cell::get));
+        // A second cleanup never starts, not even on another thread
+        assert!(!begin_cleanup());
+        assert!(!CLEANUP_THREAD.with(Cell::get));
+    }
+}
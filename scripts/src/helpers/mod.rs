@@ -1,12 +1,11 @@
 pub mod ensure_tool_exists;
-pub mod pacman_install;
-pub mod pacman_sync;
-pub mod prompt_user;
+pub mod interrupt;
 pub mod run;
+pub mod runner;
 
 pub use ensure_tool_exists::ensure_tool_exists;
-pub use pacman_install::pacman_install;
-pub use pacman_sync::pacman_sync;
-pub use prompt_user::prompt_user;
 pub use run::run_out; // if you implement run_out
 pub use run::run_show;
+pub use run::run_show_with_input;
+pub use run::run_status;
+pub use runner::CommandRunner;
@@ -1,39 +1,55 @@
-use anyhow::{Context, Result, bail};
+use anyhow::{Result, bail};
 use std::process::Command;
 
+use crate::helpers::runner::{CommandOutput, CommandRunner, command_line};
+
 /// Print a command, run it, return stdout, support dry_run.
-pub fn run_show(cmd: &mut Command, dry_run: bool) -> Result<String> {
-    let display = format!("{:?}", cmd);
-    println!("> {}", display);
+pub fn run_show(runner: &dyn CommandRunner, cmd: &mut Command) -> Result<String> {
+    let display = command_line(cmd);
+    say!("> {}", display);
 
-    if dry_run {
-        return Ok("[dry-run]".into());
-    }
+    let out = runner.run(cmd, None)?;
+    check(&display, out)
+}
 
-    let out = cmd
-        .output()
-        .with_context(|| format!("Failed to run: {}", display))?;
+/// Like `run_show`, but feeds `input` to the command's stdin (never printed).
+pub fn run_show_with_input(
+    runner: &dyn CommandRunner,
+    cmd: &mut Command,
+    input: &[u8],
+) -> Result<String> {
+    let display = command_line(cmd);
+    say!("> {}", display);
+
+    let out = runner.run(cmd, Some(input))?;
+    check(&display, out)
+}
 
-    if !out.status.success() {
-        let stderr = String::from_utf8_lossy(&out.stderr);
-        bail!("Command failed: {}\nstderr: {}", display, stderr);
-    }
+/// Run a read-only command and return its stdout as String (no printing, runs in dry runs too).
+pub fn run_out(runner: &dyn CommandRunner, cmd: &mut Command) -> Result<String> {
+    let display = command_line(cmd);
 
-    Ok(String::from_utf8_lossy(&out.stdout).to_string())
+    let out = runner.query(cmd)?;
+    check(&display, out)
 }
 
-/// Run a command and return its stdout as String (no printing, no dry-run).
-pub fn run_out(cmd: &mut Command) -> Result<String> {
-    let display = format!("{:?}", cmd);
-
-    let out = cmd
-        .output()
-        .with_context(|| format!("Failed to run: {}", display))?;
+/// Run a read-only command and only report whether it succeeded.
+pub fn run_status(runner: &dyn CommandRunner, cmd: &mut Command) -> Result<bool> {
+    Ok(runner.query(cmd)?.success)
+}
 
-    if !out.status.success() {
-        let stderr = String::from_utf8_lossy(&out.stderr);
-        bail!("Command failed: {}\nstderr: {}", display, stderr);
+fn check(display: &str, out: CommandOutput) -> Result<String> {
+    if !out.success {
+        let code = out
+            .code
+            .map_or("signal".to_string(), |c| format!("exit {}", c));
+        bail!(
+            "Command failed ({}): {}\nstderr: {}",
+            code,
+            display,
+            out.stderr
+        );
     }
 
-    Ok(String::from_utf8_lossy(&out.stdout).to_string())
+    Ok(out.stdout)
 }
@@ -0,0 +1,226 @@
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+
+use crate::helpers::interrupt;
+
+/// Captured result of an external command.
+#[derive(Debug, Clone, Default)]
+pub struct CommandOutput {
+    pub success: bool,
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Executes external commands for every helper, so they can be simulated or faked.
+pub trait CommandRunner {
+    /// Run a command that changes the system (dry-run runners only pretend).
+    fn run(&self, cmd: &mut Command, stdin: Option<&[u8]>) -> Result<CommandOutput>;
+
+    /// Run a read-only command; executed even in dry runs so plans use real data.
+    fn query(&self, cmd: &mut Command) -> Result<CommandOutput>;
+
+    /// Undo something set up earlier; runs even in dry runs, which attach
+    /// their read-only loop device for real.
+    fn cleanup(&self, cmd: &mut Command) -> Result<CommandOutput> {
+        self.run(cmd, None)
+    }
+
+    /// True if `run` does not actually execute anything.
+    fn is_dry_run(&self) -> bool {
+        false
+    }
+}
+
+/// `program arg1 arg2 ...`, used for display and for matching fakes.
+pub fn command_line(cmd: &Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|s| s.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// ---------------------------------------------------------
+// Real execution
+// ---------------------------------------------------------
+pub struct RealRunner;
+
+impl CommandRunner for RealRunner {
+    fn run(&self, cmd: &mut Command, stdin: Option<&[u8]>) -> Result<CommandOutput> {
+        let display = command_line(cmd);
+
+        // Nothing new starts after Ctrl-C
+        interrupt::check()?;
+        if interrupt::is_armed() {
+            cmd.process_group(0);
+        }
+
+        let out = cmd
+            .stdin(if stdin.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .and_then(|mut child| {
+                interrupt::set_child(child.id());
+                let fed = match (child.stdin.take(), stdin) {
+                    (Some(mut pipe), Some(input)) => pipe.write_all(input),
+                    _ => Ok(()),
+                };
+                let out = child.wait_with_output();
+                interrupt::clear_child();
+                fed.and(out)
+            })
+            .with_context(|| format!("Failed to run: {}", display))?;
+
+        // Killed by the second Ctrl-C
+        if !out.status.success() && interrupt::interrupted() {
+            return Err(anyhow::Error::new(interrupt::Interrupted)
+                .context(format!("`{}` was stopped", display)));
+        }
+
+        Ok(CommandOutput {
+            success: out.status.success(),
+            code: out.status.code(),
+            stdout: String::from_utf8_lossy(&out.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&out.stderr).to_string(),
+        })
+    }
+
+    fn query(&self, cmd: &mut Command) -> Result<CommandOutput> {
+        self.run(cmd, None)
+    }
+}
+
+// ---------------------------------------------------------
+// Dry run: inspect for real, never change anything
+// ---------------------------------------------------------
+pub struct DryRunRunner;
+
+impl CommandRunner for DryRunRunner {
+    fn run(&self, _cmd: &mut Command, _stdin: Option<&[u8]>) -> Result<CommandOutput> {
+        Ok(CommandOutput {
+            success: true,
+            code: Some(0),
+            stdout: "[dry-run]".into(),
+            stderr: String::new(),
+        })
+    }
+
+    fn query(&self, cmd: &mut Command) -> Result<CommandOutput> {
+        RealRunner.query(cmd)
+    }
+
+    fn cleanup(&self, cmd: &mut Command) -> Result<CommandOutput> {
+        RealRunner.run(cmd, None)
+    }
+
+    fn is_dry_run(&self) -> bool {
+        true
+    }
+}
+
+// ---------------------------------------------------------
+// Scripted fake that records every invocation (tests)
+// ---------------------------------------------------------
+#[cfg(test)]
+pub struct FakeRunner {
+    /// (command line prefix, canned output), first match wins
+    responses: Vec<(String, CommandOutput)>,
+    calls: std::sync::Mutex<Vec<String>>,
+    /// (command line, stdin) of every command fed input
+    inputs: std::sync::Mutex<Vec<(String, Vec<u8>)>>,
+}
+
+#[cfg(test)]
+impl FakeRunner {
+    pub fn new() -> Self {
+        FakeRunner {
+            responses: Vec::new(),
+            calls: std::sync::Mutex::new(Vec::new()),
+            inputs: std::sync::Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Answer commands starting with `prefix` with `stdout` and exit code 0.
+    pub fn respond(mut self, prefix: &str, stdout: &str) -> Self {
+        self.responses.push((
+            prefix.to_string(),
+            CommandOutput {
+                success: true,
+                code: Some(0),
+                stdout: stdout.to_string(),
+                stderr: String::new(),
+            },
+        ));
+        self
+    }
+
+    /// Fail commands starting with `prefix` with the given exit code.
+    pub fn fail(mut self, prefix: &str, code: i32, stderr: &str) -> Self {
+        self.responses.push((
+            prefix.to_string(),
+            CommandOutput {
+                success: false,
+                code: Some(code),
+                stdout: String::new(),
+                stderr: stderr.to_string(),
+            },
+        ));
+        self
+    }
+
+    /// Every command line seen so far, in order.
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Recorded command lines of one program.
+    pub fn calls_to(&self, program: &str) -> Vec<String> {
+        self.calls()
+            .into_iter()
+            .filter(|c| c.split(' ').next() == Some(program))
+            .collect()
+    }
+
+    /// Command lines that got stdin, with what they were fed.
+    pub fn inputs(&self) -> Vec<(String, Vec<u8>)> {
+        self.inputs.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl CommandRunner for FakeRunner {
+    fn run(&self, cmd: &mut Command, stdin: Option<&[u8]>) -> Result<CommandOutput> {
+        let line = command_line(cmd);
+        self.calls.lock().unwrap().push(line.clone());
+        if let Some(input) = stdin {
+            self.inputs
+                .lock()
+                .unwrap()
+                .push((line.clone(), input.to_vec()));
+        }
+
+        // Unscripted commands succeed silently
+        Ok(self
+            .responses
+            .iter()
+            .find(|(prefix, _)| line.starts_with(prefix.as_str()))
+            .map(|(_, out)| out.clone())
+            .unwrap_or(CommandOutput {
+                success: true,
+                code: Some(0),
+                ..Default::default()
+            }))
+    }
+
+    fn query(&self, cmd: &mut Command) -> Result<CommandOutput> {
+        self.run(cmd, None)
+    }
+}
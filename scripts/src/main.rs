@@ -1,3 +1,6 @@
+#[macro_use]
+mod output;
+
 mod cli;
 mod colors;
 mod commands;
@@ -8,21 +11,32 @@ use commands::Commands;
 
 fn main() {
     if let Err(err) = run() {
-        eprintln!("{}", err);
+        eprintln!("{}", colors::error(&err.to_string()));
 
         // Show the whole chain of causes (optional)
         for cause in err.chain().skip(1) {
             eprintln!("Caused by: {}", cause);
         }
 
+        // Same code a shell reports for a process killed by SIGINT
+        if helpers::interrupt::is_interrupt(&err) {
+            std::process::exit(130);
+        }
+
         std::process::exit(1);
     }
 }
 
 fn run() -> anyhow::Result<()> {
     let cli = cli::Cli::parse();
+    output::set_format(cli.output);
 
     match cli.command {
         Commands::DiskSetup(args) => commands::core::disk_setup::handle(args),
+        Commands::Disk(args) => commands::core::disk::handle(args),
+        Commands::Hibernate(args) => commands::core::hibernate::handle(args),
+        Commands::Mount(args) => commands::core::rescue::handle_mount(args),
+        Commands::Chroot(args) => commands::core::rescue::handle_chroot(args),
+        Commands::Teardown(args) => commands::core::rescue::handle_teardown(args),
     }
 }
@@ -0,0 +1,94 @@
+use serde::Serialize;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// How results are printed (global --output)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Coloured text for people
+    #[default]
+    Text,
+    /// One JSON object per line on stdout, messages go to stderr
+    Json,
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+pub fn set_format(format: OutputFormat) {
+    JSON.store(format == OutputFormat::Json, Ordering::SeqCst);
+}
+
+pub fn is_json() -> bool {
+    JSON.load(Ordering::SeqCst)
+}
+
+/// Human-readable text: stdout, or stderr when stdout carries JSON
+pub fn print_human(args: std::fmt::Arguments) {
+    if is_json() {
+        eprint!("{}", args);
+        let _ = std::io::stderr().flush();
+    } else {
+        print!("{}", args);
+        let _ = std::io::stdout().flush();
+    }
+}
+
+/// `println!` for messages, see `print_human`
+macro_rules! say {
+    () => {
+        $crate::output::print_human(format_args!("\n"))
+    };
+    ($($arg:tt)*) => {
+        $crate::output::print_human(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+#[derive(Serialize)]
+struct Event<'a, T: ?Sized> {
+    #[serde(rename = "type")]
+    kind: &'a str,
+    data: &'a T,
+}
+
+/// `{"type": kind, "data": ...}` as one line on stdout, only with `--output json`
+pub fn emit<T: Serialize + ?Sized>(kind: &str, data: &T) -> anyhow::Result<()> {
+    if !is_json() {
+        return Ok(());
+    }
+
+    let line = serde_json::to_string(&Event { kind, data })?;
+    println!("{}", line);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::core::disk_setup::structs::Filesystem;
+    use crate::commands::core::disk_setup::structs::PlannedPartition;
+    use crate::commands::core::disk_setup::structs::SizeSpec;
+
+    #[test]
+    fn events_carry_the_layout_field_names() {
+        let partitions = [PlannedPartition {
+            size: SizeSpec::Bytes(1_073_741_824),
+            type_code: "ef00".to_string(),
+            label: "EFI".to_string(),
+            filesystem: Filesystem::Vfat,
+            mount_point: Some("/boot".to_string()),
+            mount_options: None,
+        }];
+
+        let line = serde_json::to_string(&Event {
+            kind: "partitions",
+            data: &partitions[..],
+        })
+        .unwrap();
+
+        assert_eq!(
+            line,
+            r#"{"type":"partitions","data":[{"size":{"bytes":1073741824},"type":"ef00","label":"EFI","filesystem":"vfat","mount_point":"/boot","mount_options":null}]}"#
+        );
+    }
+}